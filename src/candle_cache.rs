@@ -0,0 +1,185 @@
+use std::{
+  fs::{self, File},
+  io::{BufWriter, Write},
+  time::UNIX_EPOCH,
+};
+
+use memmap2::Mmap;
+
+use crate::{read_records_from_csv, Candle};
+
+// layout (little endian):
+//   magic [u8; 8] | source_len u64 | source_mtime_nanos u64 | count u64
+//   followed by one column per field, each `count` values of 8 bytes:
+//   start_timestamp i64 | end_timestamp i64 | open f64 | high f64 | low f64 | close f64 | volume i64
+const MAGIC: &[u8; 8] = b"CANDLES1";
+const HEADER_LEN: usize = 32;
+const NUM_COLUMNS: usize = 7;
+
+fn cache_filename(csv_filename: &str) -> String {
+  return format!("{csv_filename}.bin");
+}
+
+fn source_fingerprint(csv_filename: &str) -> (u64, u64) {
+  let metadata = fs::metadata(csv_filename).unwrap();
+  let mtime = metadata.modified().unwrap().duration_since(UNIX_EPOCH).unwrap();
+  return (metadata.len(), mtime.as_nanos() as u64);
+}
+
+fn read_u64(bytes: &[u8], offset: usize) -> u64 {
+  return u64::from_le_bytes(bytes[offset..offset + 8].try_into().unwrap());
+}
+
+fn read_cache(cache_filename: &str, fingerprint: (u64, u64)) -> Option<Vec<Candle>> {
+  let file = File::open(cache_filename).ok()?;
+  // safety: the cache is only ever replaced atomically via rename, never written in place
+  let mmap = unsafe { Mmap::map(&file).ok()? };
+  if mmap.len() < HEADER_LEN || &mmap[0..8] != MAGIC {
+    return None;
+  }
+  let source_len = read_u64(&mmap, 8);
+  let source_mtime = read_u64(&mmap, 16);
+  if (source_len, source_mtime) != fingerprint {
+    return None;
+  }
+  // a count that overflows the expected length can only come from a corrupt header, rebuild then
+  let count = usize::try_from(read_u64(&mmap, 24)).ok()?;
+  let expected_len = count.checked_mul(NUM_COLUMNS * 8).and_then(|len| len.checked_add(HEADER_LEN))?;
+  if mmap.len() != expected_len {
+    return None;
+  }
+  let column = |index: usize| &mmap[HEADER_LEN + index * count * 8..HEADER_LEN + (index + 1) * count * 8];
+  let read_i64 = |index: usize, row: usize| i64::from_le_bytes(column(index)[row * 8..row * 8 + 8].try_into().unwrap());
+  let read_f64 = |index: usize, row: usize| f64::from_le_bytes(column(index)[row * 8..row * 8 + 8].try_into().unwrap());
+  let mut candles = Vec::with_capacity(count);
+  for row in 0..count {
+    candles.push(Candle {
+      start_timestamp: read_i64(0, row),
+      end_timestamp: read_i64(1, row),
+      open: read_f64(2, row),
+      high: read_f64(3, row),
+      low: read_f64(4, row),
+      close: read_f64(5, row),
+      volume: read_i64(6, row),
+    });
+  }
+  return Some(candles);
+}
+
+fn write_cache(cache_filename: &str, fingerprint: (u64, u64), candles: &[Candle]) {
+  let temporary_filename = format!("{cache_filename}.tmp");
+  let file = File::create(&temporary_filename).unwrap();
+  let mut writer = BufWriter::new(file);
+  writer.write_all(MAGIC).unwrap();
+  writer.write_all(&fingerprint.0.to_le_bytes()).unwrap();
+  writer.write_all(&fingerprint.1.to_le_bytes()).unwrap();
+  writer.write_all(&(candles.len() as u64).to_le_bytes()).unwrap();
+  let columns: [fn(&Candle) -> [u8; 8]; NUM_COLUMNS] = [
+    |candle| candle.start_timestamp.to_le_bytes(),
+    |candle| candle.end_timestamp.to_le_bytes(),
+    |candle| candle.open.to_le_bytes(),
+    |candle| candle.high.to_le_bytes(),
+    |candle| candle.low.to_le_bytes(),
+    |candle| candle.close.to_le_bytes(),
+    |candle| candle.volume.to_le_bytes(),
+  ];
+  for column in columns {
+    for candle in candles {
+      writer.write_all(&column(candle)).unwrap();
+    }
+  }
+  writer.flush().unwrap();
+  drop(writer);
+  fs::rename(&temporary_filename, cache_filename).unwrap();
+}
+
+// loads candles from the columnar cache next to the csv, rebuilding it whenever the csv size or mtime changed
+pub fn load_candles(csv_filename: &str) -> Vec<Candle> {
+  let cache_filename = cache_filename(csv_filename);
+  let fingerprint = source_fingerprint(csv_filename);
+  if let Some(candles) = read_cache(&cache_filename, fingerprint) {
+    return candles;
+  }
+  let candles = read_records_from_csv::<Candle>(csv_filename);
+  write_cache(&cache_filename, fingerprint, &candles);
+  return candles;
+}
+
+#[cfg(test)]
+mod tests {
+  use std::time::{Duration, SystemTime};
+
+  use super::*;
+
+  fn candle(start_timestamp: i64, close: f64) -> Candle {
+    return Candle {
+      start_timestamp,
+      end_timestamp: start_timestamp + 59,
+      open: close - 0.5,
+      high: close + 1.0,
+      low: close - 1.0,
+      close,
+      volume: 100,
+    };
+  }
+
+  fn temporary_filename(name: &str) -> String {
+    let filename = std::env::temp_dir().join(format!("candle-cache-test-{}-{name}", std::process::id()));
+    return filename.to_str().unwrap().to_string();
+  }
+
+  fn write_csv(filename: &str, candles: &[Candle]) {
+    let mut writer = csv::Writer::from_path(filename).unwrap();
+    for candle in candles {
+      writer.serialize(candle).unwrap();
+    }
+    writer.flush().unwrap();
+  }
+
+  fn describe(candles: &[Candle]) -> Vec<String> {
+    return candles.iter().map(|candle| format!("{candle:?}")).collect();
+  }
+
+  #[test]
+  fn round_trips_through_the_mmap() {
+    let filename = temporary_filename("round-trip.bin");
+    let candles = [candle(1677681000, 10.25), candle(1677681060, -3.0)];
+    write_cache(&filename, (123, 456), &candles);
+    let cached = read_cache(&filename, (123, 456));
+    assert_eq!(describe(&cached.unwrap()), describe(&candles));
+    // any other fingerprint is a miss
+    assert!(read_cache(&filename, (124, 456)).is_none());
+    assert!(read_cache(&filename, (123, 457)).is_none());
+    fs::remove_file(&filename).unwrap();
+  }
+
+  #[test]
+  fn treats_an_overflowing_count_as_a_miss() {
+    let filename = temporary_filename("overflow.bin");
+    write_cache(&filename, (123, 456), &[candle(1677681000, 10.0)]);
+    let mut bytes = fs::read(&filename).unwrap();
+    bytes[24..32].copy_from_slice(&u64::MAX.to_le_bytes());
+    fs::write(&filename, bytes).unwrap();
+    assert!(read_cache(&filename, (123, 456)).is_none());
+    fs::remove_file(&filename).unwrap();
+  }
+
+  #[test]
+  fn rebuilds_when_the_source_changes() {
+    let csv_filename = temporary_filename("source.csv");
+    write_csv(&csv_filename, &[candle(1677681000, 10.0)]);
+    assert_eq!(load_candles(&csv_filename)[0].close, 10.0);
+    assert!(fs::metadata(cache_filename(&csv_filename)).is_ok());
+    // longer source
+    write_csv(&csv_filename, &[candle(1677681000, 10.0), candle(1677681060, 11.0)]);
+    assert_eq!(load_candles(&csv_filename).len(), 2);
+    // same length, only the mtime tells them apart
+    write_csv(&csv_filename, &[candle(1677681000, 20.0), candle(1677681060, 11.0)]);
+    let file = File::options().write(true).open(&csv_filename).unwrap();
+    file.set_modified(SystemTime::now() - Duration::from_secs(3600)).unwrap();
+    drop(file);
+    assert_eq!(load_candles(&csv_filename)[0].close, 20.0);
+    fs::remove_file(cache_filename(&csv_filename)).unwrap();
+    fs::remove_file(&csv_filename).unwrap();
+  }
+}
@@ -0,0 +1,167 @@
+use std::ops::Range;
+
+use crate::{datetime_from_timestamp, get_regular_market_session_start_and_end, Candle};
+
+const MISSING: u32 = u32::MAX;
+const SECONDS_PER_DAY: i64 = 24 * 60 * 60;
+
+// candles sorted by start timestamp plus a dense slot table so a timestamp resolves to an index with plain arithmetic.
+// intraday slots are a fixed stride of candle_size_seconds, daily and coarser ones are calendar days in exchange local
+// time since days are 23 or 25 hours long across daylight saving changes
+pub struct CandleSeries {
+  candles: Vec<Candle>,
+  candle_size_seconds: i64,
+  first_timestamp: i64,
+  slots: Vec<u32>,
+  sessions: Vec<(i64, Range<usize>)>,
+}
+
+impl CandleSeries {
+  pub fn new(mut candles: Vec<Candle>, candle_size_seconds: i64) -> CandleSeries {
+    // the first of several candles with the same start wins
+    candles.sort_by_key(|candle| candle.start_timestamp);
+    let num_candles = candles.len();
+    candles.dedup_by_key(|candle| candle.start_timestamp);
+    if candles.len() < num_candles {
+      eprintln!("dropped {} candles with a duplicate start timestamp", num_candles - candles.len());
+    }
+    assert!(candles.len() < MISSING as usize);
+    let first_timestamp = candles.first().map(|candle| candle.start_timestamp).unwrap_or(0);
+    let last_timestamp = candles.last().map(|candle| candle.start_timestamp).unwrap_or(0);
+    let mut series = CandleSeries {
+      candles: vec![],
+      candle_size_seconds,
+      first_timestamp,
+      slots: vec![],
+      sessions: vec![],
+    };
+    let num_slots = if candles.is_empty() { 0 } else { series.slot(last_timestamp).unwrap() + 1 };
+    let mut slots = vec![MISSING; num_slots];
+    let mut sessions: Vec<(i64, Range<usize>)> = vec![];
+    for (index, candle) in candles.iter().enumerate() {
+      let offset = candle.start_timestamp - first_timestamp;
+      let is_aligned = series.is_daily() || offset % candle_size_seconds == 0;
+      assert!(is_aligned, "candle {} is not aligned to {candle_size_seconds}s", candle.start_timestamp);
+      let slot = series.slot(candle.start_timestamp).unwrap();
+      assert!(
+        slots[slot] == MISSING,
+        "candles {} and {} fall on the same day",
+        candles[slots[slot] as usize].start_timestamp,
+        candle.start_timestamp
+      );
+      slots[slot] = index as u32;
+      // group by regular session start, same grouping key signals use
+      let (regular_session_start, _) = get_regular_market_session_start_and_end(candle.start_timestamp);
+      let grouping_key = regular_session_start.timestamp();
+      match sessions.last_mut() {
+        Some((last_grouping_key, range)) if *last_grouping_key == grouping_key => range.end = index + 1,
+        _ => sessions.push((grouping_key, index..index + 1)),
+      }
+    }
+    series.candles = candles;
+    series.slots = slots;
+    series.sessions = sessions;
+    return series;
+  }
+
+  fn is_daily(&self) -> bool {
+    return self.candle_size_seconds >= SECONDS_PER_DAY;
+  }
+
+  // none before the first candle or between intraday slots
+  fn slot(&self, timestamp: i64) -> Option<usize> {
+    if self.is_daily() {
+      let first_date = datetime_from_timestamp(self.first_timestamp).date_naive();
+      let days = (datetime_from_timestamp(timestamp).date_naive() - first_date).num_days();
+      return usize::try_from(days).ok();
+    }
+    let offset = timestamp - self.first_timestamp;
+    if offset < 0 || offset % self.candle_size_seconds != 0 {
+      return None;
+    }
+    return Some((offset / self.candle_size_seconds) as usize);
+  }
+
+  pub fn candles(&self) -> &[Candle] {
+    return &self.candles;
+  }
+
+  pub fn candle_size_seconds(&self) -> i64 {
+    return self.candle_size_seconds;
+  }
+
+  // daily and coarser candles are found by any timestamp on their start date
+  pub fn index_of(&self, timestamp: i64) -> Option<usize> {
+    let slot = *self.slots.get(self.slot(timestamp)?)?;
+    if slot == MISSING {
+      return None;
+    }
+    return Some(slot as usize);
+  }
+
+  pub fn get(&self, timestamp: i64) -> Option<&Candle> {
+    return self.index_of(timestamp).map(|index| &self.candles[index]);
+  }
+
+  // candles starting in [start_timestamp, end_timestamp)
+  pub fn range(&self, start_timestamp: i64, end_timestamp: i64) -> &[Candle] {
+    let start = self.candles.partition_point(|candle| candle.start_timestamp < start_timestamp);
+    let end = self.candles.partition_point(|candle| candle.start_timestamp < end_timestamp);
+    return &self.candles[start..end.max(start)];
+  }
+
+  // (grouping_key, candles) per trading day, including that day's pre/post market candles
+  pub fn sessions(&self) -> impl Iterator<Item = (i64, &[Candle])> {
+    return self.sessions.iter().map(|(grouping_key, range)| (*grouping_key, &self.candles[range.clone()]));
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn candle(start_timestamp: i64, close: f64) -> Candle {
+    return Candle {
+      start_timestamp,
+      end_timestamp: start_timestamp + 59,
+      open: close,
+      high: close,
+      low: close,
+      close,
+      volume: 100,
+    };
+  }
+
+  #[test]
+  fn indexes_daily_candles_by_date_across_daylight_saving() {
+    // midnight eastern on friday 2023-03-10 (est) and monday 2023-03-13 (edt), 71 hours apart
+    let friday = 1678424400;
+    let monday = 1678680000;
+    let series = CandleSeries::new(vec![candle(friday, 1.0), candle(monday, 2.0)], SECONDS_PER_DAY);
+    assert_eq!(series.get(friday).unwrap().close, 1.0);
+    assert_eq!(series.get(monday).unwrap().close, 2.0);
+    // any time on the date, e.g. the walker's pointer one day stride after friday's midnight, and the open
+    assert!(series.get(friday + SECONDS_PER_DAY).is_none());
+    assert_eq!(series.get(monday + 9 * 60 * 60 + 30 * 60).unwrap().close, 2.0);
+    assert!(series.get(friday - 1).is_none());
+  }
+
+  #[test]
+  fn indexes_intraday_candles_by_stride() {
+    let open = 1677767400; // 2023-03-02 09:30 eastern
+    let series = CandleSeries::new(vec![candle(open, 1.0), candle(open + 120, 3.0)], 60);
+    assert_eq!(series.get(open).unwrap().close, 1.0);
+    assert!(series.get(open + 60).is_none());
+    assert!(series.get(open + 30).is_none());
+    assert_eq!(series.get(open + 120).unwrap().close, 3.0);
+    assert_eq!(series.sessions().count(), 1);
+  }
+
+  #[test]
+  fn keeps_the_first_of_duplicate_candles() {
+    let open = 1677767400;
+    let series = CandleSeries::new(vec![candle(open, 1.0), candle(open, 2.0), candle(open + 60, 3.0)], 60);
+    assert_eq!(series.candles().len(), 2);
+    assert_eq!(series.get(open).unwrap().close, 1.0);
+  }
+}
@@ -0,0 +1,187 @@
+use std::{
+  collections::{BTreeMap, HashMap},
+  fs::{self, File, OpenOptions},
+  hash::Hasher,
+  io::Write,
+  sync::Mutex,
+};
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::fnv::Fnv1a;
+use crate::performance::PerformanceMetrics;
+use crate::Candle;
+
+// results written under another version are not reused, bump it whenever a change alters what a combination evaluates
+// to (backtest semantics, metrics) or how entries are written
+const CHECKPOINT_FORMAT_VERSION: u32 = 2;
+
+#[derive(Serialize, Deserialize)]
+struct Entry {
+  key: String,
+  parameters: BTreeMap<String, f64>,
+  performance: PerformanceMetrics,
+}
+
+// append-only jsonl of evaluated combinations keyed by a hash of their parameters, the data they ran on and the format
+// version, so a restarted run skips everything that was already evaluated under the same conditions
+pub struct Checkpoint {
+  fingerprint: u64,
+  performances: HashMap<u64, PerformanceMetrics>,
+  file: Mutex<File>,
+}
+
+// identifies the inputs of a run apart from the parameters, setup describes whatever else shapes the results (strategy,
+// score, trading hours, trade controls, pyramiding, short selling)
+pub fn data_fingerprint(seed: u64, setup: &str, candle_sets: &[&[Candle]]) -> u64 {
+  let mut hasher = Fnv1a::default();
+  hasher.write_u32(CHECKPOINT_FORMAT_VERSION);
+  hasher.write_u64(seed);
+  hasher.write(setup.as_bytes());
+  for candles in candle_sets {
+    hasher.write_usize(candles.len());
+    for candle in candles.iter() {
+      hasher.write_i64(candle.start_timestamp);
+      hasher.write_i64(candle.end_timestamp);
+      hasher.write_u64(candle.open.to_bits());
+      hasher.write_u64(candle.high.to_bits());
+      hasher.write_u64(candle.low.to_bits());
+      hasher.write_u64(candle.close.to_bits());
+      hasher.write_i64(candle.volume);
+    }
+  }
+  return hasher.finish();
+}
+
+impl Checkpoint {
+  pub fn open(filename: &str, fingerprint: u64) -> Checkpoint {
+    let mut performances = HashMap::new();
+    let mut is_torn = false;
+    if let Ok(contents) = fs::read_to_string(filename) {
+      is_torn = contents.is_empty() == false && contents.ends_with('\n') == false;
+      for line in contents.lines() {
+        // a crash can leave the last line torn
+        let entry: Entry = match serde_json::from_str(line) {
+          Ok(entry) => entry,
+          Err(_) => {
+            eprintln!("checkpoint: skipping unreadable line in {filename}");
+            continue;
+          }
+        };
+        if let Ok(key) = u64::from_str_radix(&entry.key, 16) {
+          performances.insert(key, entry.performance);
+        }
+      }
+    }
+    let mut file = OpenOptions::new().create(true).append(true).open(filename).unwrap();
+    if is_torn {
+      // so the next entry starts on its own line
+      file.write_all(b"\n").unwrap();
+    }
+    return Checkpoint {
+      fingerprint,
+      performances,
+      file: Mutex::new(file),
+    };
+  }
+
+  pub fn key(&self, parameter_values: &[(String, f64)]) -> u64 {
+    let mut hasher = Fnv1a::default();
+    hasher.write_u64(self.fingerprint);
+    for (name, value) in parameter_values {
+      hasher.write(name.as_bytes());
+      hasher.write_u64(value.to_bits());
+    }
+    return hasher.finish();
+  }
+
+  pub fn get(&self, key: u64) -> Option<&PerformanceMetrics> {
+    return self.performances.get(&key);
+  }
+
+  pub fn len(&self) -> usize {
+    return self.performances.len();
+  }
+
+  // one write per call so concurrent batches never interleave within a line
+  pub fn append(&self, parameter_values: &[Vec<(String, f64)>], performances: &[PerformanceMetrics]) {
+    if performances.is_empty() {
+      return;
+    }
+    let mut lines = String::new();
+    for (parameter_values, performance) in parameter_values.iter().zip(performances) {
+      let entry = Entry {
+        key: format!("{:016x}", self.key(parameter_values)),
+        parameters: parameter_values.iter().cloned().collect(),
+        performance: performance.clone(),
+      };
+      lines.push_str(&serde_json::to_string(&entry).unwrap());
+      lines.push('\n');
+    }
+    let mut file = self.file.lock().unwrap();
+    file.write_all(lines.as_bytes()).unwrap();
+    file.flush().unwrap();
+  }
+}
+
+// json has no nan or infinities, serde_json writes them as null and then fails to read them back. non-finite values
+// are written as the strings "NaN", "inf" and "-inf" instead
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum JsonFloat {
+  Number(f64),
+  Text(String),
+}
+
+pub fn serialize_float<S: Serializer>(value: &f64, serializer: S) -> Result<S::Ok, S::Error> {
+  if value.is_finite() {
+    return serializer.serialize_f64(*value);
+  }
+  return serializer.serialize_str(&value.to_string());
+}
+
+pub fn deserialize_float<'de, D: Deserializer<'de>>(deserializer: D) -> Result<f64, D::Error> {
+  match JsonFloat::deserialize(deserializer)? {
+    JsonFloat::Number(value) => return Ok(value),
+    JsonFloat::Text(text) => return text.parse().map_err(serde::de::Error::custom),
+  }
+}
+
+#[derive(Serialize, Deserialize)]
+struct KeyedFloat(i64, #[serde(serialize_with = "serialize_float", deserialize_with = "deserialize_float")] f64);
+
+pub fn serialize_keyed_floats<S: Serializer>(values: &[(i64, f64)], serializer: S) -> Result<S::Ok, S::Error> {
+  return serializer.collect_seq(values.iter().map(|(key, value)| KeyedFloat(*key, *value)));
+}
+
+pub fn deserialize_keyed_floats<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<(i64, f64)>, D::Error> {
+  let values = Vec::<KeyedFloat>::deserialize(deserializer)?;
+  return Ok(values.into_iter().map(|KeyedFloat(key, value)| (key, value)).collect());
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn non_finite_metrics_survive_a_reload() {
+    let filename = std::env::temp_dir().join(format!("checkpoint-test-{}.jsonl", std::process::id()));
+    let filename = filename.to_str().unwrap();
+    let _ = fs::remove_file(filename);
+    let mut performance = PerformanceMetrics::from_returns(&[0.01, f64::INFINITY]);
+    performance.session_returns.push((2, f64::NAN));
+    performance.max_drawdown = f64::NEG_INFINITY;
+    let parameter_values = vec![("fast_periods".to_string(), 10.0)];
+    let checkpoint = Checkpoint::open(filename, 1);
+    checkpoint.append(std::slice::from_ref(&parameter_values), std::slice::from_ref(&performance));
+    let key = checkpoint.key(&parameter_values);
+    drop(checkpoint);
+    let reloaded = Checkpoint::open(filename, 1);
+    fs::remove_file(filename).unwrap();
+    let reloaded_performance = reloaded.get(key).expect("entry was skipped on reload");
+    assert_eq!(reloaded_performance.profit_loss_percentage, f64::INFINITY);
+    assert_eq!(reloaded_performance.max_drawdown, f64::NEG_INFINITY);
+    assert_eq!(reloaded_performance.session_returns[0], (0, 0.01));
+    assert!(reloaded_performance.session_returns[2].1.is_nan());
+  }
+}
@@ -0,0 +1,141 @@
+use chrono::{NaiveDate, TimeZone};
+use chrono_tz::US;
+use serde::Deserialize;
+
+use crate::{determine_session_type, Candle, MarketSessionType};
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PriceAdjustment {
+  Adjusted,
+  Unadjusted,
+}
+
+// one row per ex-date, e.g.
+//   date,split_ratio,cash_dividend
+//   2022-08-25,3,
+//   2022-09-16,,0.57
+#[derive(Debug, Clone, Deserialize)]
+pub struct CorporateAction {
+  pub date: String,               // ex-date, YYYY-MM-DD in exchange local time
+  pub split_ratio: Option<f64>,   // new shares per old share, 4 for a 4-for-1 split, 0.1 for a 1-for-10 reverse split
+  pub cash_dividend: Option<f64>, // per share, as paid on the ex-date
+}
+
+impl CorporateAction {
+  // everything that started before midnight of the ex-date (pre-market included) is pre-action
+  fn ex_timestamp(&self) -> i64 {
+    let date = NaiveDate::parse_from_str(&self.date, "%Y-%m-%d").unwrap();
+    let midnight = date.and_hms_opt(0, 0, 0).unwrap();
+    return US::Eastern.from_local_datetime(&midnight).unwrap().timestamp();
+  }
+}
+
+// back-adjusts prices and volume so the most recent candles keep their raw values and older ones are scaled to match
+pub fn adjust_candles(candles: &[Candle], corporate_actions: &[CorporateAction]) -> Result<Vec<Candle>, String> {
+  let mut actions: Vec<(i64, &CorporateAction)> = corporate_actions.iter().map(|action| (action.ex_timestamp(), action)).collect();
+  actions.sort_by_key(|(ex_timestamp, _)| *ex_timestamp);
+  // per action price/volume factors, the dividend factor uses the raw regular session close right before the ex-date
+  // rather than a post-market print
+  let mut factors = vec![];
+  for (ex_timestamp, action) in &actions {
+    let split_ratio = action.split_ratio.unwrap_or(1.0);
+    let cash_dividend = action.cash_dividend.unwrap_or(0.0);
+    if split_ratio.is_finite() == false || split_ratio <= 0.0 {
+      return Err(format!("{}: split_ratio must be positive, got {split_ratio}", action.date));
+    }
+    if cash_dividend.is_finite() == false || cash_dividend < 0.0 {
+      return Err(format!("{}: cash_dividend must not be negative, got {cash_dividend}", action.date));
+    }
+    // daily and coarser candles start outside the regular session, any of them before the ex-date is a close
+    let candles_before = || candles.iter().rev().filter(|candle| candle.start_timestamp < *ex_timestamp);
+    let previous_close = candles_before()
+      .find(|candle| determine_session_type(candle.start_timestamp) == MarketSessionType::Regular)
+      .or_else(|| candles_before().next())
+      .map(|candle| candle.close);
+    let dividend_factor = match previous_close {
+      Some(previous_close) if cash_dividend > 0.0 => 1.0 - cash_dividend / previous_close,
+      _ => 1.0,
+    };
+    if dividend_factor <= 0.0 {
+      return Err(format!("{}: cash_dividend {cash_dividend} is not below the previous close", action.date));
+    }
+    factors.push((*ex_timestamp, dividend_factor / split_ratio, split_ratio));
+  }
+  // walk backwards through time accumulating every action that happened after the candle
+  let mut adjusted = candles.to_vec();
+  let mut price_factor = 1.0;
+  let mut volume_factor = 1.0;
+  let mut next_action = factors.len();
+  for candle in adjusted.iter_mut().rev() {
+    while next_action > 0 && candle.start_timestamp < factors[next_action - 1].0 {
+      price_factor *= factors[next_action - 1].1;
+      volume_factor *= factors[next_action - 1].2;
+      next_action -= 1;
+    }
+    candle.open *= price_factor;
+    candle.high *= price_factor;
+    candle.low *= price_factor;
+    candle.close *= price_factor;
+    candle.volume = (candle.volume as f64 * volume_factor).round() as i64;
+  }
+  return Ok(adjusted);
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn candle(start_timestamp: i64, close: f64) -> Candle {
+    return Candle {
+      start_timestamp,
+      end_timestamp: start_timestamp + 59,
+      open: close,
+      high: close,
+      low: close,
+      close,
+      volume: 100,
+    };
+  }
+
+  fn action(date: &str, split_ratio: Option<f64>, cash_dividend: Option<f64>) -> CorporateAction {
+    return CorporateAction {
+      date: date.to_string(),
+      split_ratio,
+      cash_dividend,
+    };
+  }
+
+  // 2023-03-01 15:59 and 16:30 eastern, then 2023-03-02 09:30
+  const LAST_REGULAR: i64 = 1677704340;
+  const POST_MARKET: i64 = 1677706200;
+  const NEXT_OPEN: i64 = 1677767400;
+
+  #[test]
+  fn splits_scale_prices_down_and_volume_up_before_the_ex_date() {
+    let candles = [candle(LAST_REGULAR, 300.0), candle(NEXT_OPEN, 100.0)];
+    let adjusted = adjust_candles(&candles, &[action("2023-03-02", Some(3.0), None)]).unwrap();
+    assert_eq!(adjusted[0].close, 100.0);
+    assert_eq!(adjusted[0].volume, 300);
+    assert_eq!(adjusted[1].close, 100.0);
+    assert_eq!(adjusted[1].volume, 100);
+  }
+
+  #[test]
+  fn dividends_use_the_last_regular_session_close() {
+    let candles = [candle(LAST_REGULAR, 100.0), candle(POST_MARKET, 50.0), candle(NEXT_OPEN, 99.0)];
+    let adjusted = adjust_candles(&candles, &[action("2023-03-02", None, Some(1.0))]).unwrap();
+    assert!((adjusted[0].close - 99.0).abs() < 1e-9);
+    assert!((adjusted[1].close - 49.5).abs() < 1e-9);
+    assert_eq!(adjusted[2].close, 99.0);
+  }
+
+  #[test]
+  fn rejects_invalid_actions() {
+    let candles = [candle(LAST_REGULAR, 100.0), candle(NEXT_OPEN, 99.0)];
+    assert!(adjust_candles(&candles, &[action("2023-03-02", Some(0.0), None)]).is_err());
+    assert!(adjust_candles(&candles, &[action("2023-03-02", Some(-2.0), None)]).is_err());
+    assert!(adjust_candles(&candles, &[action("2023-03-02", None, Some(-1.0))]).is_err());
+    assert!(adjust_candles(&candles, &[action("2023-03-02", None, Some(100.0))]).is_err());
+  }
+}
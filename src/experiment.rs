@@ -0,0 +1,481 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use rust_decimal::prelude::*;
+use rust_decimal::Decimal;
+use serde::Deserialize;
+
+use crate::corporate_actions::PriceAdjustment;
+use crate::heatmap::HeatmapParameters;
+use crate::monte_carlo::MonteCarloParameters;
+use crate::overfitting::OverfittingParameters;
+use crate::performance::Fitness;
+use crate::rules::RuleSet;
+use crate::score::ScoreParameters;
+use crate::search::Search;
+use crate::short_selling::DirectionPolicy;
+use crate::strategy::{Composite, CompositeParameters, StrategyDefinition};
+use crate::trade_controls::{AddCondition, Pyramiding, TradeControls};
+use crate::trading_hours::TradingHoursParameters;
+use crate::walk_forward::WalkForwardParameters;
+use crate::{build_decimal_range, BacktestParameters, SignalParameters};
+
+const DEFAULT_EXPERIMENT: &str = include_str!("../experiment.toml");
+
+pub const BACKTEST_PARAMETER_NAMES: [&str; 4] = [
+  "slippage_percentage",
+  "short_borrow_fee_percentage",
+  "profit_limit_percentage",
+  "stop_loss_percentage",
+];
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ParameterSpace {
+  Range { min: Decimal, max: Decimal, step: Decimal },
+  List { values: Vec<Decimal> },
+  LogRange { min: f64, max: f64, count: usize },
+}
+
+impl ParameterSpace {
+  // a range has to reach its max in steps forward
+  fn validate(&self) -> Result<(), String> {
+    if let ParameterSpace::Range { min, max, step } = self {
+      if *step <= Decimal::ZERO {
+        return Err(format!("range step must be above 0, got {step}"));
+      }
+      if min > max {
+        return Err(format!("range min {min} is above its max {max}"));
+      }
+    }
+    return Ok(());
+  }
+
+  pub fn values(&self) -> Vec<f64> {
+    match self {
+      ParameterSpace::Range { min, max, step } => {
+        return build_decimal_range(*min, *max, *step).iter().map(|value| value.to_f64().unwrap()).collect();
+      }
+      ParameterSpace::List { values } => return values.iter().map(|value| value.to_f64().unwrap()).collect(),
+      ParameterSpace::LogRange { min, max, count } => {
+        assert!(*min > 0.0 && *max > 0.0, "log_range bounds must be positive");
+        if *count <= 1 {
+          return vec![*min];
+        }
+        let ratio = (max / min).powf(1.0 / (*count - 1) as f64);
+        return (0..*count).map(|index| min * ratio.powi(index as i32)).collect();
+      }
+    }
+  }
+}
+
+#[derive(Debug, Clone)]
+enum Operand {
+  Parameter(String),
+  Value(f64),
+}
+
+#[derive(Debug, Clone)]
+pub struct Constraint {
+  left: Operand,
+  operator: String,
+  right: Operand,
+}
+
+impl Constraint {
+  // "<name|number> <op> <name|number>", op is one of < <= > >= == !=
+  fn parse(text: &str) -> Constraint {
+    let tokens: Vec<&str> = text.split_whitespace().collect();
+    if tokens.len() != 3 || ["<", "<=", ">", ">=", "==", "!="].contains(&tokens[1]) == false {
+      panic!("invalid constraint {text:?}, expected e.g. \"fast_periods < slow_periods\"");
+    }
+    let parse_operand = |token: &str| match token.parse::<f64>() {
+      Ok(value) => Operand::Value(value),
+      Err(_) => Operand::Parameter(token.to_string()),
+    };
+    return Constraint {
+      left: parse_operand(tokens[0]),
+      operator: tokens[1].to_string(),
+      right: parse_operand(tokens[2]),
+    };
+  }
+
+  fn parameter_names(&self) -> Vec<&str> {
+    return [&self.left, &self.right]
+      .iter()
+      .filter_map(|operand| match operand {
+        Operand::Parameter(name) => Some(name.as_str()),
+        Operand::Value(_) => None,
+      })
+      .collect();
+  }
+
+  pub fn is_satisfied(&self, values: &BTreeMap<String, f64>) -> bool {
+    let resolve = |operand: &Operand| match operand {
+      Operand::Parameter(name) => values[name],
+      Operand::Value(value) => *value,
+    };
+    let left = resolve(&self.left);
+    let right = resolve(&self.right);
+    match self.operator.as_str() {
+      "<" => return left < right,
+      "<=" => return left <= right,
+      ">" => return left > right,
+      ">=" => return left >= right,
+      "==" => return left == right,
+      _ => return left != right,
+    }
+  }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Experiment {
+  pub resolution: i64,
+  pub seed: u64,
+  pub signal_price_adjustment: PriceAdjustment,
+  pub fill_price_adjustment: PriceAdjustment,
+  pub corporate_actions_filename: String,
+  #[serde(default)]
+  pub constraints: Vec<String>,
+  pub parameters: BTreeMap<String, ParameterSpace>,
+  #[serde(default)]
+  pub search: Search,
+  #[serde(default)]
+  pub fitness: Fitness,
+  #[serde(default)]
+  pub walk_forward: Option<WalkForwardParameters>,
+  #[serde(default)]
+  pub overfitting: Option<OverfittingParameters>,
+  #[serde(default)]
+  pub monte_carlo: Option<MonteCarloParameters>,
+  #[serde(default)]
+  pub heatmaps: Vec<HeatmapParameters>,
+  // append-only results store, a rerun with the same data and code skips the combinations already in it
+  #[serde(default)]
+  pub checkpoint_filename: Option<String>,
+  // signal rules replacing the default ema cross, see the rules module for the language
+  #[serde(default)]
+  pub rules: Option<String>,
+  // several ema cross / rules members voting on the direction, with filters that can veto to flat
+  #[serde(default)]
+  pub composite: Option<CompositeParameters>,
+  // entry thresholds and position sizes from the strategy's continuous score
+  #[serde(default)]
+  pub score: Option<ScoreParameters>,
+  // entry windows, forced exit and blackout periods
+  #[serde(default)]
+  pub trading_hours: Option<TradingHoursParameters>,
+  // cooldown, minimum hold, daily trade cap and loss limit in trade construction
+  #[serde(default)]
+  pub trade_controls: TradeControls,
+  // adding units to a position while its signal persists
+  #[serde(default)]
+  pub pyramiding: Option<Pyramiding>,
+  // long_only, short_only or both
+  #[serde(default)]
+  pub direction_policy: DirectionPolicy,
+  // what the candles are of, picks its rows of the hard to borrow list
+  #[serde(default)]
+  pub symbol: Option<String>,
+  // csv of symbols (and dates) short entries are blocked for
+  #[serde(default)]
+  pub hard_to_borrow_filename: Option<String>,
+  #[serde(skip)]
+  pub strategy: StrategyDefinition,
+  // raw file contents, echoed into the results
+  #[serde(skip)]
+  pub source: String,
+}
+
+pub fn backtest_parameters_from_values(values: &BTreeMap<String, f64>) -> BacktestParameters {
+  return BacktestParameters {
+    slippage_percentage: values["slippage_percentage"],
+    short_borrow_fee_percentage: values["short_borrow_fee_percentage"],
+    profit_limit_percentage: values["profit_limit_percentage"],
+    stop_loss_percentage: values["stop_loss_percentage"],
+  };
+}
+
+pub fn parameter_values(signal_parameters: &SignalParameters, backtest_parameters: &BacktestParameters) -> Vec<(String, f64)> {
+  let mut values = vec![("warmup_periods".to_string(), signal_parameters.warmup_periods as f64)];
+  values.extend(signal_parameters.values.iter().map(|(name, value)| (name.clone(), *value)));
+  values.extend([
+    ("slippage_percentage".to_string(), backtest_parameters.slippage_percentage),
+    ("short_borrow_fee_percentage".to_string(), backtest_parameters.short_borrow_fee_percentage),
+    ("profit_limit_percentage".to_string(), backtest_parameters.profit_limit_percentage),
+    ("stop_loss_percentage".to_string(), backtest_parameters.stop_loss_percentage),
+  ]);
+  return values;
+}
+
+pub fn load_experiment(filename: Option<&str>) -> Experiment {
+  let source = match filename {
+    Some(filename) => std::fs::read_to_string(filename).unwrap(),
+    None => DEFAULT_EXPERIMENT.to_string(),
+  };
+  return parse_experiment(source);
+}
+
+// panics on anything that would otherwise only fail after the optimization has run
+fn parse_experiment(source: String) -> Experiment {
+  let mut experiment: Experiment = toml::from_str(&source).unwrap();
+  experiment.source = source;
+  // no borrow cost unless given
+  experiment
+    .parameters
+    .entry("short_borrow_fee_percentage".to_string())
+    .or_insert(ParameterSpace::List { values: vec![Decimal::ZERO] });
+  if let Some(values) = experiment.monte_carlo.as_mut().and_then(|monte_carlo| monte_carlo.parameters.as_mut()) {
+    values.entry("short_borrow_fee_percentage".to_string()).or_insert(0.0);
+  }
+  for (name, space) in &experiment.parameters {
+    space.validate().unwrap_or_else(|error| panic!("invalid parameter {name}: {error}"));
+  }
+  if let Some(overfitting) = &experiment.overfitting {
+    overfitting.validate().unwrap_or_else(|error| panic!("invalid overfitting: {error}"));
+  }
+  if let Search::Genetic(genetic) = &experiment.search {
+    genetic.validate().unwrap_or_else(|error| panic!("invalid genetic search: {error}"));
+  }
+  // tpe and genetic choose which combinations to evaluate by their fitness over every session, test windows included
+  if let Some(walk_forward) = &experiment.walk_forward {
+    walk_forward.validate().unwrap_or_else(|error| panic!("invalid walk_forward: {error}"));
+    let is_adaptive = matches!(experiment.search, Search::Tpe { .. } | Search::Genetic(_));
+    assert!(is_adaptive == false, "walk_forward needs a grid, random or latin_hypercube search");
+  }
+  if experiment.hard_to_borrow_filename.is_some() {
+    assert!(experiment.symbol.is_some(), "hard_to_borrow_filename needs the experiment's symbol");
+  }
+  if let Some(text) = &experiment.rules {
+    let rule_set = RuleSet::parse(text).unwrap_or_else(|error| panic!("invalid rules: {error}"));
+    experiment.strategy = StrategyDefinition::Rules(rule_set);
+  }
+  if let Some(parameters) = &experiment.composite {
+    assert!(experiment.rules.is_none(), "set either rules or composite, not both");
+    let composite = Composite::parse(parameters).unwrap_or_else(|error| panic!("invalid composite: {error}"));
+    experiment.strategy = StrategyDefinition::Composite(composite);
+  }
+  if let Some(score) = &experiment.score {
+    score.validate().unwrap_or_else(|error| panic!("invalid score: {error}"));
+    assert!(
+      experiment.strategy.has_score(),
+      "score needs a strategy with a score, add a score line to the rules"
+    );
+  }
+  if let Some(pyramiding) = &experiment.pyramiding {
+    let needs_score = pyramiding.add_when == AddCondition::StrongerScore;
+    assert!(
+      needs_score == false || experiment.strategy.has_score(),
+      "pyramiding add_when = \"stronger_score\" needs a strategy with a score, add a score line to the rules"
+    );
+  }
+  for name in experiment.signal_value_names() {
+    let is_reserved = name == "warmup_periods" || BACKTEST_PARAMETER_NAMES.contains(&name.as_str());
+    assert!(is_reserved == false, "signal parameter ${name} clashes with a built-in parameter");
+  }
+  let signal_parameter_names = experiment.signal_parameter_names();
+  for name in experiment.parameters.keys() {
+    let is_known = signal_parameter_names.contains(name) || BACKTEST_PARAMETER_NAMES.contains(&name.as_str());
+    assert!(is_known, "unknown parameter {name}");
+  }
+  for name in signal_parameter_names.iter().map(|name| name.as_str()).chain(BACKTEST_PARAMETER_NAMES) {
+    assert!(experiment.parameters.contains_key(name), "missing parameter {name}");
+  }
+  // the same names as the search space, the borrow fee was defaulted above
+  if let Some(values) = experiment.monte_carlo.as_ref().and_then(|monte_carlo| monte_carlo.parameters.as_ref()) {
+    for name in values.keys() {
+      assert!(experiment.parameters.contains_key(name), "unknown monte_carlo parameter {name}");
+    }
+    for name in signal_parameter_names.iter().map(|name| name.as_str()).chain(BACKTEST_PARAMETER_NAMES) {
+      assert!(values.contains_key(name), "missing monte_carlo parameter {name}");
+    }
+  }
+  for text in &experiment.constraints {
+    let constraint = Constraint::parse(text);
+    let names = constraint.parameter_names();
+    let is_signal_constraint = names.iter().all(|name| signal_parameter_names.iter().any(|signal_name| signal_name == name));
+    let is_backtest_constraint = names.iter().all(|name| BACKTEST_PARAMETER_NAMES.contains(name));
+    assert!(
+      is_signal_constraint || is_backtest_constraint,
+      "constraint {text:?} must only reference signal or backtest parameters"
+    );
+  }
+  // every pairing of threshold candidates a constraint does not rule out
+  if let Some(score) = &experiment.score {
+    let names = score.parameter_names();
+    let names: Vec<&str> = names.iter().map(|name| name.as_str()).collect();
+    for values in experiment.combinations(&names) {
+      score.validate_values(&values).unwrap_or_else(|error| panic!("invalid score: {error}"));
+    }
+  }
+  if let StrategyDefinition::Composite(composite) = &experiment.strategy {
+    composite
+      .validate_weights(|name| experiment.parameters[name].values())
+      .unwrap_or_else(|error| panic!("invalid composite: {error}"));
+  }
+  for heatmap in &experiment.heatmaps {
+    for name in [&heatmap.x, &heatmap.y] {
+      assert!(experiment.parameters.contains_key(name), "unknown heatmap axis {name}");
+    }
+  }
+  return experiment;
+}
+
+impl Experiment {
+  // warmup first, then whatever the strategy and the score take
+  pub fn signal_parameter_names(&self) -> Vec<String> {
+    let mut names = vec!["warmup_periods".to_string()];
+    names.extend(self.signal_value_names());
+    return names;
+  }
+
+  // sorted, the keys of SignalParameters::values
+  pub fn signal_value_names(&self) -> Vec<String> {
+    let mut names: BTreeSet<String> = self.strategy.parameter_names().into_iter().collect();
+    if let Some(score) = &self.score {
+      names.extend(score.parameter_names());
+    }
+    return names.into_iter().collect();
+  }
+
+  pub fn signal_parameters_from_values(&self, values: &BTreeMap<String, f64>) -> SignalParameters {
+    return SignalParameters {
+      warmup_periods: values["warmup_periods"] as usize,
+      values: self.signal_value_names().into_iter().map(|name| (name.clone(), values[&name])).collect(),
+    };
+  }
+
+  pub fn constraints(&self) -> Vec<Constraint> {
+    return self.constraints.iter().map(|text| Constraint::parse(text)).collect();
+  }
+
+  // cartesian product of the named parameters, dropping combinations that break a constraint on those parameters
+  fn combinations(&self, names: &[&str]) -> Vec<BTreeMap<String, f64>> {
+    let mut combinations = vec![BTreeMap::new()];
+    for name in names {
+      let values = self.parameters[*name].values();
+      let mut next_combinations = vec![];
+      for combination in &combinations {
+        for value in &values {
+          let mut next_combination = combination.clone();
+          next_combination.insert(name.to_string(), *value);
+          next_combinations.push(next_combination);
+        }
+      }
+      combinations = next_combinations;
+    }
+    let constraints: Vec<Constraint> = self
+      .constraints()
+      .into_iter()
+      .filter(|constraint| constraint.parameter_names().iter().all(|name| names.contains(name)))
+      .collect();
+    combinations.retain(|combination| constraints.iter().all(|constraint| constraint.is_satisfied(combination)));
+    return combinations;
+  }
+
+  pub fn signal_parameter_combinations(&self) -> Vec<SignalParameters> {
+    let names = self.signal_parameter_names();
+    let names: Vec<&str> = names.iter().map(|name| name.as_str()).collect();
+    return self
+      .combinations(&names)
+      .iter()
+      .map(|values| self.signal_parameters_from_values(values))
+      .collect();
+  }
+
+  pub fn backtest_parameter_combinations(&self) -> Vec<BacktestParameters> {
+    return self
+      .combinations(&BACKTEST_PARAMETER_NAMES)
+      .iter()
+      .map(backtest_parameters_from_values)
+      .collect();
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  // the default experiment with extra top level settings
+  fn experiment_with(settings: &str) -> Experiment {
+    return parse_experiment(format!("{settings}\n{DEFAULT_EXPERIMENT}"));
+  }
+
+  #[test]
+  fn loads_the_default_experiment() {
+    let experiment = load_experiment(None);
+    assert_eq!(experiment.signal_parameter_names(), ["warmup_periods", "fast_periods", "slow_periods"]);
+  }
+
+  #[test]
+  #[should_panic(expected = "invalid parameter fast_periods: range step must be above 0, got 0")]
+  fn rejects_ranges_that_never_end() {
+    let source = DEFAULT_EXPERIMENT.replace(
+      "fast_periods = { type = \"range\", min = 10, max = 50, step = 5 }",
+      "fast_periods = { type = \"range\", min = 10, max = 50, step = 0 }",
+    );
+    parse_experiment(source);
+  }
+
+  #[test]
+  #[should_panic(expected = "invalid parameter slow_periods: range min 100 is above its max 20")]
+  fn rejects_empty_ranges() {
+    let source = DEFAULT_EXPERIMENT.replace(
+      "slow_periods = { type = \"range\", min = 20, max = 100, step = 10 }",
+      "slow_periods = { type = \"range\", min = 100, max = 20, step = 10 }",
+    );
+    parse_experiment(source);
+  }
+
+  #[test]
+  fn walk_forward_accepts_non_adaptive_searches() {
+    experiment_with(r#"walk_forward = { mode = "rolling", train_sessions = 10, test_sessions = 5 }"#);
+  }
+
+  #[test]
+  #[should_panic(expected = "invalid walk_forward: train_sessions and test_sessions must be at least 1, got 0 and 5")]
+  fn walk_forward_rejects_empty_windows() {
+    experiment_with(r#"walk_forward = { mode = "rolling", train_sessions = 0, test_sessions = 5 }"#);
+  }
+
+  #[test]
+  #[should_panic(expected = "walk_forward needs a grid, random or latin_hypercube search")]
+  fn walk_forward_rejects_adaptive_searches() {
+    let source = DEFAULT_EXPERIMENT.replace(r#"search = { strategy = "grid" }"#, r#"search = { strategy = "tpe", budget = 50 }"#);
+    parse_experiment(format!(
+      "walk_forward = {{ mode = \"anchored\", train_sessions = 10, test_sessions = 5 }}\n{source}"
+    ));
+  }
+
+  #[test]
+  #[should_panic(expected = "pyramiding add_when = \"stronger_score\" needs a strategy with a score")]
+  fn stronger_score_pyramiding_needs_a_score() {
+    experiment_with(
+      r#"rules = "long when close > ema($fast_periods); short when close < ema($slow_periods)"
+pyramiding = { max_units = 2, add_when = "stronger_score" }"#,
+    );
+  }
+
+  const MONTE_CARLO_VALUES: &str = "warmup_periods = 1, fast_periods = 10, slow_periods = 20, slippage_percentage = 0.000125, \
+    profit_limit_percentage = 0.002, stop_loss_percentage = -0.002";
+
+  #[test]
+  fn monte_carlo_accepts_every_parameter() {
+    let experiment = experiment_with(&format!("monte_carlo = {{ parameters = {{ {MONTE_CARLO_VALUES} }} }}"));
+    let values = experiment.monte_carlo.unwrap().parameters.unwrap();
+    assert_eq!(values["short_borrow_fee_percentage"], 0.0);
+  }
+
+  #[test]
+  #[should_panic(expected = "unknown monte_carlo parameter fast_period")]
+  fn monte_carlo_rejects_misspelled_parameters() {
+    let values = MONTE_CARLO_VALUES.replace("fast_periods", "fast_period");
+    experiment_with(&format!("monte_carlo = {{ parameters = {{ {values} }} }}"));
+  }
+
+  #[test]
+  #[should_panic(expected = "missing monte_carlo parameter stop_loss_percentage")]
+  fn monte_carlo_rejects_incomplete_parameters() {
+    let values = MONTE_CARLO_VALUES.replace(", stop_loss_percentage = -0.002", "");
+    experiment_with(&format!("monte_carlo = {{ parameters = {{ {values} }} }}"));
+  }
+}
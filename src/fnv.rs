@@ -0,0 +1,29 @@
+use std::hash::Hasher;
+
+// fnv-1a, unlike std's default hasher it is stable across builds and runs so hashes can be persisted
+pub struct Fnv1a(u64);
+
+impl Default for Fnv1a {
+  fn default() -> Fnv1a {
+    return Fnv1a(0xcbf29ce484222325);
+  }
+}
+
+impl Hasher for Fnv1a {
+  fn write(&mut self, bytes: &[u8]) {
+    for byte in bytes {
+      self.0 ^= *byte as u64;
+      self.0 = self.0.wrapping_mul(0x100000001b3);
+    }
+  }
+
+  fn finish(&self) -> u64 {
+    return self.0;
+  }
+}
+
+pub fn hash_bytes(bytes: &[u8]) -> u64 {
+  let mut hasher = Fnv1a::default();
+  hasher.write(bytes);
+  return hasher.finish();
+}
@@ -0,0 +1,242 @@
+use std::collections::{HashMap, HashSet};
+
+use rand::rngs::StdRng;
+use rand::Rng;
+use serde::Deserialize;
+
+use crate::search::{self, Point, SearchSpace};
+
+// give up repairing an infeasible child after this many mutations and draw a fresh random point instead
+const MAX_REPAIRS: usize = 100;
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct GeneticParameters {
+  pub population_size: usize,
+  pub generations: usize,
+  #[serde(default = "default_tournament_size")]
+  pub tournament_size: usize,
+  #[serde(default = "default_crossover_rate")]
+  pub crossover_rate: f64,
+  #[serde(default = "default_mutation_rate")]
+  pub mutation_rate: f64,
+  #[serde(default = "default_elitism")]
+  pub elitism: usize,
+}
+
+fn default_tournament_size() -> usize {
+  return 3;
+}
+
+fn default_crossover_rate() -> f64 {
+  return 0.9;
+}
+
+fn default_mutation_rate() -> f64 {
+  return 0.1;
+}
+
+fn default_elitism() -> usize {
+  return 2;
+}
+
+impl GeneticParameters {
+  pub fn validate(&self) -> Result<(), String> {
+    if self.population_size == 0 {
+      return Err("population_size must be at least 1".to_string());
+    }
+    if self.tournament_size == 0 {
+      return Err("tournament_size must be at least 1".to_string());
+    }
+    if self.elitism > self.population_size {
+      return Err(format!("elitism {} is above population_size {}", self.elitism, self.population_size));
+    }
+    for (name, rate) in [("crossover_rate", self.crossover_rate), ("mutation_rate", self.mutation_rate)] {
+      if (0.0..=1.0).contains(&rate) == false {
+        return Err(format!("{name} must be between 0 and 1, got {rate}"));
+      }
+    }
+    return Ok(());
+  }
+}
+
+fn tournament<'a>(population: &'a [(Point, f64)], tournament_size: usize, rng: &mut StdRng) -> &'a Point {
+  let mut best = &population[rng.gen_range(0..population.len())];
+  for _ in 1..tournament_size {
+    let contender = &population[rng.gen_range(0..population.len())];
+    if contender.1 > best.1 {
+      best = contender;
+    }
+  }
+  return &best.0;
+}
+
+// uniform crossover, every gene comes from either parent with equal probability
+fn crossover(parent_a: &Point, parent_b: &Point, rng: &mut StdRng) -> Point {
+  return parent_a.iter().zip(parent_b).map(|(a, b)| if rng.gen_bool(0.5) { *a } else { *b }).collect();
+}
+
+// a mutated gene either steps to a neighbouring value or is redrawn uniformly
+fn mutate(point: &mut Point, space: &SearchSpace, mutation_rate: f64, rng: &mut StdRng) {
+  for (dimension, index) in point.iter_mut().enumerate() {
+    let num_values = space.values[dimension].len();
+    if num_values < 2 || rng.gen_bool(mutation_rate) == false {
+      continue;
+    }
+    if rng.gen_bool(0.5) {
+      *index = if *index == 0 || (*index + 1 < num_values && rng.gen_bool(0.5)) {
+        *index + 1
+      } else {
+        *index - 1
+      };
+    } else {
+      *index = rng.gen_range(0..num_values);
+    }
+  }
+}
+
+fn median(sorted_descending: &[(Point, f64)]) -> f64 {
+  let middle = sorted_descending.len() / 2;
+  if sorted_descending.len().is_multiple_of(2) {
+    return (sorted_descending[middle - 1].1 + sorted_descending[middle].1) / 2.0;
+  }
+  return sorted_descending[middle].1;
+}
+
+// maximizes fitness; evaluate scores a whole generation of not yet seen points at once so it can run them in parallel.
+// returns every evaluated point with its fitness in evaluation order
+pub fn genetic_search<F>(space: &SearchSpace, parameters: &GeneticParameters, rng: &mut StdRng, mut evaluate: F) -> Vec<(Point, f64)>
+where
+  F: FnMut(&[Point]) -> Vec<f64>,
+{
+  let mut history: Vec<(Point, f64)> = vec![];
+  let mut fitness_by_point: HashMap<Point, f64> = HashMap::new();
+  let mut score_population = |population: Vec<Point>, history: &mut Vec<(Point, f64)>| {
+    let mut queued = HashSet::new();
+    let unseen: Vec<Point> = population
+      .iter()
+      .filter(|point| fitness_by_point.contains_key(*point) == false && queued.insert((*point).clone()))
+      .cloned()
+      .collect();
+    for (point, fitness) in unseen.iter().zip(evaluate(&unseen)) {
+      if fitness_by_point.insert(point.clone(), fitness).is_none() {
+        history.push((point.clone(), fitness));
+      }
+    }
+    let mut scored: Vec<(Point, f64)> = population.into_iter().map(|point| (point.clone(), fitness_by_point[&point])).collect();
+    scored.sort_by(|a, b| b.1.total_cmp(&a.1));
+    return scored;
+  };
+  let initial_population = search::random_search(space, parameters.population_size, rng);
+  if initial_population.is_empty() {
+    return history;
+  }
+  let mut population = score_population(initial_population, &mut history);
+  eprintln!("generation 0: best {} median {}", population[0].1, median(&population));
+  for generation in 1..=parameters.generations {
+    // elites survive unchanged
+    let mut next_population: Vec<Point> = population.iter().take(parameters.elitism).map(|(point, _)| point.clone()).collect();
+    while next_population.len() < parameters.population_size {
+      let parent_a = tournament(&population, parameters.tournament_size, rng);
+      let parent_b = tournament(&population, parameters.tournament_size, rng);
+      let mut child = if rng.gen_bool(parameters.crossover_rate) {
+        crossover(parent_a, parent_b, rng)
+      } else {
+        parent_a.clone()
+      };
+      mutate(&mut child, space, parameters.mutation_rate, rng);
+      let mut repairs = 0;
+      while space.is_feasible(&child) == false && repairs < MAX_REPAIRS {
+        mutate(&mut child, space, parameters.mutation_rate.max(0.5), rng);
+        repairs += 1;
+      }
+      // a random point, or the (feasible) first parent when none can be found
+      if space.is_feasible(&child) == false {
+        child = search::random_search(space, 1, rng).pop().unwrap_or_else(|| parent_a.clone());
+      }
+      next_population.push(child);
+    }
+    population = score_population(next_population, &mut history);
+    eprintln!("generation {generation}: best {} median {}", population[0].1, median(&population));
+  }
+  return history;
+}
+
+#[cfg(test)]
+mod tests {
+  use rand::SeedableRng;
+
+  use super::*;
+  use crate::experiment;
+
+  fn parameters() -> GeneticParameters {
+    return GeneticParameters {
+      population_size: 12,
+      generations: 5,
+      tournament_size: default_tournament_size(),
+      crossover_rate: default_crossover_rate(),
+      mutation_rate: default_mutation_rate(),
+      elitism: default_elitism(),
+    };
+  }
+
+  // peaks in the middle of every dimension
+  fn fitness(points: &[Point]) -> Vec<f64> {
+    return points
+      .iter()
+      .map(|point| -point.iter().map(|index| (*index as f64 - 4.0).powi(2)).sum::<f64>())
+      .collect();
+  }
+
+  fn run(seed: u64) -> Vec<(Point, f64)> {
+    let space = SearchSpace::new(&experiment::load_experiment(None));
+    return genetic_search(&space, &parameters(), &mut StdRng::seed_from_u64(seed), fitness);
+  }
+
+  fn best_fitness(history: &[(Point, f64)]) -> f64 {
+    return history.iter().map(|(_, fitness)| *fitness).fold(f64::NEG_INFINITY, f64::max);
+  }
+
+  #[test]
+  fn is_deterministic_under_a_seed() {
+    let first = run(7);
+    let second = run(7);
+    assert_eq!(first, second);
+    assert_eq!(best_fitness(&first), best_fitness(&second));
+    assert_ne!(first, run(8));
+  }
+
+  #[test]
+  fn evaluates_only_distinct_feasible_points() {
+    let space = SearchSpace::new(&experiment::load_experiment(None));
+    let history = run(7);
+    let distinct: HashSet<&Point> = history.iter().map(|(point, _)| point).collect();
+    assert_eq!(distinct.len(), history.len());
+    assert!(history.iter().all(|(point, _)| space.is_feasible(point)));
+  }
+
+  #[test]
+  fn validates_rates_and_sizes() {
+    let with = |change: fn(&mut GeneticParameters)| {
+      let mut parameters = parameters();
+      change(&mut parameters);
+      return parameters.validate();
+    };
+    assert!(with(|_| {}).is_ok());
+    assert_eq!(
+      with(|parameters| parameters.crossover_rate = 1.5),
+      Err("crossover_rate must be between 0 and 1, got 1.5".to_string())
+    );
+    assert_eq!(
+      with(|parameters| parameters.mutation_rate = -0.1),
+      Err("mutation_rate must be between 0 and 1, got -0.1".to_string())
+    );
+    assert_eq!(
+      with(|parameters| parameters.elitism = 13),
+      Err("elitism 13 is above population_size 12".to_string())
+    );
+    assert_eq!(
+      with(|parameters| parameters.tournament_size = 0),
+      Err("tournament_size must be at least 1".to_string())
+    );
+  }
+}
@@ -0,0 +1,208 @@
+use serde::Deserialize;
+
+use crate::performance::PerformanceMetrics;
+
+const CELL_WIDTH: usize = 56;
+const CELL_HEIGHT: usize = 24;
+const MARGIN_LEFT: usize = 110;
+const MARGIN_TOP: usize = 40;
+const MARGIN_BOTTOM: usize = 70;
+const LEGEND_WIDTH: usize = 130;
+
+// viridis, low to high
+const COLOR_STOPS: [(f64, f64, f64); 5] = [
+  (68.0, 1.0, 84.0),
+  (59.0, 82.0, 139.0),
+  (33.0, 145.0, 140.0),
+  (94.0, 201.0, 98.0),
+  (253.0, 231.0, 37.0),
+];
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Metric {
+  ProfitLossPercentage,
+  NumTrades,
+  WinRate,
+  MaxDrawdown,
+  SharpeRatio,
+  ProfitFactor,
+  ReturnOverDrawdown,
+  Robustness,
+}
+
+impl Metric {
+  pub fn name(&self) -> &'static str {
+    match self {
+      Metric::ProfitLossPercentage => return "profit_loss_percentage",
+      Metric::NumTrades => return "num_trades",
+      Metric::WinRate => return "win_rate",
+      Metric::MaxDrawdown => return "max_drawdown",
+      Metric::SharpeRatio => return "sharpe_ratio",
+      Metric::ProfitFactor => return "profit_factor",
+      Metric::ReturnOverDrawdown => return "return_over_drawdown",
+      Metric::Robustness => return "robustness",
+    }
+  }
+
+  pub fn value(&self, metrics: &PerformanceMetrics, robustness: f64) -> f64 {
+    match self {
+      Metric::ProfitLossPercentage => return metrics.profit_loss_percentage,
+      Metric::NumTrades => return metrics.num_trades as f64,
+      Metric::WinRate => return metrics.win_rate(),
+      Metric::MaxDrawdown => return metrics.max_drawdown,
+      Metric::SharpeRatio => return metrics.sharpe_ratio(),
+      Metric::ProfitFactor => return metrics.profit_factor(),
+      Metric::ReturnOverDrawdown => return metrics.return_over_drawdown(),
+      Metric::Robustness => return robustness,
+    }
+  }
+}
+
+// how the combinations that share a cell (differ only in the other parameters) are summarized
+#[derive(Debug, Clone, Copy, Deserialize, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum Aggregate {
+  #[default]
+  Max,
+  Min,
+  Mean,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct HeatmapParameters {
+  pub x: String,
+  pub y: String,
+  pub metric: Metric,
+  #[serde(default)]
+  pub aggregate: Aggregate,
+}
+
+// cells[y][x] from (x index, y index, value) entries, none where nothing was evaluated
+pub fn build_cells(num_x: usize, num_y: usize, entries: &[(usize, usize, f64)], aggregate: Aggregate) -> Vec<Vec<Option<f64>>> {
+  let mut values: Vec<Vec<Vec<f64>>> = vec![vec![vec![]; num_x]; num_y];
+  for (x, y, value) in entries {
+    if value.is_nan() == false {
+      values[*y][*x].push(*value);
+    }
+  }
+  return values
+    .into_iter()
+    .map(|row| {
+      row
+        .into_iter()
+        .map(|cell| {
+          if cell.is_empty() {
+            return None;
+          }
+          match aggregate {
+            Aggregate::Max => return Some(cell.iter().copied().fold(f64::NEG_INFINITY, f64::max)),
+            Aggregate::Min => return Some(cell.iter().copied().fold(f64::INFINITY, f64::min)),
+            Aggregate::Mean => return Some(cell.iter().sum::<f64>() / cell.len() as f64),
+          }
+        })
+        .collect()
+    })
+    .collect();
+}
+
+fn color(fraction: f64) -> String {
+  let position = fraction.clamp(0.0, 1.0) * (COLOR_STOPS.len() - 1) as f64;
+  let index = (position.floor() as usize).min(COLOR_STOPS.len() - 2);
+  let t = position - index as f64;
+  let (r0, g0, b0) = COLOR_STOPS[index];
+  let (r1, g1, b1) = COLOR_STOPS[index + 1];
+  let mix = |a: f64, b: f64| (a + (b - a) * t).round() as u8;
+  return format!("#{:02x}{:02x}{:02x}", mix(r0, r1), mix(g0, g1), mix(b0, b1));
+}
+
+fn escape(text: &str) -> String {
+  return text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;");
+}
+
+// standalone svg, x values left to right and y values bottom to top. infinite values get the end colors of the scale
+pub fn render_svg(title: &str, x_name: &str, x_values: &[f64], y_name: &str, y_values: &[f64], cells: &[Vec<Option<f64>>]) -> String {
+  let finite: Vec<f64> = cells.iter().flatten().flatten().copied().filter(|value| value.is_finite()).collect();
+  let low = finite.iter().copied().fold(f64::INFINITY, f64::min);
+  let high = finite.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+  let fraction = |value: f64| {
+    if value == f64::INFINITY {
+      return 1.0;
+    }
+    if value == f64::NEG_INFINITY || high <= low {
+      return 0.0;
+    }
+    return (value - low) / (high - low);
+  };
+  let plot_width = CELL_WIDTH * x_values.len();
+  let plot_height = CELL_HEIGHT * y_values.len();
+  let width = MARGIN_LEFT + plot_width + LEGEND_WIDTH;
+  let height = MARGIN_TOP + plot_height + MARGIN_BOTTOM;
+  let mut svg = String::new();
+  svg.push_str(&format!(
+    "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{width}\" height=\"{height}\" font-family=\"sans-serif\" font-size=\"11\">\n"
+  ));
+  svg.push_str(&format!("<rect width=\"{width}\" height=\"{height}\" fill=\"white\"/>\n"));
+  svg.push_str(&format!("<text x=\"{}\" y=\"24\" font-size=\"14\">{}</text>\n", MARGIN_LEFT, escape(title)));
+  // cells, row 0 at the bottom
+  for (row, y_value) in y_values.iter().enumerate() {
+    let top = MARGIN_TOP + plot_height - (row + 1) * CELL_HEIGHT;
+    for (column, x_value) in x_values.iter().enumerate() {
+      let left = MARGIN_LEFT + column * CELL_WIDTH;
+      let (fill, label) = match cells[row][column] {
+        Some(value) => (color(fraction(value)), format!("{value}")),
+        None => ("#dddddd".to_string(), "not evaluated".to_string()),
+      };
+      svg.push_str(&format!(
+        "<rect x=\"{left}\" y=\"{top}\" width=\"{CELL_WIDTH}\" height=\"{CELL_HEIGHT}\" fill=\"{fill}\"><title>{x_name}={x_value} {y_name}={y_value}: {label}</title></rect>\n"
+      ));
+    }
+    svg.push_str(&format!(
+      "<text x=\"{}\" y=\"{}\" text-anchor=\"end\" dominant-baseline=\"middle\">{y_value}</text>\n",
+      MARGIN_LEFT - 6,
+      top + CELL_HEIGHT / 2
+    ));
+  }
+  for (column, x_value) in x_values.iter().enumerate() {
+    svg.push_str(&format!(
+      "<text x=\"{}\" y=\"{}\" text-anchor=\"middle\">{x_value}</text>\n",
+      MARGIN_LEFT + column * CELL_WIDTH + CELL_WIDTH / 2,
+      MARGIN_TOP + plot_height + 16
+    ));
+  }
+  // axis names
+  svg.push_str(&format!(
+    "<text x=\"{}\" y=\"{}\" text-anchor=\"middle\" font-size=\"13\">{}</text>\n",
+    MARGIN_LEFT + plot_width / 2,
+    MARGIN_TOP + plot_height + 44,
+    escape(x_name)
+  ));
+  svg.push_str(&format!(
+    "<text x=\"16\" y=\"{}\" text-anchor=\"middle\" font-size=\"13\" transform=\"rotate(-90 16 {})\">{}</text>\n",
+    MARGIN_TOP + plot_height / 2,
+    MARGIN_TOP + plot_height / 2,
+    escape(y_name)
+  ));
+  // legend, high at the top
+  let legend_left = MARGIN_LEFT + plot_width + 20;
+  let steps = 20;
+  let step_height = plot_height.max(CELL_HEIGHT * 4) as f64 / steps as f64;
+  for step in 0..steps {
+    svg.push_str(&format!(
+      "<rect x=\"{legend_left}\" y=\"{:.1}\" width=\"16\" height=\"{:.1}\" fill=\"{}\"/>\n",
+      MARGIN_TOP as f64 + step as f64 * step_height,
+      step_height + 0.5,
+      color(1.0 - step as f64 / (steps - 1) as f64)
+    ));
+  }
+  if finite.is_empty() == false {
+    svg.push_str(&format!("<text x=\"{}\" y=\"{}\">{high:.6}</text>\n", legend_left + 22, MARGIN_TOP + 10));
+    svg.push_str(&format!(
+      "<text x=\"{}\" y=\"{:.1}\">{low:.6}</text>\n",
+      legend_left + 22,
+      MARGIN_TOP as f64 + steps as f64 * step_height
+    ));
+  }
+  svg.push_str("</svg>\n");
+  return svg;
+}
@@ -0,0 +1,788 @@
+// streaming indicators behind one interface so any of them can feed a signal rule
+
+use std::collections::VecDeque;
+
+use ta::indicators::{
+  AverageTrueRange, BollingerBands, ExponentialMovingAverage, FastStochastic, KeltnerChannel, MovingAverageConvergenceDivergence, OnBalanceVolume,
+  RelativeStrengthIndex, SimpleMovingAverage,
+};
+use ta::Next;
+
+use crate::{determine_session_type, get_regular_market_session_start_and_end, Candle, MarketSessionType};
+
+impl ta::Open for Candle {
+  fn open(&self) -> f64 {
+    return self.open;
+  }
+}
+
+impl ta::High for Candle {
+  fn high(&self) -> f64 {
+    return self.high;
+  }
+}
+
+impl ta::Low for Candle {
+  fn low(&self) -> f64 {
+    return self.low;
+  }
+}
+
+impl ta::Close for Candle {
+  fn close(&self) -> f64 {
+    return self.close;
+  }
+}
+
+impl ta::Volume for Candle {
+  fn volume(&self) -> f64 {
+    return self.volume as f64;
+  }
+}
+
+// consumes one closed candle at a time and returns the indicator's current value
+pub trait Indicator {
+  fn next(&mut self, candle: &Candle) -> f64;
+}
+
+// which line of a band indicator (bollinger, keltner) to return
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BandLine {
+  Upper,
+  Middle,
+  Lower,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MacdLine {
+  Macd,
+  Signal,
+  Histogram,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StochasticLine {
+  // raw %k
+  K,
+  // simple moving average of %k
+  D,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AdxLine {
+  Adx,
+  PlusDi,
+  MinusDi,
+}
+
+pub struct Sma(SimpleMovingAverage);
+
+impl Sma {
+  pub fn new(periods: usize) -> Sma {
+    return Sma(SimpleMovingAverage::new(periods).unwrap());
+  }
+}
+
+impl Indicator for Sma {
+  fn next(&mut self, candle: &Candle) -> f64 {
+    return self.0.next(candle);
+  }
+}
+
+pub struct Ema(ExponentialMovingAverage);
+
+impl Ema {
+  pub fn new(periods: usize) -> Ema {
+    return Ema(ExponentialMovingAverage::new(periods).unwrap());
+  }
+}
+
+impl Indicator for Ema {
+  fn next(&mut self, candle: &Candle) -> f64 {
+    return self.0.next(candle);
+  }
+}
+
+// linearly weighted, the newest close has weight periods
+pub struct Wma {
+  periods: usize,
+  values: VecDeque<f64>,
+}
+
+impl Wma {
+  pub fn new(periods: usize) -> Wma {
+    assert!(periods > 0, "wma periods must be positive");
+    return Wma {
+      periods,
+      values: VecDeque::with_capacity(periods),
+    };
+  }
+
+  fn next_value(&mut self, value: f64) -> f64 {
+    if self.values.len() == self.periods {
+      self.values.pop_front();
+    }
+    self.values.push_back(value);
+    let weighted_sum: f64 = self.values.iter().enumerate().map(|(index, value)| (index + 1) as f64 * value).sum();
+    let total_weight = (self.values.len() * (self.values.len() + 1) / 2) as f64;
+    return weighted_sum / total_weight;
+  }
+}
+
+impl Indicator for Wma {
+  fn next(&mut self, candle: &Candle) -> f64 {
+    return self.next_value(candle.close);
+  }
+}
+
+// hull: wma(2 * wma(n / 2) - wma(n), sqrt(n))
+pub struct HullMa {
+  half: Wma,
+  full: Wma,
+  smoothing: Wma,
+}
+
+impl HullMa {
+  pub fn new(periods: usize) -> HullMa {
+    return HullMa {
+      half: Wma::new((periods / 2).max(1)),
+      full: Wma::new(periods),
+      smoothing: Wma::new(((periods as f64).sqrt().round() as usize).max(1)),
+    };
+  }
+}
+
+impl Indicator for HullMa {
+  fn next(&mut self, candle: &Candle) -> f64 {
+    let half = self.half.next_value(candle.close);
+    let full = self.full.next_value(candle.close);
+    return self.smoothing.next_value(2.0 * half - full);
+  }
+}
+
+pub struct Rsi(RelativeStrengthIndex);
+
+impl Rsi {
+  pub fn new(periods: usize) -> Rsi {
+    return Rsi(RelativeStrengthIndex::new(periods).unwrap());
+  }
+}
+
+impl Indicator for Rsi {
+  fn next(&mut self, candle: &Candle) -> f64 {
+    return self.0.next(candle);
+  }
+}
+
+pub struct Macd {
+  macd: MovingAverageConvergenceDivergence,
+  line: MacdLine,
+}
+
+impl Macd {
+  pub fn new(fast_periods: usize, slow_periods: usize, signal_periods: usize, line: MacdLine) -> Macd {
+    return Macd {
+      macd: MovingAverageConvergenceDivergence::new(fast_periods, slow_periods, signal_periods).unwrap(),
+      line,
+    };
+  }
+}
+
+impl Indicator for Macd {
+  fn next(&mut self, candle: &Candle) -> f64 {
+    let output = self.macd.next(candle);
+    match self.line {
+      MacdLine::Macd => return output.macd,
+      MacdLine::Signal => return output.signal,
+      MacdLine::Histogram => return output.histogram,
+    }
+  }
+}
+
+pub struct Bollinger {
+  bands: BollingerBands,
+  line: BandLine,
+}
+
+impl Bollinger {
+  pub fn new(periods: usize, multiplier: f64, line: BandLine) -> Bollinger {
+    return Bollinger {
+      bands: BollingerBands::new(periods, multiplier).unwrap(),
+      line,
+    };
+  }
+}
+
+impl Indicator for Bollinger {
+  fn next(&mut self, candle: &Candle) -> f64 {
+    let output = self.bands.next(candle);
+    match self.line {
+      BandLine::Upper => return output.upper,
+      BandLine::Middle => return output.average,
+      BandLine::Lower => return output.lower,
+    }
+  }
+}
+
+pub struct Atr(AverageTrueRange);
+
+impl Atr {
+  pub fn new(periods: usize) -> Atr {
+    return Atr(AverageTrueRange::new(periods).unwrap());
+  }
+}
+
+impl Indicator for Atr {
+  fn next(&mut self, candle: &Candle) -> f64 {
+    return self.0.next(candle);
+  }
+}
+
+pub struct Keltner {
+  channel: KeltnerChannel,
+  line: BandLine,
+}
+
+impl Keltner {
+  pub fn new(periods: usize, multiplier: f64, line: BandLine) -> Keltner {
+    return Keltner {
+      channel: KeltnerChannel::new(periods, multiplier).unwrap(),
+      line,
+    };
+  }
+}
+
+impl Indicator for Keltner {
+  fn next(&mut self, candle: &Candle) -> f64 {
+    let output = self.channel.next(candle);
+    match self.line {
+      BandLine::Upper => return output.upper,
+      BandLine::Middle => return output.average,
+      BandLine::Lower => return output.lower,
+    }
+  }
+}
+
+pub struct Stochastic {
+  k: FastStochastic,
+  d: SimpleMovingAverage,
+  line: StochasticLine,
+}
+
+impl Stochastic {
+  pub fn new(periods: usize, d_periods: usize, line: StochasticLine) -> Stochastic {
+    return Stochastic {
+      k: FastStochastic::new(periods).unwrap(),
+      d: SimpleMovingAverage::new(d_periods).unwrap(),
+      line,
+    };
+  }
+}
+
+impl Indicator for Stochastic {
+  fn next(&mut self, candle: &Candle) -> f64 {
+    let k = self.k.next(candle);
+    let d = self.d.next(k);
+    match self.line {
+      StochasticLine::K => return k,
+      StochasticLine::D => return d,
+    }
+  }
+}
+
+// wilder's directional movement, smoothed with wilder's moving average (alpha = 1 / periods). unlike wilder's own
+// seeding, which starts the smoothed sums from the first periods values and the adx from the mean of the first
+// periods dx values, the averages here start from the first candle pair and the adx from the first dx. early values
+// therefore differ from most charting packages and converge after a few multiples of periods. 0 on the first candle
+pub struct Adx {
+  periods: usize,
+  line: AdxLine,
+  previous: Option<Candle>,
+  smoothed_true_range: f64,
+  smoothed_plus_dm: f64,
+  smoothed_minus_dm: f64,
+  adx: Option<f64>,
+}
+
+impl Adx {
+  pub fn new(periods: usize, line: AdxLine) -> Adx {
+    assert!(periods > 0, "adx periods must be positive");
+    return Adx {
+      periods,
+      line,
+      previous: None,
+      smoothed_true_range: 0.0,
+      smoothed_plus_dm: 0.0,
+      smoothed_minus_dm: 0.0,
+      adx: None,
+    };
+  }
+}
+
+impl Indicator for Adx {
+  fn next(&mut self, candle: &Candle) -> f64 {
+    let previous = match self.previous.replace(*candle) {
+      Some(previous) => previous,
+      None => return 0.0,
+    };
+    let up_move = candle.high - previous.high;
+    let down_move = previous.low - candle.low;
+    let plus_dm = if up_move > down_move && up_move > 0.0 { up_move } else { 0.0 };
+    let minus_dm = if down_move > up_move && down_move > 0.0 { down_move } else { 0.0 };
+    let true_range = (candle.high - candle.low)
+      .max((candle.high - previous.close).abs())
+      .max((candle.low - previous.close).abs());
+    let alpha = 1.0 / self.periods as f64;
+    self.smoothed_true_range += alpha * (true_range - self.smoothed_true_range);
+    self.smoothed_plus_dm += alpha * (plus_dm - self.smoothed_plus_dm);
+    self.smoothed_minus_dm += alpha * (minus_dm - self.smoothed_minus_dm);
+    let (plus_di, minus_di) = if self.smoothed_true_range > 0.0 {
+      (
+        100.0 * self.smoothed_plus_dm / self.smoothed_true_range,
+        100.0 * self.smoothed_minus_dm / self.smoothed_true_range,
+      )
+    } else {
+      (0.0, 0.0)
+    };
+    let dx = if plus_di + minus_di > 0.0 {
+      100.0 * (plus_di - minus_di).abs() / (plus_di + minus_di)
+    } else {
+      0.0
+    };
+    let adx = match self.adx {
+      Some(adx) => adx + alpha * (dx - adx),
+      None => dx,
+    };
+    self.adx = Some(adx);
+    match self.line {
+      AdxLine::Adx => return adx,
+      AdxLine::PlusDi => return plus_di,
+      AdxLine::MinusDi => return minus_di,
+    }
+  }
+}
+
+pub struct Obv(OnBalanceVolume);
+
+impl Obv {
+  pub fn new() -> Obv {
+    return Obv(OnBalanceVolume::new());
+  }
+}
+
+impl Indicator for Obv {
+  fn next(&mut self, candle: &Candle) -> f64 {
+    return self.0.next(candle);
+  }
+}
+
+// average volume, for relative volume filters
+pub struct VolumeSma(SimpleMovingAverage);
+
+impl VolumeSma {
+  pub fn new(periods: usize) -> VolumeSma {
+    return VolumeSma(SimpleMovingAverage::new(periods).unwrap());
+  }
+}
+
+impl Indicator for VolumeSma {
+  fn next(&mut self, candle: &Candle) -> f64 {
+    return self.0.next(candle.volume as f64);
+  }
+}
+
+// which session a candle belongs to, shared by the indicators that reset every session (grouping_key)
+struct SessionTracker {
+  grouping_key: Option<i64>,
+  is_regular_started: bool,
+}
+
+enum SessionEvent {
+  // first candle of a new grouping_key
+  NewSession,
+  // first regular market candle of the current session
+  RegularOpen,
+  None,
+}
+
+impl SessionTracker {
+  fn new() -> SessionTracker {
+    return SessionTracker {
+      grouping_key: None,
+      is_regular_started: false,
+    };
+  }
+
+  // the regular open can coincide with a new session when there was no pre-market data, new session wins then and
+  // the regular open is reported through is_regular_started
+  fn update(&mut self, candle: &Candle) -> SessionEvent {
+    let (regular_session_start, _) = get_regular_market_session_start_and_end(candle.start_timestamp);
+    let grouping_key = regular_session_start.timestamp();
+    let is_regular = determine_session_type(candle.start_timestamp) == MarketSessionType::Regular;
+    if self.grouping_key != Some(grouping_key) {
+      self.grouping_key = Some(grouping_key);
+      self.is_regular_started = is_regular;
+      return SessionEvent::NewSession;
+    }
+    if is_regular && self.is_regular_started == false {
+      self.is_regular_started = true;
+      return SessionEvent::RegularOpen;
+    }
+    return SessionEvent::None;
+  }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum VwapLine {
+  Vwap,
+  // vwap plus/minus band_multiplier volume weighted standard deviations
+  Upper,
+  Lower,
+}
+
+// volume weighted typical price anchored at the regular open. pre-market candles get their own running vwap that
+// restarts with every session (grouping_key) and is discarded at the open
+pub struct SessionVwap {
+  session: SessionTracker,
+  line: VwapLine,
+  band_multiplier: f64,
+  cumulative_volume: f64,
+  cumulative_price_volume: f64,
+  cumulative_squared_price_volume: f64,
+}
+
+impl SessionVwap {
+  pub fn new(line: VwapLine, band_multiplier: f64) -> SessionVwap {
+    return SessionVwap {
+      session: SessionTracker::new(),
+      line,
+      band_multiplier,
+      cumulative_volume: 0.0,
+      cumulative_price_volume: 0.0,
+      cumulative_squared_price_volume: 0.0,
+    };
+  }
+}
+
+impl Indicator for SessionVwap {
+  fn next(&mut self, candle: &Candle) -> f64 {
+    match self.session.update(candle) {
+      SessionEvent::NewSession | SessionEvent::RegularOpen => {
+        self.cumulative_volume = 0.0;
+        self.cumulative_price_volume = 0.0;
+        self.cumulative_squared_price_volume = 0.0;
+      }
+      SessionEvent::None => {}
+    }
+    let typical_price = (candle.high + candle.low + candle.close) / 3.0;
+    let volume = candle.volume as f64;
+    self.cumulative_volume += volume;
+    self.cumulative_price_volume += typical_price * volume;
+    self.cumulative_squared_price_volume += typical_price * typical_price * volume;
+    if self.cumulative_volume == 0.0 {
+      return typical_price;
+    }
+    let vwap = self.cumulative_price_volume / self.cumulative_volume;
+    let variance = (self.cumulative_squared_price_volume / self.cumulative_volume - vwap * vwap).max(0.0);
+    match self.line {
+      VwapLine::Vwap => return vwap,
+      VwapLine::Upper => return vwap + self.band_multiplier * variance.sqrt(),
+      VwapLine::Lower => return vwap - self.band_multiplier * variance.sqrt(),
+    }
+  }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RangeLine {
+  High,
+  Low,
+}
+
+// high/low of the first minutes of the regular session, nan until that window has fully closed
+pub struct OpeningRange {
+  session: SessionTracker,
+  seconds: i64,
+  line: RangeLine,
+  high: f64,
+  low: f64,
+  is_complete: bool,
+}
+
+impl OpeningRange {
+  pub fn new(minutes: usize, line: RangeLine) -> OpeningRange {
+    assert!(minutes > 0, "opening range minutes must be positive");
+    return OpeningRange {
+      session: SessionTracker::new(),
+      seconds: minutes as i64 * 60,
+      line,
+      high: f64::NEG_INFINITY,
+      low: f64::INFINITY,
+      is_complete: false,
+    };
+  }
+}
+
+impl Indicator for OpeningRange {
+  fn next(&mut self, candle: &Candle) -> f64 {
+    if let SessionEvent::NewSession = self.session.update(candle) {
+      self.high = f64::NEG_INFINITY;
+      self.low = f64::INFINITY;
+      self.is_complete = false;
+    }
+    let (regular_session_start, _) = get_regular_market_session_start_and_end(candle.start_timestamp);
+    let range_end = regular_session_start.timestamp() + self.seconds;
+    if self.session.is_regular_started && candle.start_timestamp < range_end {
+      self.high = self.high.max(candle.high);
+      self.low = self.low.min(candle.low);
+      self.is_complete = candle.end_timestamp >= range_end - 1;
+    } else if candle.start_timestamp >= range_end {
+      self.is_complete = self.high.is_finite();
+    }
+    if self.is_complete == false {
+      return f64::NAN;
+    }
+    match self.line {
+      RangeLine::High => return self.high,
+      RangeLine::Low => return self.low,
+    }
+  }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PriorDayLine {
+  High,
+  Low,
+  Close,
+}
+
+// regular session high/low/close of the previous session, nan during the first one
+pub struct PriorDay {
+  session: SessionTracker,
+  line: PriorDayLine,
+  current: Option<(f64, f64, f64)>,
+  prior: Option<(f64, f64, f64)>,
+}
+
+impl PriorDay {
+  pub fn new(line: PriorDayLine) -> PriorDay {
+    return PriorDay {
+      session: SessionTracker::new(),
+      line,
+      current: None,
+      prior: None,
+    };
+  }
+}
+
+impl Indicator for PriorDay {
+  fn next(&mut self, candle: &Candle) -> f64 {
+    if let SessionEvent::NewSession = self.session.update(candle) {
+      if self.current.is_some() {
+        self.prior = self.current.take();
+      }
+    }
+    if determine_session_type(candle.start_timestamp) == MarketSessionType::Regular {
+      self.current = Some(match self.current {
+        Some((high, low, _)) => (high.max(candle.high), low.min(candle.low), candle.close),
+        None => (candle.high, candle.low, candle.close),
+      });
+    }
+    match (self.prior, self.line) {
+      (None, _) => return f64::NAN,
+      (Some((high, _, _)), PriorDayLine::High) => return high,
+      (Some((_, low, _)), PriorDayLine::Low) => return low,
+      (Some((_, _, close)), PriorDayLine::Close) => return close,
+    }
+  }
+}
+
+// high/low of the current session's pre-market candles, frozen once the regular session opens and nan without any
+pub struct PreMarketRange {
+  session: SessionTracker,
+  line: RangeLine,
+  high: f64,
+  low: f64,
+}
+
+impl PreMarketRange {
+  pub fn new(line: RangeLine) -> PreMarketRange {
+    return PreMarketRange {
+      session: SessionTracker::new(),
+      line,
+      high: f64::NAN,
+      low: f64::NAN,
+    };
+  }
+}
+
+impl Indicator for PreMarketRange {
+  fn next(&mut self, candle: &Candle) -> f64 {
+    if let SessionEvent::NewSession = self.session.update(candle) {
+      self.high = f64::NAN;
+      self.low = f64::NAN;
+    }
+    if determine_session_type(candle.start_timestamp) == MarketSessionType::Pre {
+      // f64::max/min ignore nan, so the first candle replaces it
+      self.high = self.high.max(candle.high);
+      self.low = self.low.min(candle.low);
+    }
+    match self.line {
+      RangeLine::High => return self.high,
+      RangeLine::Low => return self.low,
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  // wednesday 2023-03-01 09:30 eastern
+  const REGULAR_OPEN: i64 = 1677681000;
+  const DAY: i64 = 86400;
+
+  fn candle(start_timestamp: i64, high: f64, low: f64, close: f64, volume: i64) -> Candle {
+    return Candle {
+      start_timestamp,
+      end_timestamp: start_timestamp + 59,
+      open: close,
+      high,
+      low,
+      close,
+      volume,
+    };
+  }
+
+  fn flat(start_timestamp: i64, price: f64) -> Candle {
+    return candle(start_timestamp, price, price, price, 100);
+  }
+
+  fn run(indicator: &mut dyn Indicator, candles: &[Candle]) -> Vec<f64> {
+    return candles.iter().map(|candle| indicator.next(candle)).collect();
+  }
+
+  fn assert_close(actual: &[f64], expected: &[f64]) {
+    assert_eq!(actual.len(), expected.len());
+    for (actual, expected) in actual.iter().zip(expected) {
+      let is_same = (actual.is_nan() && expected.is_nan()) || (actual - expected).abs() < 1e-9;
+      assert!(is_same, "{actual:?} != {expected:?}");
+    }
+  }
+
+  fn closes(values: &[f64]) -> Vec<Candle> {
+    return values
+      .iter()
+      .enumerate()
+      .map(|(index, &close)| flat(REGULAR_OPEN + index as i64 * 60, close))
+      .collect();
+  }
+
+  #[test]
+  fn wma_weights_what_it_has_during_warm_up() {
+    let values = run(&mut Wma::new(3), &closes(&[1.0, 2.0, 3.0, 4.0]));
+    assert_close(&values, &[1.0, 5.0 / 3.0, 14.0 / 6.0, 20.0 / 6.0]);
+  }
+
+  #[test]
+  fn hull_tracks_a_straight_line_once_warm() {
+    let values = run(&mut HullMa::new(4), &closes(&[1.0, 2.0, 3.0, 4.0, 5.0, 6.0]));
+    assert_close(&values[4..], &[5.0, 6.0]);
+    // half = wma(2) = 11 / 3, full = wma(4) = 3, smoothing = wma(2) of 3 and 13 / 3
+    assert_close(&values[3..4], &[35.0 / 9.0]);
+  }
+
+  #[test]
+  fn adx_seeds_from_the_first_dx() {
+    let candles = [
+      candle(REGULAR_OPEN, 10.0, 8.0, 9.0, 100),
+      // +dm 2, tr 3
+      candle(REGULAR_OPEN + 60, 12.0, 9.0, 11.0, 100),
+      // -dm 2, tr 4
+      candle(REGULAR_OPEN + 120, 11.0, 7.0, 8.0, 100),
+    ];
+    let adx = run(&mut Adx::new(2, AdxLine::Adx), &candles);
+    let plus_di = run(&mut Adx::new(2, AdxLine::PlusDi), &candles);
+    let minus_di = run(&mut Adx::new(2, AdxLine::MinusDi), &candles);
+    assert_close(&plus_di, &[0.0, 200.0 / 3.0, 50.0 / 2.75]);
+    assert_close(&minus_di, &[0.0, 0.0, 100.0 / 2.75]);
+    // the first dx (100) is the first adx, wilder would wait for periods dx values and average them
+    assert_close(&adx, &[0.0, 100.0, 100.0 + 0.5 * (100.0 / 3.0 - 100.0)]);
+  }
+
+  #[test]
+  fn session_vwap_restarts_at_the_open_and_every_session() {
+    let candles = [
+      // pre-market, typical price 10
+      candle(REGULAR_OPEN - 3600, 12.0, 9.0, 9.0, 100),
+      flat(REGULAR_OPEN - 3540, 12.0),
+      flat(REGULAR_OPEN, 20.0),
+      flat(REGULAR_OPEN + 60, 30.0),
+      flat(REGULAR_OPEN + DAY - 3600, 50.0),
+    ];
+    let vwap = run(&mut SessionVwap::new(VwapLine::Vwap, 2.0), &candles);
+    let upper = run(&mut SessionVwap::new(VwapLine::Upper, 2.0), &candles);
+    let lower = run(&mut SessionVwap::new(VwapLine::Lower, 2.0), &candles);
+    assert_close(&vwap, &[10.0, 11.0, 20.0, 25.0, 50.0]);
+    // the regular session's standard deviation is 5 after two candles
+    assert_close(&upper, &[10.0, 13.0, 20.0, 35.0, 50.0]);
+    assert_close(&lower, &[10.0, 9.0, 20.0, 15.0, 50.0]);
+  }
+
+  #[test]
+  fn session_vwap_falls_back_to_the_typical_price_without_volume() {
+    let values = run(&mut SessionVwap::new(VwapLine::Vwap, 2.0), &[candle(REGULAR_OPEN, 12.0, 9.0, 9.0, 0)]);
+    assert_close(&values, &[10.0]);
+  }
+
+  #[test]
+  fn opening_range_freezes_after_its_window() {
+    let candles = [
+      flat(REGULAR_OPEN - 3600, 100.0),
+      candle(REGULAR_OPEN, 11.0, 9.0, 10.0, 100),
+      candle(REGULAR_OPEN + 60, 13.0, 10.0, 12.0, 100),
+      candle(REGULAR_OPEN + 120, 20.0, 1.0, 5.0, 100),
+      flat(REGULAR_OPEN + DAY - 3600, 50.0),
+      flat(REGULAR_OPEN + DAY, 60.0),
+    ];
+    let high = run(&mut OpeningRange::new(2, RangeLine::High), &candles);
+    let low = run(&mut OpeningRange::new(2, RangeLine::Low), &candles);
+    // nan before the open and until the second minute closes, the third candle is outside the window
+    assert_close(&high, &[f64::NAN, f64::NAN, 13.0, 13.0, f64::NAN, f64::NAN]);
+    assert_close(&low, &[f64::NAN, f64::NAN, 9.0, 9.0, f64::NAN, f64::NAN]);
+  }
+
+  #[test]
+  fn prior_day_uses_the_previous_regular_session() {
+    let candles = [
+      flat(REGULAR_OPEN - 3600, 100.0),
+      candle(REGULAR_OPEN, 12.0, 8.0, 10.0, 100),
+      candle(REGULAR_OPEN + 60, 15.0, 9.0, 11.0, 100),
+      // post-market
+      flat(REGULAR_OPEN + 7 * 3600, 200.0),
+      flat(REGULAR_OPEN + DAY - 3600, 50.0),
+      flat(REGULAR_OPEN + DAY, 60.0),
+    ];
+    let high = run(&mut PriorDay::new(PriorDayLine::High), &candles);
+    let low = run(&mut PriorDay::new(PriorDayLine::Low), &candles);
+    let close = run(&mut PriorDay::new(PriorDayLine::Close), &candles);
+    let nan = f64::NAN;
+    assert_close(&high, &[nan, nan, nan, nan, 15.0, 15.0]);
+    assert_close(&low, &[nan, nan, nan, nan, 8.0, 8.0]);
+    assert_close(&close, &[nan, nan, nan, nan, 11.0, 11.0]);
+  }
+
+  #[test]
+  fn pre_market_range_freezes_at_the_open() {
+    let candles = [
+      candle(REGULAR_OPEN - 3600, 11.0, 9.0, 10.0, 100),
+      candle(REGULAR_OPEN - 3540, 12.0, 10.0, 11.0, 100),
+      candle(REGULAR_OPEN, 20.0, 1.0, 5.0, 100),
+      // no pre-market data the next session
+      flat(REGULAR_OPEN + DAY, 60.0),
+    ];
+    let high = run(&mut PreMarketRange::new(RangeLine::High), &candles);
+    let low = run(&mut PreMarketRange::new(RangeLine::Low), &candles);
+    assert_close(&high, &[11.0, 12.0, 12.0, f64::NAN]);
+    assert_close(&low, &[9.0, 9.0, 9.0, f64::NAN]);
+  }
+}
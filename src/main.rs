@@ -1,20 +1,63 @@
-use std::{collections::HashMap, fs::File, time::Instant};
+mod candle_cache;
+mod candle_series;
+mod checkpoint;
+mod corporate_actions;
+mod experiment;
+mod fnv;
+mod genetic;
+mod heatmap;
+mod indicators;
+mod monte_carlo;
+mod overfitting;
+mod performance;
+mod polygon;
+mod robustness;
+mod rules;
+mod score;
+mod search;
+mod short_selling;
+mod strategy;
+mod ticks;
+mod timeframe;
+mod trade_controls;
+mod trade_path;
+mod trading_hours;
+mod walk_forward;
+
+use std::{
+  collections::{BTreeMap, HashMap},
+  fs::File,
+};
 
 use chrono::{DateTime, Datelike, Duration, TimeZone, Weekday};
 use chrono_tz::{Tz, US};
-use csv::ReaderBuilder;
+use csv::{ReaderBuilder, WriterBuilder};
 use memoize::memoize;
 use ordered_float::OrderedFloat;
-use rand::Rng;
-use rand::rngs::ThreadRng;
-use rust_decimal::prelude::*;
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use rayon::prelude::*;
 use rust_decimal::Decimal;
-use rust_decimal_macros::dec;
-use serde::Deserialize;
-use ta::{indicators::ExponentialMovingAverage, Next};
+use serde::{Deserialize, Serialize};
+
+use candle_series::CandleSeries;
+use checkpoint::Checkpoint;
+use corporate_actions::{CorporateAction, PriceAdjustment};
+use experiment::Experiment;
+use monte_carlo::MonteCarloParameters;
+use overfitting::OverfittingParameters;
+use performance::PerformanceMetrics;
+use score::Score;
+use search::{Search, SearchSpace};
+use short_selling::{HardToBorrow, ShortSelling};
+use strategy::Strategy;
+use trade_controls::{Pyramiding, TradeControls};
+use trade_path::TradePath;
+use trading_hours::{Blackout, TradingHours};
+use walk_forward::WalkForwardParameters;
 
 #[derive(PartialEq, Debug, Clone)]
-enum Direction {
+pub enum Direction {
   Long,
   Short,
   Flat,
@@ -33,15 +76,23 @@ enum Action {
   Close,
   OpenNew,
   SwitchDirection,
+  // another unit in the direction already held
+  Add,
 }
 
 struct Signal {
   pub grouping_key: i64,
   pub timestamp: i64,
   pub direction: Direction,
+  // the strategy's strength behind its direction, positive for long
+  pub score: Option<f64>,
+  // flat whatever anything wants: outside the regular session, on its last candle, from the forced exit on and during
+  // blackouts. the minimum hold never delays these exits
+  pub is_forced_flat: bool,
 }
 
-#[derive(Debug, Copy, Clone, Deserialize)]
+#[allow(dead_code)]
+#[derive(Debug, Copy, Clone, Deserialize, Serialize)]
 struct Candle {
   pub start_timestamp: i64,
   pub end_timestamp: i64,
@@ -55,6 +106,7 @@ struct Candle {
 #[derive(PartialEq)]
 enum TradeType {
   Open,
+  Add,
   Close,
 }
 
@@ -63,6 +115,8 @@ struct Trade {
   pub timestamp: i64,
   pub r#type: TradeType,
   pub direction: Direction,
+  // fraction of a unit entered, 1 for whole units (and closes)
+  pub size: f64,
 }
 
 #[derive(Debug)]
@@ -78,11 +132,14 @@ enum TradeExitReason {
   Close,
 }
 
+#[allow(dead_code)]
 struct TradeBacktestResult {
   grouping_key: i64,
   open_timestamp: i64,
   exit_timestamp: i64,
   close_timestamp: i64,
+  // units held at the exit and their average cost
+  num_units: usize,
   open_price: f64,
   close_price: f64,
   profit_limit_price: f64,
@@ -95,10 +152,11 @@ struct TradeBacktestResult {
   exit_type: TradeExitType,
 }
 
-
 #[derive(Debug, Clone)]
 struct BacktestParameters {
   slippage_percentage: f64,
+  // annualized, shorts pay it pro rata for the calendar time they are held
+  short_borrow_fee_percentage: f64,
   profit_limit_percentage: f64,
   stop_loss_percentage: f64,
 }
@@ -106,8 +164,8 @@ struct BacktestParameters {
 #[derive(Debug, Clone)]
 struct SignalParameters {
   warmup_periods: usize,
-  fast_periods: usize,
-  slow_periods: usize,
+  // the strategy's and the score's own parameters by name
+  values: BTreeMap<String, f64>,
 }
 
 fn build_decimal_range(min: Decimal, max: Decimal, step: Decimal) -> Vec<Decimal> {
@@ -120,16 +178,6 @@ fn build_decimal_range(min: Decimal, max: Decimal, step: Decimal) -> Vec<Decimal
   return results;
 }
 
-fn build_usize_range(min: usize, max: usize, step: usize) -> Vec<usize> {
-  let mut pointer = min;
-  let mut results = vec![];
-  while pointer <= max {
-    results.push(pointer);
-    pointer += step;
-  }
-  return results;
-}
-
 fn read_records_from_csv<T>(filename: &str) -> Vec<T>
 where
   T: for<'de> Deserialize<'de>,
@@ -144,6 +192,18 @@ where
   return candles;
 }
 
+fn write_records_to_csv<T>(filename: &str, records: &[T])
+where
+  T: Serialize,
+{
+  let file = File::create(filename).unwrap();
+  let mut csv_writer = WriterBuilder::new().has_headers(true).from_writer(file);
+  for record in records {
+    csv_writer.serialize(record).unwrap();
+  }
+  csv_writer.flush().unwrap();
+}
+
 #[memoize]
 fn datetime_from_timestamp(timestamp: i64) -> DateTime<Tz> {
   let naive = chrono::NaiveDateTime::from_timestamp_opt(timestamp, 0).unwrap();
@@ -274,6 +334,20 @@ fn calculate_stop_loss_price(direction: &Direction, open_price: f64, stop_loss_p
   }
 }
 
+// weighted by size, unless nothing was entered
+fn calculate_average_cost(entries: &[Trade], entry_prices: &[f64]) -> f64 {
+  let total_size: f64 = entries.iter().map(|entry| entry.size).sum();
+  if total_size == 0.0 {
+    return entry_prices.iter().sum::<f64>() / entry_prices.len() as f64;
+  }
+  return entries
+    .iter()
+    .zip(entry_prices)
+    .map(|(entry, entry_price)| entry.size * entry_price)
+    .sum::<f64>()
+    / total_size;
+}
+
 fn calculate_profit_loss(direction: &Direction, open_price: f64, exit_price: f64) -> f64 {
   if *direction == Direction::Long {
     return exit_price - open_price;
@@ -282,75 +356,100 @@ fn calculate_profit_loss(direction: &Direction, open_price: f64, exit_price: f64
   }
 }
 
+// a position is its entries (the open, then any adds) and its close. every entry is one unit of its size with its own
+// fill price, profit limit and stop loss follow the average cost of the units held so far and exit all of them at once
 fn backtest_trade(
-  trade_open: &Trade,
+  entries: &[Trade],
   trade_close: &Trade,
-  candles_map: &HashMap<i64, &Candle>,
+  candles: &CandleSeries,
+  trade_paths: &[TradePath],
   backtest_parameters: &BacktestParameters,
-  candle_size_seconds: i64,
 ) -> TradeBacktestResult {
   let slippage_percentage = backtest_parameters.slippage_percentage;
   let profit_limit_percentage = backtest_parameters.profit_limit_percentage;
   let stop_loss_percentage = backtest_parameters.stop_loss_percentage;
+  let trade_open = &entries[0];
+  let direction = &trade_open.direction;
   // get candles
-  let open_candle = candles_map.get(&trade_open.timestamp).unwrap();
-  let close_candle = candles_map.get(&trade_close.timestamp).unwrap();
+  let close_candle = candles.get(trade_close.timestamp).unwrap();
   // estimate open/close fill prices
-  let open_price = calculate_open_price(&open_candle, &trade_open.direction, slippage_percentage);
-  let close_price = calculate_close_price(&close_candle, &trade_open.direction, slippage_percentage);
-  // estimate profit limit/stop loss prices
-  let profit_limit_price = calculate_profit_limit_price(&trade_open.direction, open_price, profit_limit_percentage);
-  let stop_loss_price = calculate_stop_loss_price(&trade_open.direction, open_price, stop_loss_percentage);
-  // determine trade exit
-  let determine_trade_exit = || {
-    let mut pointer = trade_open.timestamp;
-    while pointer < trade_close.timestamp { // do not include trade_close candle on purpose as to not introduce lookahead bias
-      let candle = candles_map.get(&pointer).unwrap();
-      // check for stop loss
-      if trade_open.direction == Direction::Long {
-        let worst_case_scenario = candle.low;
-        if worst_case_scenario <= stop_loss_price {
-          return (TradeExitReason::StopLoss, stop_loss_price, candle);
-        }
-      } else {
-        let worst_case_scenario = candle.high;
-        if worst_case_scenario >= stop_loss_price {
-          return (TradeExitReason::StopLoss, stop_loss_price, candle);
-        }
-      }
-      // check for profit limit
-      if trade_open.direction == Direction::Long {
-        let best_case_scenario = candle.high;
-        if best_case_scenario >= profit_limit_price {
-          return (TradeExitReason::ProfitLimit, profit_limit_price, candle);
-        }
-      } else {
-        let best_case_scenario = candle.low;
-        if best_case_scenario <= profit_limit_price {
-          return (TradeExitReason::ProfitLimit, profit_limit_price, candle);
-        }
-      }
-      // progress pointer through time
-      pointer += candle_size_seconds;
+  let entry_prices: Vec<f64> = entries
+    .iter()
+    .map(|entry| calculate_open_price(candles.get(entry.timestamp).unwrap(), direction, slippage_percentage))
+    .collect();
+  let close_price = calculate_close_price(close_candle, direction, slippage_percentage);
+  // determine trade exit, segment by segment between entries
+  let mut average_cost = entry_prices[0];
+  let mut profit_limit_price = 0.0;
+  let mut stop_loss_price = 0.0;
+  let mut exit = None;
+  for (segment, trade_path) in trade_paths.iter().enumerate() {
+    // estimate profit limit/stop loss prices
+    average_cost = calculate_average_cost(&entries[..=segment], &entry_prices[..=segment]);
+    profit_limit_price = calculate_profit_limit_price(direction, average_cost, profit_limit_percentage);
+    stop_loss_price = calculate_stop_loss_price(direction, average_cost, stop_loss_percentage);
+    let (stop_loss_index, profit_limit_index) = if *direction == Direction::Long {
+      (
+        trade_path.first_low_at_or_below(stop_loss_price),
+        trade_path.first_high_at_or_above(profit_limit_price),
+      )
+    } else {
+      (
+        trade_path.first_high_at_or_above(stop_loss_price),
+        trade_path.first_low_at_or_below(profit_limit_price),
+      )
+    };
+    // stop loss wins when both are touched within the same candle
+    exit = match (stop_loss_index, profit_limit_index) {
+      (Some(stop_loss_index), Some(profit_limit_index)) if profit_limit_index < stop_loss_index => Some((
+        TradeExitReason::ProfitLimit,
+        profit_limit_price,
+        trade_path.candle(profit_limit_index),
+        segment + 1,
+      )),
+      (Some(stop_loss_index), _) => Some((TradeExitReason::StopLoss, stop_loss_price, trade_path.candle(stop_loss_index), segment + 1)),
+      (None, Some(profit_limit_index)) => Some((
+        TradeExitReason::ProfitLimit,
+        profit_limit_price,
+        trade_path.candle(profit_limit_index),
+        segment + 1,
+      )),
+      (None, None) => None,
+    };
+    if exit.is_some() {
+      break;
     }
-    // asume we close right at the open of the next candle due to direction change
-    return (TradeExitReason::Close, close_price, close_candle);
-  };
-  let (exit_reason, exit_price, exit_candle) = determine_trade_exit();
-  let profit_loss = calculate_profit_loss(&trade_open.direction, open_price, exit_price);
-  let profit_loss_percentage = profit_loss / open_price;
+  }
+  // asume we close right at the open of the next candle due to direction change
+  let (exit_reason, exit_price, exit_candle, num_units) = exit.unwrap_or((TradeExitReason::Close, close_price, close_candle, entries.len()));
+  // every unit counts as its size of one position's worth of capital, shorts pay the annualized borrow fee pro rata for the calendar
+  // time they are held
+  let mut profit_loss = 0.0;
+  let mut profit_loss_percentage = 0.0;
+  for (entry, entry_price) in entries[..num_units].iter().zip(&entry_prices) {
+    let borrow_fee = if *direction == Direction::Short {
+      let holding_seconds = exit_candle.start_timestamp - entry.timestamp;
+      entry_price * backtest_parameters.short_borrow_fee_percentage * holding_seconds as f64 / (365.0 * 24.0 * 60.0 * 60.0)
+    } else {
+      0.0
+    };
+    let unit_profit_loss = calculate_profit_loss(direction, *entry_price, exit_price) - borrow_fee;
+    profit_loss += entry.size * unit_profit_loss;
+    profit_loss_percentage += entry.size * unit_profit_loss / entry_price;
+  }
   let exit_type = if profit_loss > 0.0 { TradeExitType::Win } else { TradeExitType::Loss };
   return TradeBacktestResult {
     grouping_key: trade_open.grouping_key,
     open_timestamp: trade_open.timestamp,
     exit_timestamp: exit_candle.start_timestamp,
     close_timestamp: trade_close.timestamp,
-    open_price,
+    num_units,
+    open_price: average_cost,
     close_price,
     profit_limit_price,
     stop_loss_price,
     exit_reason,
-    exit_candle: *exit_candle.clone(),
+    exit_candle: *exit_candle,
     exit_price,
     profit_loss,
     profit_loss_percentage,
@@ -358,71 +457,61 @@ fn backtest_trade(
   };
 }
 
-fn build_signals(candles: &Vec<Candle>, candles_map: &HashMap<i64, &Candle>, signal_parameters: &SignalParameters, candle_size_seconds: i64, rng: &mut ThreadRng) -> Vec<Signal> {
-  let warmup_periods = signal_parameters.warmup_periods;
-  let fast_periods = signal_parameters.fast_periods;
-  let slow_periods = signal_parameters.slow_periods;
-  // build indicators
-  let mut fast = ExponentialMovingAverage::new(fast_periods).unwrap();
-  let mut slow = ExponentialMovingAverage::new(slow_periods).unwrap();
-  let mut last_fast = 0.0;
-  let mut last_slow = 0.0;
+// walks the candles and asks the strategy for a direction at every candle open, the strategy only ever sees fully closed
+// candles plus the open of the current one. its direction is replaced by score thresholds when there are any, flat outside
+// the regular session, before warmup and on its last candle, then restricted to the trading hours
+fn build_signals(
+  candles: &CandleSeries,
+  strategy: &mut dyn Strategy,
+  warmup_periods: usize,
+  score: &Score,
+  trading_hours: &TradingHours,
+  rng: &mut StdRng,
+) -> Vec<Signal> {
+  let candle_size_seconds = candles.candle_size_seconds();
   let mut num_periods = 0;
   // traverse time
-  let parsed_start = datetime_from_timestamp(candles[0].start_timestamp);
-  let parsed_end = datetime_from_timestamp(candles[candles.len() - 1].end_timestamp);
+  let parsed_start = datetime_from_timestamp(candles.candles()[0].start_timestamp);
+  let parsed_end = datetime_from_timestamp(candles.candles()[candles.candles().len() - 1].end_timestamp);
   let mut pointer = parsed_start;
   let mut signals = vec![];
   while pointer <= parsed_end {
     let current_session_type = determine_session_type(pointer.timestamp());
     // skip when market is not open
     if current_session_type == MarketSessionType::None {
-      pointer = pointer + Duration::seconds(candle_size_seconds);
+      pointer += Duration::seconds(candle_size_seconds);
       continue;
     }
     // TODO: prediction/estimation so that we aren't always late to trades?
     // get previous fully closed candle (alway look back 1 candle to prevent lookahead bias)
     let massaged_timestamp = pointer.timestamp() - candle_size_seconds;
-    let previous_candle = candles_map.get(&massaged_timestamp);
+    let previous_candle = candles.get(massaged_timestamp);
     if previous_candle.is_none() {
       if current_session_type == MarketSessionType::Regular {
         panic!("no candle for {pointer} {massaged_timestamp}?");
       }
       // skip missing pre/post market candles
-      pointer = pointer + Duration::seconds(candle_size_seconds);
+      pointer += Duration::seconds(candle_size_seconds);
       continue;
     }
     let previous_candle = previous_candle.unwrap();
-    // feed to indicators
-    last_fast = fast.next(previous_candle.close);
-    last_slow = slow.next(previous_candle.close);
+    // feed to strategy
+    strategy.on_closed_candle(previous_candle);
     // get only open price from current candle to prevent lookahead bias
-    let current_candle = candles_map.get(&pointer.timestamp());
+    let current_candle = candles.get(pointer.timestamp());
     if current_candle.is_none() {
       if current_session_type == MarketSessionType::Regular {
         panic!("no candle for {pointer} {massaged_timestamp}?");
       }
       // skip missing pre/post market candles
-      pointer = pointer + Duration::seconds(candle_size_seconds);
+      pointer += Duration::seconds(candle_size_seconds);
       continue;
     }
     let current_candle = current_candle.unwrap();
-    // feed to indicators (pretend we can accurately predict close)
-    let accuracy = 0.45;
-    let predicted_close_correctly = rng.gen_bool(accuracy) == true;
-    if predicted_close_correctly == true {
-      last_fast = fast.next(current_candle.close);
-      last_slow = slow.next(current_candle.close);
-    } else {
-      last_fast = fast.next(current_candle.open);
-      last_slow = slow.next(current_candle.open);
-    }
-    // calculate indicator direction
-    let indicator_direction = if last_fast > last_slow {
-      Direction::Long
-    } else {
-      Direction::Short
-    };
+    let indicator_direction = strategy.direction(pointer.timestamp(), current_candle, rng);
+    let indicator_score = strategy.score();
+    let previous_direction = signals.last().map(|signal: &Signal| signal.direction.clone()).unwrap_or(Direction::Flat);
+    let indicator_direction = score.direction(indicator_score, &previous_direction, indicator_direction);
     num_periods += 1;
     // calculate warmup
     let is_warmed_up = num_periods >= warmup_periods;
@@ -432,30 +521,47 @@ fn build_signals(candles: &Vec<Candle>, candles_map: &HashMap<i64, &Candle>, sig
     let (regular_session_start, regular_session_end) = get_regular_market_session_start_and_end(pointer.timestamp());
     let distance_to_regular_session_end = regular_session_end.timestamp() - pointer.timestamp();
     let is_last_candle_of_regular_session = current_session_type == MarketSessionType::Regular && distance_to_regular_session_end <= (candle_size_seconds - 1);
-    let should_be_flat = is_warmed_up == false || is_pre_market || is_post_market || is_last_candle_of_regular_session;
-    let direction = if should_be_flat {
-      Direction::Flat
-    } else {
-      indicator_direction
-    };
+    let is_forced_flat = is_pre_market || is_post_market || is_last_candle_of_regular_session || trading_hours.is_forced_flat(pointer.timestamp());
+    let should_be_flat = is_warmed_up == false || is_forced_flat;
+    let direction = if should_be_flat { Direction::Flat } else { indicator_direction };
+    // entry windows, forced exit and blackouts
+    let direction = trading_hours.apply(pointer.timestamp(), &previous_direction, direction);
     // push
     signals.push(Signal {
       grouping_key: regular_session_start.timestamp(),
       timestamp: pointer.timestamp(), // use pointer timestamp, not use candle timestamp to prevent lookahead bias
       direction,
+      score: indicator_score,
+      is_forced_flat,
     });
     // increment
-    pointer = pointer + Duration::seconds(candle_size_seconds);
+    pointer += Duration::seconds(candle_size_seconds);
   }
   return signals;
 }
 
-fn build_trades(signals: &Vec<Signal>) -> Vec<Trade> {
+fn build_trades(
+  signals: &[Signal],
+  score: &Score,
+  short_selling: &ShortSelling,
+  trade_controls: &TradeControls,
+  pyramiding: Option<&Pyramiding>,
+  fill_candles: &CandleSeries,
+) -> Vec<Trade> {
   let mut trades = vec![];
   let mut last_direction = Direction::Flat;
-  for signal in signals {
-    let signal_direction = &signal.direction;
-    let action = match (&last_direction, signal_direction) {
+  let mut num_units = 0;
+  let mut last_entry_index = 0;
+  let mut last_entry_score = None;
+  // direction policy and hard to borrow days
+  let wanted_directions: Vec<Direction> = signals
+    .iter()
+    .map(|signal| short_selling.allow(signal.grouping_key, signal.direction.clone()))
+    .collect();
+  // cooldown, minimum hold, daily trade cap and loss limit
+  let directions = trade_controls.directions(signals, &wanted_directions, fill_candles);
+  for (index, (signal, signal_direction)) in signals.iter().zip(&directions).enumerate() {
+    let mut action = match (&last_direction, signal_direction) {
       // stay in (no change)
       (Direction::Short, Direction::Short) => Action::NoChange,
       (Direction::Long, Direction::Long) => Action::NoChange,
@@ -470,13 +576,26 @@ fn build_trades(signals: &Vec<Signal>) -> Vec<Trade> {
       (Direction::Short, Direction::Long) => Action::SwitchDirection,
       (Direction::Long, Direction::Short) => Action::SwitchDirection,
     };
+    // add to a position the signal keeps asking for
+    if let (Action::NoChange, Some(pyramiding)) = (&action, pyramiding) {
+      let is_persisting = last_direction != Direction::Flat && wanted_directions[index] == last_direction;
+      if is_persisting && pyramiding.should_add(num_units, index - last_entry_index, &last_direction, signal.score, last_entry_score) {
+        action = Action::Add;
+      }
+    }
+    if matches!(action, Action::OpenNew | Action::SwitchDirection | Action::Add) {
+      last_entry_score = signal.score;
+    }
     match action {
       Action::OpenNew => {
+        num_units = 1;
+        last_entry_index = index;
         trades.push(Trade {
           grouping_key: signal.grouping_key,
           timestamp: signal.timestamp,
           r#type: TradeType::Open,
-          direction: signal.direction.clone(),
+          direction: signal_direction.clone(),
+          size: score.size(signal.score),
         });
       }
       Action::NoChange => {}
@@ -486,138 +605,923 @@ fn build_trades(signals: &Vec<Signal>) -> Vec<Trade> {
           timestamp: signal.timestamp,
           r#type: TradeType::Close,
           direction: last_direction,
+          size: 1.0,
         });
       }
       Action::SwitchDirection => {
+        num_units = 1;
+        last_entry_index = index;
         trades.push(Trade {
           grouping_key: signal.grouping_key,
           timestamp: signal.timestamp,
           r#type: TradeType::Close,
           direction: last_direction,
+          size: 1.0,
         });
         trades.push(Trade {
           grouping_key: signal.grouping_key,
           timestamp: signal.timestamp,
           r#type: TradeType::Open,
-          direction: signal.direction.clone(),
+          direction: signal_direction.clone(),
+          size: score.size(signal.score),
+        });
+      }
+      Action::Add => {
+        num_units += 1;
+        last_entry_index = index;
+        trades.push(Trade {
+          grouping_key: signal.grouping_key,
+          timestamp: signal.timestamp,
+          r#type: TradeType::Add,
+          direction: signal_direction.clone(),
+          size: score.size(signal.score),
         });
       }
     }
-    last_direction = signal.direction.clone();
+    last_direction = signal_direction.clone();
   }
   return trades;
 }
 
-fn build_backtest_parameter_combinations() -> Vec<BacktestParameters> {
-  let mut backtest_parameter_combinations = vec![];
-  let min = dec!(0.002);
-  let max = dec!(0.01);
-  let step = dec!(0.0005);
-  let profit_limit_percentages = build_decimal_range(min, max, step);
-  let min = dec!(-0.01);
-  let max = dec!(-0.002);
-  let step = dec!(0.0005);
-  let stop_loss_percentages = build_decimal_range(min, max, step);
-  for profit_limit_percentage in &profit_limit_percentages {
-    for stop_loss_percentage in &stop_loss_percentages {
-      let backtest_parameters = BacktestParameters {
-        slippage_percentage: 0.000125,
-        profit_limit_percentage: profit_limit_percentage.to_f64().unwrap(),
-        stop_loss_percentage: stop_loss_percentage.to_f64().unwrap(),
+// derive a per-combination seed so results do not depend on evaluation order or thread count
+fn signal_parameters_seed(seed: u64, signal_parameters: &SignalParameters) -> u64 {
+  let mut hash = seed ^ 0x9e3779b97f4a7c15;
+  for value in std::iter::once(signal_parameters.warmup_periods as f64).chain(signal_parameters.values.values().copied()) {
+    // integral values hash as the integers they are (negative ones as their two's complement), fractional ones by their bits
+    let value = if value.fract() == 0.0 { value as i64 as u64 } else { value.to_bits() };
+    // splitmix64 step
+    hash = hash.wrapping_add(value).wrapping_add(0x9e3779b97f4a7c15);
+    hash = (hash ^ (hash >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    hash = (hash ^ (hash >> 27)).wrapping_mul(0x94d049bb133111eb);
+    hash ^= hash >> 31;
+  }
+  return hash;
+}
+
+// what a run evaluates against, shared by every combination
+struct EvaluationContext<'a> {
+  experiment: &'a Experiment,
+  candles: &'a CandleSeries,
+  fill_candles: &'a CandleSeries,
+  trading_hours: &'a TradingHours,
+  short_selling: &'a ShortSelling,
+  checkpoint: Option<&'a Checkpoint>,
+}
+
+fn build_signal_trades(context: &EvaluationContext, signal_parameters: &SignalParameters) -> Vec<Trade> {
+  let mut rng = StdRng::seed_from_u64(signal_parameters_seed(context.experiment.seed, signal_parameters));
+  // build signals
+  let experiment = context.experiment;
+  let mut strategy = experiment.strategy.build(&signal_parameters.values);
+  let score = match &experiment.score {
+    Some(score_parameters) => score_parameters.build(&signal_parameters.values),
+    None => Score::default(),
+  };
+  let signals = build_signals(
+    context.candles,
+    strategy.as_mut(),
+    signal_parameters.warmup_periods,
+    &score,
+    context.trading_hours,
+    &mut rng,
+  );
+  // build trades from signals
+  return build_trades(
+    &signals,
+    &score,
+    context.short_selling,
+    &experiment.trade_controls,
+    experiment.pyramiding.as_ref(),
+    context.fill_candles,
+  );
+}
+
+type Evaluation = (SignalParameters, BacktestParameters, PerformanceMetrics);
+
+// every position as its entries (open + adds) and its close
+fn trade_positions(trades: &[Trade]) -> impl Iterator<Item = (&[Trade], &Trade)> {
+  return trades.split_inclusive(|trade| trade.r#type == TradeType::Close).map(|chunk| {
+    let (trade_close, entries) = chunk.split_last().unwrap();
+    assert!(entries[0].r#type == TradeType::Open);
+    assert!(entries[1..].iter().all(|entry| entry.r#type == TradeType::Add));
+    assert!(trade_close.r#type == TradeType::Close);
+    assert!(entries.iter().all(|entry| entry.direction == trade_close.direction));
+    assert!(entries.windows(2).all(|pair| pair[0].timestamp < pair[1].timestamp));
+    assert!(entries[entries.len() - 1].timestamp < trade_close.timestamp);
+    return (entries, trade_close);
+  });
+}
+
+// one path per segment between consecutive entries and the close
+fn build_trade_paths<'a>(entries: &[Trade], trade_close: &Trade, fill_candles: &'a CandleSeries) -> Vec<TradePath<'a>> {
+  let ends = entries[1..].iter().chain([trade_close]);
+  return entries
+    .iter()
+    .zip(ends)
+    // do not include the end candle on purpose as to not introduce lookahead bias
+    .map(|(start, end)| TradePath::new(fill_candles.range(start.timestamp, end.timestamp)))
+    .collect();
+}
+
+// performance of every backtest parameter combination (same order) over the trades of one set of signal parameters
+fn backtest_trades(trades: &[Trade], fill_candles: &CandleSeries, backtest_parameter_combinations: &[BacktestParameters]) -> Vec<PerformanceMetrics> {
+  let mut performances = vec![PerformanceMetrics::default(); backtest_parameter_combinations.len()];
+  for (entries, trade_close) in trade_positions(trades) {
+    let trade_paths = build_trade_paths(entries, trade_close, fill_candles);
+    // loop backtest parameter combinations
+    for (index, backtest_parameters) in backtest_parameter_combinations.iter().enumerate() {
+      let backtest_result = backtest_trade(entries, trade_close, fill_candles, &trade_paths, backtest_parameters);
+      performances[index].record(backtest_result.grouping_key, backtest_result.profit_loss_percentage);
+    }
+  }
+  return performances;
+}
+
+// every trade of a single combination, in the order they were closed
+fn backtest_trade_results(trades: &[Trade], fill_candles: &CandleSeries, backtest_parameters: &BacktestParameters) -> Vec<TradeBacktestResult> {
+  return trade_positions(trades)
+    .map(|(entries, trade_close)| {
+      let trade_paths = build_trade_paths(entries, trade_close, fill_candles);
+      return backtest_trade(entries, trade_close, fill_candles, &trade_paths, backtest_parameters);
+    })
+    .collect();
+}
+
+// every combination of every batch, batches run in parallel and share their trades across backtest parameter combinations.
+// combinations found in the checkpoint are not evaluated again, new results are appended to it as each batch finishes
+fn evaluate_batches(context: &EvaluationContext, batches: Vec<(SignalParameters, Vec<BacktestParameters>)>) -> Vec<Evaluation> {
+  let fill_candles = context.fill_candles;
+  let performances: Vec<Vec<PerformanceMetrics>> = batches
+    .par_iter()
+    .map(|(signal_parameters, backtest_parameter_combinations)| {
+      let checkpoint = match context.checkpoint {
+        Some(checkpoint) => checkpoint,
+        None => {
+          let trades = build_signal_trades(context, signal_parameters);
+          return backtest_trades(&trades, fill_candles, backtest_parameter_combinations);
+        }
       };
-      backtest_parameter_combinations.push(backtest_parameters);
+      let keys: Vec<u64> = backtest_parameter_combinations
+        .iter()
+        .map(|backtest_parameters| checkpoint.key(&experiment::parameter_values(signal_parameters, backtest_parameters)))
+        .collect();
+      let missing: Vec<BacktestParameters> = backtest_parameter_combinations
+        .iter()
+        .zip(&keys)
+        .filter(|(_, key)| checkpoint.get(**key).is_none())
+        .map(|(backtest_parameters, _)| backtest_parameters.clone())
+        .collect();
+      let mut missing_performances = vec![];
+      if missing.is_empty() == false {
+        let trades = build_signal_trades(context, signal_parameters);
+        missing_performances = backtest_trades(&trades, fill_candles, &missing);
+        let parameter_values: Vec<Vec<(String, f64)>> = missing
+          .iter()
+          .map(|backtest_parameters| experiment::parameter_values(signal_parameters, backtest_parameters))
+          .collect();
+        checkpoint.append(&parameter_values, &missing_performances);
+      }
+      let mut missing_performances = missing_performances.into_iter();
+      return keys
+        .iter()
+        .map(|key| match checkpoint.get(*key) {
+          Some(performance) => performance.clone(),
+          None => missing_performances.next().unwrap(),
+        })
+        .collect();
+    })
+    .collect();
+  let mut evaluations = vec![];
+  for ((signal_parameters, backtest_parameter_combinations), signal_performances) in batches.into_iter().zip(performances) {
+    for (backtest_parameters, performance) in backtest_parameter_combinations.into_iter().zip(signal_performances) {
+      evaluations.push((signal_parameters.clone(), backtest_parameters, performance));
     }
   }
-  return backtest_parameter_combinations;
-}
-
-fn build_signal_parameter_combinations() -> Vec<SignalParameters> {
-  let mut signal_parameter_combinations = vec![];
-  let min = 10;
-  let max = 50;
-  let step = 5;
-  let fast_periods = build_usize_range(min, max, step);
-  let min = 20;
-  let max = 100;
-  let step = 10;
-  let slow_periods = build_usize_range(min, max, step);
-  for slow_periods in &slow_periods {
-    for fast_periods in &fast_periods {
-      // skip where fast is greater than or equal to slow?
-      if fast_periods >= slow_periods {
-        continue;
+  return evaluations;
+}
+
+// sampled points, in the same order, grouped by their signal parameters so trades are only built once per group
+fn evaluate_points(context: &EvaluationContext, space: &SearchSpace, points: &[search::Point]) -> Vec<Evaluation> {
+  let mut grouped_points: BTreeMap<&[usize], Vec<usize>> = BTreeMap::new();
+  for (index, point) in points.iter().enumerate() {
+    grouped_points.entry(&point[0..space.num_signal_parameters]).or_default().push(index);
+  }
+  let mut batches = vec![];
+  let mut order = vec![];
+  for indices in grouped_points.into_values() {
+    let signal_parameters = context.experiment.signal_parameters_from_values(&space.point_values(&points[indices[0]]));
+    let backtest_parameter_combinations = indices
+      .iter()
+      .map(|index| experiment::backtest_parameters_from_values(&space.point_values(&points[*index])))
+      .collect();
+    batches.push((signal_parameters, backtest_parameter_combinations));
+    order.extend(indices);
+  }
+  let mut evaluations: Vec<Option<Evaluation>> = vec![None; points.len()];
+  for (index, evaluation) in order.into_iter().zip(evaluate_batches(context, batches)) {
+    evaluations[index] = Some(evaluation);
+  }
+  return evaluations.into_iter().map(|evaluation| evaluation.unwrap()).collect();
+}
+
+// returns[combination][session] of summed profit_loss_percentage, zero on sessions without trades
+fn build_session_returns_matrix(sessions: &[i64], evaluations: &[Evaluation]) -> Vec<Vec<f64>> {
+  let session_indices: HashMap<i64, usize> = sessions.iter().enumerate().map(|(index, grouping_key)| (*grouping_key, index)).collect();
+  return evaluations
+    .iter()
+    .map(|(_, _, performance)| {
+      let mut returns = vec![0.0; sessions.len()];
+      for (grouping_key, session_return) in &performance.session_returns {
+        returns[session_indices[grouping_key]] += session_return;
       }
-      let backtest_context = SignalParameters {
-        warmup_periods: 1,
-        fast_periods: *fast_periods,
-        slow_periods: *slow_periods,
-      };
-      signal_parameter_combinations.push(backtest_context);
+      return returns;
+    })
+    .collect();
+}
+
+fn report_walk_forward(experiment: &Experiment, walk_forward_parameters: &WalkForwardParameters, sessions: &[i64], evaluations: &[Evaluation]) {
+  let returns = build_session_returns_matrix(sessions, evaluations);
+  let folds = walk_forward::walk_forward(&returns, sessions.len(), walk_forward_parameters, experiment.fitness);
+  let parameter_values: Vec<Vec<(String, f64)>> = evaluations
+    .iter()
+    .map(|(signal_parameters, backtest_parameters, _)| experiment::parameter_values(signal_parameters, backtest_parameters))
+    .collect();
+  let format_session = |index: usize| datetime_from_timestamp(sessions[index]).format("%Y-%m-%d").to_string();
+  // one row per fold
+  let filename = "./output/walk-forward.csv";
+  let mut csv_writer = WriterBuilder::new().from_path(filename).unwrap();
+  let mut header = vec!["train_start", "train_end", "test_start", "test_end"];
+  header.extend(
+    parameter_values
+      .first()
+      .map(|values| values.iter().map(|(name, _)| name.as_str()).collect())
+      .unwrap_or(vec![]),
+  );
+  header.extend(["train_score", "test_return"]);
+  csv_writer.write_record(&header).unwrap();
+  for fold in &folds {
+    let mut record = vec![
+      format_session(fold.train.start),
+      format_session(fold.train.end - 1),
+      format_session(fold.test.start),
+      format_session(fold.test.end - 1),
+    ];
+    record.extend(parameter_values[fold.selected].iter().map(|(_, value)| value.to_string()));
+    record.extend([fold.train_score.to_string(), fold.test_return.to_string()]);
+    csv_writer.write_record(&record).unwrap();
+  }
+  csv_writer.flush().unwrap();
+  // summary
+  let out_of_sample = PerformanceMetrics::from_returns(&walk_forward::stitched_returns(&returns, &folds));
+  let distinct_selections = folds.iter().map(|fold| fold.selected).collect::<std::collections::HashSet<usize>>().len();
+  eprintln!("walk forward: {} folds written to {filename}", folds.len());
+  eprintln!(
+    "walk forward out of sample: sessions {} return {} max drawdown {} sharpe ratio {} win rate {}",
+    out_of_sample.num_trades,
+    out_of_sample.profit_loss_percentage,
+    out_of_sample.max_drawdown,
+    out_of_sample.sharpe_ratio(),
+    out_of_sample.win_rate()
+  );
+  eprintln!("walk forward parameter stability: {distinct_selections} distinct selections");
+  for (name, mean, standard_deviation) in walk_forward::parameter_stability(&parameter_values, &folds) {
+    eprintln!("  {name}: mean {mean} std {standard_deviation}");
+  }
+}
+
+// baseline is not trading at all, i.e. zero return every session
+fn report_overfitting(experiment: &Experiment, overfitting_parameters: &OverfittingParameters, sessions: &[i64], evaluations: &[Evaluation]) {
+  let returns = build_session_returns_matrix(sessions, evaluations);
+  if returns.len() < 2 || sessions.len() < 3 {
+    eprintln!("overfitting: needs at least 2 combinations and 3 sessions");
+    return;
+  }
+  let sharpe_ratios: Vec<f64> = returns.iter().map(|row| overfitting::sharpe_ratio(row)).collect();
+  let (best, best_sharpe) = sharpe_ratios.iter().enumerate().fold(
+    (0, f64::NEG_INFINITY),
+    |acc, (index, sharpe)| if *sharpe > acc.1 { (index, *sharpe) } else { acc },
+  );
+  let mean_sharpe = overfitting::mean(&sharpe_ratios);
+  let sharpe_variance = sharpe_ratios.iter().map(|sharpe| (sharpe - mean_sharpe).powi(2)).sum::<f64>() / (sharpe_ratios.len() - 1) as f64;
+  let pbo = overfitting::probability_of_backtest_overfitting(&returns, overfitting_parameters.partitions);
+  let deflated_sharpe = overfitting::deflated_sharpe_ratio(&returns[best], returns.len(), sharpe_variance);
+  let baseline = vec![0.0; sessions.len()];
+  let reality_check = overfitting::reality_check(&returns, &baseline, overfitting_parameters, experiment.seed);
+  let (signal_parameters, backtest_parameters, _) = &evaluations[best];
+  eprintln!("overfitting: {} combinations over {} sessions", returns.len(), sessions.len());
+  eprintln!(
+    "  best per session sharpe ratio {best_sharpe} at {:?}",
+    experiment::parameter_values(signal_parameters, backtest_parameters)
+  );
+  eprintln!("  probability of backtest overfitting {pbo} ({} partitions)", overfitting_parameters.partitions);
+  eprintln!("  deflated sharpe ratio {deflated_sharpe}");
+  eprintln!(
+    "  reality check p-value {} spa p-value {} ({} bootstrap samples)",
+    reality_check.white_p_value, reality_check.hansen_spa_p_value, overfitting_parameters.bootstrap_samples
+  );
+}
+
+fn report_monte_carlo(context: &EvaluationContext, monte_carlo_parameters: &MonteCarloParameters, evaluations: &[Evaluation]) {
+  let experiment = context.experiment;
+  let (signal_parameters, backtest_parameters) = match &monte_carlo_parameters.parameters {
+    Some(values) => (
+      experiment.signal_parameters_from_values(values),
+      experiment::backtest_parameters_from_values(values),
+    ),
+    None => match evaluations
+      .iter()
+      .max_by(|a, b| experiment.fitness.score(&a.2).total_cmp(&experiment.fitness.score(&b.2)))
+    {
+      Some((signal_parameters, backtest_parameters, _)) => (signal_parameters.clone(), backtest_parameters.clone()),
+      None => return,
+    },
+  };
+  let trades = build_signal_trades(context, &signal_parameters);
+  let results = backtest_trade_results(&trades, context.fill_candles, &backtest_parameters);
+  eprintln!(
+    "monte carlo: {} trades of {:?}, position size {} ruin at {} drawdown",
+    results.len(),
+    experiment::parameter_values(&signal_parameters, &backtest_parameters),
+    monte_carlo_parameters.position_size,
+    monte_carlo_parameters.ruin_drawdown
+  );
+  if results.is_empty() {
+    return;
+  }
+  let mut rng = StdRng::seed_from_u64(experiment.seed);
+  // one row per simulation
+  let filename = "./output/monte-carlo.csv";
+  let mut csv_writer = WriterBuilder::new().from_path(filename).unwrap();
+  csv_writer
+    .write_record(["resampling", "simulation", "final_return", "max_drawdown", "longest_losing_streak", "ruined"])
+    .unwrap();
+  for resampling in monte_carlo::RESAMPLINGS {
+    let simulations = monte_carlo::monte_carlo(&results, resampling, monte_carlo_parameters, &mut rng);
+    for (index, simulation) in simulations.iter().enumerate() {
+      csv_writer
+        .write_record([
+          resampling.name().to_string(),
+          index.to_string(),
+          simulation.final_return.to_string(),
+          simulation.max_drawdown.to_string(),
+          simulation.longest_losing_streak.to_string(),
+          simulation.ruined.to_string(),
+        ])
+        .unwrap();
+    }
+    // summary
+    let confidence = monte_carlo_parameters.confidence;
+    let final_returns: Vec<f64> = simulations.iter().map(|simulation| simulation.final_return).collect();
+    let max_drawdowns: Vec<f64> = simulations.iter().map(|simulation| simulation.max_drawdown).collect();
+    let losing_streaks: Vec<f64> = simulations.iter().map(|simulation| simulation.longest_losing_streak as f64).collect();
+    eprintln!("  {} ({} simulations):", resampling.name(), simulations.len());
+    for (name, values) in [
+      ("final return", final_returns),
+      ("max drawdown", max_drawdowns),
+      ("longest losing streak", losing_streaks),
+    ] {
+      let distribution = monte_carlo::distribution(&values, confidence);
+      eprintln!(
+        "    {name}: mean {} median {} {}% interval [{}, {}]",
+        distribution.mean,
+        distribution.median,
+        confidence * 100.0,
+        distribution.lower,
+        distribution.upper
+      );
     }
+    eprintln!("    risk of ruin: {}", monte_carlo::risk_of_ruin(&simulations));
   }
-  return signal_parameter_combinations;
+  csv_writer.flush().unwrap();
+  eprintln!("monte carlo simulations written to {filename}");
+}
+
+// one svg per configured heatmap, cells summarize every combination sharing their two axis values
+fn report_heatmaps(experiment: &Experiment, space: &SearchSpace, points: &[search::Point], evaluations: &[Evaluation], robustness: &[f64]) {
+  for heatmap_parameters in &experiment.heatmaps {
+    let x_dimension = space.names.iter().position(|name| *name == heatmap_parameters.x).unwrap();
+    let y_dimension = space.names.iter().position(|name| *name == heatmap_parameters.y).unwrap();
+    let entries: Vec<(usize, usize, f64)> = points
+      .iter()
+      .zip(evaluations.iter().zip(robustness))
+      .map(|(point, ((_, _, performance), robustness))| {
+        (
+          point[x_dimension],
+          point[y_dimension],
+          heatmap_parameters.metric.value(performance, *robustness),
+        )
+      })
+      .collect();
+    let x_values = &space.values[x_dimension];
+    let y_values = &space.values[y_dimension];
+    let cells = heatmap::build_cells(x_values.len(), y_values.len(), &entries, heatmap_parameters.aggregate);
+    let title = format!("{} ({:?})", heatmap_parameters.metric.name(), heatmap_parameters.aggregate).to_lowercase();
+    let svg = heatmap::render_svg(&title, &heatmap_parameters.x, x_values, &heatmap_parameters.y, y_values, &cells);
+    let filename = format!(
+      "./output/heatmap-{}-{}-{}.svg",
+      heatmap_parameters.metric.name(),
+      heatmap_parameters.x,
+      heatmap_parameters.y
+    );
+    std::fs::write(&filename, svg).unwrap();
+    eprintln!("heatmap written to {filename}");
+  }
+}
+
+fn scrape_polygon(args: &[String]) {
+  // usage: scrape_polygon <ticker> <from> <to> [resolution]
+  let ticker = &args[0];
+  let from = &args[1];
+  let to = &args[2];
+  let resolution: i64 = args.get(3).map(|value| value.parse().unwrap()).unwrap_or(1);
+  let config = polygon::PolygonConfig {
+    base_url: std::env::var("POLYGON_BASE_URL").unwrap_or(polygon::DEFAULT_BASE_URL.to_string()),
+    api_key: std::env::var("POLYGON_API_KEY").expect("POLYGON_API_KEY must be set"),
+    cache_directory: Some("./output/polygon-cache".into()),
+    requests_per_minute: std::env::var("POLYGON_REQUESTS_PER_MINUTE").map(|value| value.parse().unwrap()).unwrap_or(5),
+    max_retries: 5,
+  };
+  let mut client = polygon::PolygonClient::new(config);
+  let candles = client.get_aggregates(ticker, resolution, "minute", from, to);
+  let candles_filename = format!("./output/candles-{resolution}.csv");
+  write_records_to_csv(&candles_filename, &candles);
+  println!("wrote {} candles to {candles_filename}", candles.len());
+}
+
+fn build_candles(args: &[String]) {
+  // usage: build_candles <trades.csv> [resolution]
+  let trades_filename = &args[0];
+  let resolution: i64 = args.get(1).map(|value| value.parse().unwrap()).unwrap_or(1);
+  let ticks = read_records_from_csv::<ticks::TradeTick>(trades_filename);
+  let candles = ticks::build_candles_from_ticks(&ticks, resolution * 60, &ticks::DEFAULT_EXCLUDED_CONDITIONS);
+  let candles_filename = format!("./output/candles-{resolution}.csv");
+  write_records_to_csv(&candles_filename, &candles);
+  println!("wrote {} candles built from {} trades to {candles_filename}", candles.len(), ticks.len());
 }
 
 fn main() {
-  // rng
-  let mut rng = rand::thread_rng();
+  let args: Vec<String> = std::env::args().collect();
+  match args.get(1).map(|mode| mode.as_str()) {
+    Some("scrape_polygon") => scrape_polygon(&args[2..]),
+    Some("build_candles") => build_candles(&args[2..]),
+    Some("optimize") => optimize(&experiment::load_experiment(args.get(2).map(|filename| filename.as_str()))),
+    None => optimize(&experiment::load_experiment(None)),
+    Some(mode) => panic!("unknown mode {mode}"),
+  }
+}
+
+// one row per evaluated combination, sorted by its parameter values. the strategy's parameters, profit limit and stop
+// loss always get a column, the other parameters only when they take more than one value
+fn format_results(space: &SearchSpace, evaluations: Vec<Evaluation>, robustness: Vec<f64>) -> Vec<String> {
+  let mut total_performance_map = BTreeMap::new();
+  for ((signal_parameters, backtest_parameters, performance), robustness) in evaluations.into_iter().zip(robustness) {
+    let values = experiment::parameter_values(&signal_parameters, &backtest_parameters);
+    let key: Vec<OrderedFloat<f64>> = values.iter().map(|(_, value)| OrderedFloat(*value)).collect();
+    total_performance_map.insert(key, (performance, robustness));
+  }
+  let always_columns = ["profit_limit_percentage", "stop_loss_percentage"];
+  let is_column: Vec<bool> = space
+    .names
+    .iter()
+    .zip(&space.values)
+    .enumerate()
+    .map(|(dimension, (name, values))| {
+      let is_strategy_parameter = dimension > 0 && dimension < space.num_signal_parameters;
+      return is_strategy_parameter || always_columns.contains(&name.as_str()) || values.len() > 1;
+    })
+    .collect();
+  // rules without parameters have no signal columns
+  let parameter_columns: String = space
+    .names
+    .iter()
+    .zip(&is_column)
+    .filter(|(_, is_column)| **is_column)
+    .map(|(name, _)| format!("{name},"))
+    .collect();
+  let mut lines = vec![format!(
+    "{parameter_columns}profit_loss_percentage,num_trades,win_rate,max_drawdown,sharpe_ratio,profit_factor,robustness"
+  )];
+  for (key, (performance, robustness)) in total_performance_map.iter() {
+    let parameter_values: String = key
+      .iter()
+      .zip(&is_column)
+      .filter(|(_, is_column)| **is_column)
+      .map(|(value, _)| format!("{value},"))
+      .collect();
+    let profit_loss_percentage = performance.profit_loss_percentage;
+    let num_trades = performance.num_trades;
+    let win_rate = performance.win_rate();
+    let max_drawdown = performance.max_drawdown;
+    let sharpe_ratio = performance.sharpe_ratio();
+    let profit_factor = performance.profit_factor();
+    lines.push(format!(
+      "{parameter_values}{profit_loss_percentage},{num_trades},{win_rate},{max_drawdown},{sharpe_ratio},{profit_factor},{robustness}"
+    ));
+  }
+  return lines;
+}
+
+fn optimize(experiment: &Experiment) {
+  // seed for the close prediction rng, each signal parameter combination derives its own from it
+  let seed = experiment.seed;
   // load candles
-  let resolution = 1;
+  let resolution = experiment.resolution;
   let candles_filename = format!("./output/candles-{resolution}.csv");
   let candle_size_seconds = resolution * 60;
-  let candles = read_records_from_csv::<Candle>(&candles_filename);
-  let mut candles_map = HashMap::new();
-  for candle in &candles {
-    candles_map.insert(candle.start_timestamp, candle);
-  }
-  // build all possible signal/trade combinations
-  let mut total_performance_map = std::collections::BTreeMap::new();
-  let backtest_parameter_combinations = build_backtest_parameter_combinations();
-  let signal_parameter_combinations = build_signal_parameter_combinations();
-  for signal_parameters in &signal_parameter_combinations {
-    // build signals
-    let signals = build_signals(&candles, &candles_map, &signal_parameters, candle_size_seconds, &mut rng);
-    // build trades from signals
-    let trades = build_trades(&signals);
-    let trades_slice: &[Trade] = &trades;
-    let chunk_size = 2; // open + close
-    let chunked_trades: Vec<&[Trade]> = trades_slice.chunks(chunk_size).collect();
-    for chunk in chunked_trades {
-      // get open + close from chunk
-      let trade_open = &chunk[0];
-      let trade_close = &chunk[1];
-      assert!(trade_open.r#type == TradeType::Open);
-      assert!(trade_close.r#type == TradeType::Close);
-      assert!(trade_open.direction == trade_close.direction);
-      assert!(trade_open.timestamp != trade_close.timestamp);
-      // loop backtest parameter combinations
-      for backtest_parameters in &backtest_parameter_combinations {
-        // backtest trade
-        let backtest_result = backtest_trade(trade_open, trade_close, &candles_map, backtest_parameters, candle_size_seconds);
-        // record performance
-        let profit_loss_percentage = backtest_result.profit_loss_percentage;
-        let profit_limit_percentage = backtest_parameters.profit_limit_percentage;
-        let stop_loss_percentage = backtest_parameters.stop_loss_percentage;
-        let fast_periods = signal_parameters.fast_periods;
-        let slow_periods = signal_parameters.slow_periods;
-        let signal_key = (fast_periods, slow_periods);
-        let size_key = (OrderedFloat(profit_limit_percentage), OrderedFloat(stop_loss_percentage));
-        let total_key = (signal_key, size_key);
-        if total_performance_map.contains_key(&total_key) == false {
-          total_performance_map.insert(total_key, 0.0);
-        }
-        let total_key_performance = total_performance_map.get(&total_key).unwrap();
-        total_performance_map.insert(total_key, total_key_performance + profit_loss_percentage);
+  let raw_candles = candle_cache::load_candles(&candles_filename);
+  // split/dividend adjust (signals and fills can each use adjusted or raw prices)
+  let corporate_actions_filename = experiment.corporate_actions_filename.as_str();
+  let signal_price_adjustment = experiment.signal_price_adjustment;
+  let fill_price_adjustment = experiment.fill_price_adjustment;
+  let adjusted_candles = if std::path::Path::new(corporate_actions_filename).exists() {
+    let corporate_actions = read_records_from_csv::<CorporateAction>(corporate_actions_filename);
+    corporate_actions::adjust_candles(&raw_candles, &corporate_actions).unwrap_or_else(|error| panic!("invalid corporate action {error}"))
+  } else {
+    raw_candles.clone()
+  };
+  let select_candles = |price_adjustment: PriceAdjustment| match price_adjustment {
+    PriceAdjustment::Adjusted => &adjusted_candles,
+    PriceAdjustment::Unadjusted => &raw_candles,
+  };
+  let candles = CandleSeries::new(select_candles(signal_price_adjustment).clone(), candle_size_seconds);
+  let fill_candles = CandleSeries::new(select_candles(fill_price_adjustment).clone(), candle_size_seconds);
+  eprintln!("loaded {} candles across {} sessions", candles.candles().len(), candles.sessions().count());
+  if let Some(overfitting_parameters) = &experiment.overfitting {
+    overfitting_parameters
+      .fits_sessions(candles.sessions().count())
+      .unwrap_or_else(|error| panic!("invalid overfitting: {error}"));
+  }
+  if let Some(walk_forward_parameters) = &experiment.walk_forward {
+    walk_forward_parameters
+      .fits_sessions(candles.sessions().count())
+      .unwrap_or_else(|error| panic!("invalid walk_forward: {error}"));
+  }
+  // entry windows, forced exit and blackouts applied to every strategy's signals
+  let trading_hours = match &experiment.trading_hours {
+    Some(parameters) => {
+      let blackouts = match &parameters.blackouts_filename {
+        Some(blackouts_filename) => read_records_from_csv::<Blackout>(blackouts_filename),
+        None => vec![],
+      };
+      TradingHours::new(parameters, &blackouts)
+    }
+    None => TradingHours::default(),
+  };
+  // sides the account can take
+  let hard_to_borrow = match &experiment.hard_to_borrow_filename {
+    Some(hard_to_borrow_filename) => read_records_from_csv::<HardToBorrow>(hard_to_borrow_filename),
+    None => vec![],
+  };
+  let short_selling = ShortSelling::new(experiment.direction_policy, experiment.symbol.as_deref(), &hard_to_borrow);
+  // resume from earlier runs on the same data and setup
+  let checkpoint = experiment.checkpoint_filename.as_ref().map(|filename| {
+    let setup = format!(
+      "{:?} {:?} {:?} {:?} {:?} {:?}",
+      experiment.strategy, experiment.score, trading_hours, experiment.trade_controls, experiment.pyramiding, short_selling
+    );
+    let fingerprint = checkpoint::data_fingerprint(seed, &setup, &[candles.candles(), fill_candles.candles()]);
+    let checkpoint = Checkpoint::open(filename, fingerprint);
+    eprintln!("checkpoint: {} results in {filename}", checkpoint.len());
+    return checkpoint;
+  });
+  let context = EvaluationContext {
+    experiment,
+    candles: &candles,
+    fill_candles: &fill_candles,
+    trading_hours: &trading_hours,
+    short_selling: &short_selling,
+    checkpoint: checkpoint.as_ref(),
+  };
+  // evaluate the parameter space
+  let space = SearchSpace::new(experiment);
+  let mut search_rng = StdRng::seed_from_u64(seed);
+  let fitness = experiment.fitness;
+  let evaluations = match &experiment.search {
+    Search::Grid => {
+      let backtest_parameter_combinations = experiment.backtest_parameter_combinations();
+      let batches = experiment
+        .signal_parameter_combinations()
+        .into_iter()
+        .map(|signal_parameters| (signal_parameters, backtest_parameter_combinations.clone()))
+        .collect();
+      evaluate_batches(&context, batches)
+    }
+    Search::Random { budget } => {
+      let points = search::random_search(&space, *budget, &mut search_rng);
+      evaluate_points(&context, &space, &points)
+    }
+    Search::LatinHypercube { budget } => {
+      let points = search::latin_hypercube(&space, *budget, &mut search_rng);
+      evaluate_points(&context, &space, &points)
+    }
+    Search::Tpe {
+      budget,
+      initial_points,
+      gamma,
+      candidates,
+    } => {
+      // sequential, trades are cached per signal parameters since most proposals share them
+      let mut trades_cache: HashMap<Vec<usize>, Vec<Trade>> = HashMap::new();
+      let mut evaluations = vec![];
+      search::tpe_search(&space, *budget, *initial_points, *gamma, *candidates, &mut search_rng, |point| {
+        let values = space.point_values(point);
+        let signal_parameters = experiment.signal_parameters_from_values(&values);
+        let backtest_parameters = experiment::backtest_parameters_from_values(&values);
+        let parameter_values = experiment::parameter_values(&signal_parameters, &backtest_parameters);
+        let performance = match context.checkpoint.and_then(|checkpoint| checkpoint.get(checkpoint.key(&parameter_values))) {
+          Some(performance) => performance.clone(),
+          None => {
+            let trades = trades_cache
+              .entry(point[0..space.num_signal_parameters].to_vec())
+              .or_insert_with(|| build_signal_trades(&context, &signal_parameters));
+            let performance = backtest_trades(trades, &fill_candles, std::slice::from_ref(&backtest_parameters)).remove(0);
+            if let Some(checkpoint) = context.checkpoint {
+              checkpoint.append(&[parameter_values], std::slice::from_ref(&performance));
+            }
+            performance
+          }
+        };
+        let score = fitness.score(&performance);
+        evaluations.push((signal_parameters, backtest_parameters, performance));
+        return score;
+      });
+      evaluations
+    }
+    Search::Genetic(genetic_parameters) => {
+      let mut evaluations = vec![];
+      genetic::genetic_search(&space, genetic_parameters, &mut search_rng, |points| {
+        let generation_evaluations = evaluate_points(&context, &space, points);
+        let scores = generation_evaluations.iter().map(|(_, _, performance)| fitness.score(performance)).collect();
+        evaluations.extend(generation_evaluations);
+        return scores;
+      });
+      evaluations
+    }
+  };
+  // out of sample check
+  if let Some(walk_forward_parameters) = &experiment.walk_forward {
+    let sessions: Vec<i64> = candles.sessions().map(|(grouping_key, _)| grouping_key).collect();
+    report_walk_forward(experiment, walk_forward_parameters, &sessions, &evaluations);
+  }
+  if let Some(overfitting_parameters) = &experiment.overfitting {
+    let sessions: Vec<i64> = candles.sessions().map(|(grouping_key, _)| grouping_key).collect();
+    report_overfitting(experiment, overfitting_parameters, &sessions, &evaluations);
+  }
+  if let Some(monte_carlo_parameters) = &experiment.monte_carlo {
+    report_monte_carlo(&context, monte_carlo_parameters, &evaluations);
+  }
+  // parameter surface
+  let points: Vec<search::Point> = evaluations
+    .iter()
+    .map(|(signal_parameters, backtest_parameters, _)| {
+      let values: Vec<f64> = experiment::parameter_values(signal_parameters, backtest_parameters)
+        .iter()
+        .map(|(_, value)| *value)
+        .collect();
+      return space.point_of(&values);
+    })
+    .collect();
+  let scores: Vec<f64> = evaluations.iter().map(|(_, _, performance)| fitness.score(performance)).collect();
+  let dimension_sizes: Vec<usize> = space.values.iter().map(|values| values.len()).collect();
+  let robustness = robustness::robustness_scores(&dimension_sizes, &points, &scores);
+  report_heatmaps(experiment, &space, &points, &evaluations, &robustness);
+  // print results, prefixed with the experiment for reproducibility
+  for line in experiment.source.lines() {
+    println!("# {line}");
+  }
+  for line in format_results(&space, evaluations, robustness) {
+    println!("{line}");
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn signal_parameters(values: &[(&str, f64)]) -> SignalParameters {
+    return SignalParameters {
+      warmup_periods: 1,
+      values: values.iter().map(|(name, value)| (name.to_string(), *value)).collect(),
+    };
+  }
+
+  #[test]
+  fn seeds_tell_negative_parameters_apart() {
+    let seed = |value: f64| signal_parameters_seed(0, &signal_parameters(&[("offset", value)]));
+    assert_ne!(seed(-1.0), seed(-2.0));
+    assert_ne!(seed(-1.0), seed(0.0));
+    assert_ne!(seed(1.0), seed(-1.0));
+    assert_ne!(seed(0.5), seed(-0.5));
+  }
+
+  fn backtest_parameters() -> BacktestParameters {
+    return BacktestParameters {
+      slippage_percentage: 0.000125,
+      short_borrow_fee_percentage: 0.0,
+      profit_limit_percentage: 0.004,
+      stop_loss_percentage: -0.004,
+    };
+  }
+
+  fn results_of(space: &SearchSpace, evaluations: Vec<Evaluation>) -> Vec<String> {
+    let robustness = vec![0.0; evaluations.len()];
+    return format_results(space, evaluations, robustness);
+  }
+
+  #[test]
+  fn results_keep_combinations_that_only_differ_in_warmup_or_slippage() {
+    let mut experiment = experiment::load_experiment(None);
+    let list = |values: &[i64]| experiment::ParameterSpace::List {
+      values: values.iter().map(|value| Decimal::from(*value)).collect(),
+    };
+    let strategy_values = [("fast_periods", 10.0), ("slow_periods", 20.0)];
+    // single valued warmup and slippage get no column
+    let space = SearchSpace::new(&experiment);
+    let evaluations = vec![(signal_parameters(&strategy_values), backtest_parameters(), PerformanceMetrics::default())];
+    let lines = results_of(&space, evaluations);
+    assert!(lines[0].starts_with("fast_periods,slow_periods,profit_limit_percentage,stop_loss_percentage,profit_loss_percentage,"));
+    assert!(lines[1].starts_with("10,20,0.004,-0.004,"));
+    experiment.parameters.insert("warmup_periods".to_string(), list(&[1, 5]));
+    experiment.parameters.insert("slippage_percentage".to_string(), list(&[0, 1]));
+    let space = SearchSpace::new(&experiment);
+    let mut evaluations = vec![];
+    for warmup_periods in [1, 5] {
+      for slippage_percentage in [0.0, 1.0] {
+        let signal_parameters = SignalParameters {
+          warmup_periods,
+          ..signal_parameters(&strategy_values)
+        };
+        let backtest_parameters = BacktestParameters {
+          slippage_percentage,
+          ..backtest_parameters()
+        };
+        evaluations.push((signal_parameters, backtest_parameters, PerformanceMetrics::default()));
       }
     }
+    let lines = results_of(&space, evaluations);
+    assert!(lines[0].starts_with("warmup_periods,fast_periods,slow_periods,slippage_percentage,profit_limit_percentage,"));
+    assert_eq!(lines.len(), 5);
+    assert!(lines[1].starts_with("1,10,20,0,0.004,"));
+    assert!(lines[4].starts_with("5,10,20,1,0.004,"));
+  }
+
+  #[test]
+  fn results_keep_combinations_that_only_differ_in_borrow_fee() {
+    let mut experiment = experiment::load_experiment(None);
+    experiment.parameters.insert(
+      "short_borrow_fee_percentage".to_string(),
+      experiment::ParameterSpace::List {
+        values: vec![Decimal::ZERO, Decimal::new(3, 2)],
+      },
+    );
+    let space = SearchSpace::new(&experiment);
+    let evaluations = [0.0, 0.03]
+      .iter()
+      .map(|short_borrow_fee_percentage| {
+        let backtest_parameters = BacktestParameters {
+          short_borrow_fee_percentage: *short_borrow_fee_percentage,
+          ..backtest_parameters()
+        };
+        return (
+          signal_parameters(&[("fast_periods", 10.0), ("slow_periods", 20.0)]),
+          backtest_parameters,
+          PerformanceMetrics::default(),
+        );
+      })
+      .collect();
+    let lines = results_of(&space, evaluations);
+    assert!(lines[0].starts_with("fast_periods,slow_periods,short_borrow_fee_percentage,profit_limit_percentage,"));
+    assert_eq!(lines.len(), 3);
+    assert!(lines[1].starts_with("10,20,0,0.004,"));
+    assert!(lines[2].starts_with("10,20,0.03,0.004,"));
+  }
+
+  // 2023-03-01 09:30 eastern, one signal per minute from there
+  const REGULAR_OPEN: i64 = 1677681000;
+
+  // opens at REGULAR_OPEN and closes held_seconds later on flat candles that never touch the profit limit or stop loss
+  fn flat_trade_result(direction: Direction, held_seconds: i64, short_borrow_fee_percentage: f64) -> TradeBacktestResult {
+    let candles = (0..=held_seconds / 60)
+      .map(|minute| Candle {
+        start_timestamp: REGULAR_OPEN + minute * 60,
+        end_timestamp: REGULAR_OPEN + minute * 60 + 59,
+        open: 100.0,
+        high: 100.0,
+        low: 100.0,
+        close: 100.0,
+        volume: 100,
+      })
+      .collect();
+    let fill_candles = CandleSeries::new(candles, 60);
+    let trade = |timestamp: i64, r#type: TradeType| Trade {
+      grouping_key: REGULAR_OPEN,
+      timestamp,
+      r#type,
+      direction: direction.clone(),
+      size: 1.0,
+    };
+    let entries = [trade(REGULAR_OPEN, TradeType::Open)];
+    let trade_close = trade(REGULAR_OPEN + held_seconds, TradeType::Close);
+    let trade_paths = build_trade_paths(&entries, &trade_close, &fill_candles);
+    let backtest_parameters = BacktestParameters {
+      slippage_percentage: 0.0,
+      short_borrow_fee_percentage,
+      ..backtest_parameters()
+    };
+    return backtest_trade(&entries, &trade_close, &fill_candles, &trade_paths, &backtest_parameters);
   }
-  // print results
-  println!("fast_periods,slow_periods,profit_limit_percentage,stop_loss_percentage,profit_loss_percentage");
-  for (key, profit_loss_percentage) in total_performance_map.iter() {
-    let fast_periods = key.0.0;
-    let slow_periods = key.0.1;
-    let profit_limit_percentage = key.1.0;
-    let stop_loss_percentage = key.1.1;
-    println!("{fast_periods},{slow_periods},{profit_limit_percentage},{stop_loss_percentage},{profit_loss_percentage}");
+
+  #[test]
+  fn shorts_pay_the_borrow_fee_pro_rata() {
+    let held_seconds = 3600;
+    let result = flat_trade_result(Direction::Short, held_seconds, 0.05);
+    let borrow_fee = 100.0 * 0.05 * held_seconds as f64 / (365.0 * 24.0 * 60.0 * 60.0);
+    assert!(matches!(result.exit_reason, TradeExitReason::Close));
+    assert!((result.profit_loss + borrow_fee).abs() < 1e-12);
+    assert!((result.profit_loss_percentage + borrow_fee / 100.0).abs() < 1e-12);
+    let result = flat_trade_result(Direction::Long, held_seconds, 0.05);
+    assert_eq!(result.profit_loss, 0.0);
+  }
+
+  fn pyramided_trades(pyramiding: &Pyramiding, signals: &[(Direction, f64)]) -> Vec<(i64, &'static str, Direction)> {
+    let signals: Vec<Signal> = signals
+      .iter()
+      .enumerate()
+      .map(|(minute, (direction, score))| Signal {
+        grouping_key: REGULAR_OPEN,
+        timestamp: REGULAR_OPEN + minute as i64 * 60,
+        direction: direction.clone(),
+        score: Some(*score),
+        is_forced_flat: false,
+      })
+      .collect();
+    let fill_candles = CandleSeries::new(vec![], 60);
+    let short_selling = ShortSelling::new(short_selling::DirectionPolicy::Both, None, &[]);
+    let trades = build_trades(
+      &signals,
+      &Score::default(),
+      &short_selling,
+      &TradeControls::default(),
+      Some(pyramiding),
+      &fill_candles,
+    );
+    return trades
+      .into_iter()
+      .map(|trade| {
+        let r#type = match trade.r#type {
+          TradeType::Open => "open",
+          TradeType::Add => "add",
+          TradeType::Close => "close",
+        };
+        return ((trade.timestamp - REGULAR_OPEN) / 60, r#type, trade.direction);
+      })
+      .collect();
+  }
+
+  #[test]
+  fn pyramiding_adds_while_the_direction_persists() {
+    let pyramiding = Pyramiding {
+      max_units: 3,
+      add_every_periods: 2,
+      add_when: trade_controls::AddCondition::Persisting,
+    };
+    let mut signals = vec![(Direction::Long, 0.0); 6];
+    signals.extend([(Direction::Short, 0.0), (Direction::Flat, 0.0)]);
+    let trades = pyramided_trades(&pyramiding, &signals);
+    assert_eq!(
+      trades,
+      [
+        (0, "open", Direction::Long),
+        (2, "add", Direction::Long),
+        (4, "add", Direction::Long),
+        (6, "close", Direction::Long),
+        (6, "open", Direction::Short),
+        (7, "close", Direction::Short),
+      ]
+    );
   }
-}
\ No newline at end of file
+
+  #[test]
+  fn pyramiding_adds_on_a_stronger_score() {
+    let pyramiding = Pyramiding {
+      max_units: 5,
+      add_every_periods: 1,
+      add_when: trade_controls::AddCondition::StrongerScore,
+    };
+    let short = |score: f64| (Direction::Short, score);
+    // only entries further below zero than the last one add, nan never does
+    let signals = [short(-0.1), short(-0.05), short(-0.2), short(-0.2), short(f64::NAN), short(-0.3)];
+    let mut signals = signals.to_vec();
+    signals.push((Direction::Flat, 0.0));
+    let trades = pyramided_trades(&pyramiding, &signals);
+    assert_eq!(
+      trades,
+      [
+        (0, "open", Direction::Short),
+        (2, "add", Direction::Short),
+        (5, "add", Direction::Short),
+        (6, "close", Direction::Short),
+      ]
+    );
+  }
+}
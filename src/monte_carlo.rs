@@ -0,0 +1,165 @@
+use std::collections::BTreeMap;
+
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::Rng;
+use serde::Deserialize;
+
+use crate::TradeBacktestResult;
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct MonteCarloParameters {
+  #[serde(default = "default_simulations")]
+  pub simulations: usize,
+  // fraction of equity put into every trade, returns compound
+  #[serde(default = "default_position_size")]
+  pub position_size: f64,
+  // a path is ruined once its drawdown from peak equity reaches this fraction
+  #[serde(default = "default_ruin_drawdown")]
+  pub ruin_drawdown: f64,
+  #[serde(default = "default_confidence")]
+  pub confidence: f64,
+  // values of every parameter, the best evaluated combination by fitness when omitted
+  #[serde(default)]
+  pub parameters: Option<BTreeMap<String, f64>>,
+}
+
+fn default_simulations() -> usize {
+  return 10_000;
+}
+
+fn default_position_size() -> f64 {
+  return 1.0;
+}
+
+fn default_ruin_drawdown() -> f64 {
+  return 0.5;
+}
+
+fn default_confidence() -> f64 {
+  return 0.95;
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum Resampling {
+  // trades drawn independently with replacement
+  Bootstrap,
+  // whole sessions (grouping_key) drawn with replacement, keeps intraday clustering of wins and losses
+  SessionBootstrap,
+  // the same trades in a random order, final return is unchanged but the path is not
+  Permutation,
+}
+
+impl Resampling {
+  pub fn name(&self) -> &'static str {
+    match self {
+      Resampling::Bootstrap => return "bootstrap",
+      Resampling::SessionBootstrap => return "session_bootstrap",
+      Resampling::Permutation => return "permutation",
+    }
+  }
+}
+
+pub const RESAMPLINGS: [Resampling; 3] = [Resampling::Bootstrap, Resampling::SessionBootstrap, Resampling::Permutation];
+
+#[derive(Debug, Clone)]
+pub struct Simulation {
+  pub final_return: f64,
+  pub max_drawdown: f64,
+  pub longest_losing_streak: usize,
+  pub ruined: bool,
+}
+
+// compounds the trade returns at the given position size, drawdown is relative to peak equity
+pub fn simulate_path(returns: &[f64], position_size: f64, ruin_drawdown: f64) -> Simulation {
+  let mut equity = 1.0_f64;
+  let mut peak = 1.0_f64;
+  let mut max_drawdown = 0.0_f64;
+  let mut losing_streak = 0;
+  let mut longest_losing_streak = 0;
+  for profit_loss_percentage in returns {
+    equity = (equity * (1.0 + position_size * profit_loss_percentage)).max(0.0);
+    peak = peak.max(equity);
+    max_drawdown = max_drawdown.max(1.0 - equity / peak);
+    if *profit_loss_percentage > 0.0 {
+      losing_streak = 0;
+    } else {
+      losing_streak += 1;
+      longest_losing_streak = longest_losing_streak.max(losing_streak);
+    }
+  }
+  return Simulation {
+    final_return: equity - 1.0,
+    max_drawdown,
+    longest_losing_streak,
+    ruined: max_drawdown >= ruin_drawdown,
+  };
+}
+
+// results are in the order the trades were closed, so every session is one contiguous run
+fn resample(results: &[TradeBacktestResult], sessions: &[&[TradeBacktestResult]], resampling: Resampling, rng: &mut StdRng) -> Vec<f64> {
+  match resampling {
+    Resampling::Bootstrap => {
+      return (0..results.len())
+        .map(|_| results[rng.gen_range(0..results.len())].profit_loss_percentage)
+        .collect();
+    }
+    Resampling::SessionBootstrap => {
+      return (0..sessions.len())
+        .flat_map(|_| sessions[rng.gen_range(0..sessions.len())].iter())
+        .map(|result| result.profit_loss_percentage)
+        .collect();
+    }
+    Resampling::Permutation => {
+      let mut returns: Vec<f64> = results.iter().map(|result| result.profit_loss_percentage).collect();
+      returns.shuffle(rng);
+      return returns;
+    }
+  }
+}
+
+pub fn monte_carlo(results: &[TradeBacktestResult], resampling: Resampling, parameters: &MonteCarloParameters, rng: &mut StdRng) -> Vec<Simulation> {
+  if results.is_empty() {
+    return vec![];
+  }
+  let sessions: Vec<&[TradeBacktestResult]> = results.chunk_by(|a, b| a.grouping_key == b.grouping_key).collect();
+  return (0..parameters.simulations)
+    .map(|_| {
+      simulate_path(
+        &resample(results, &sessions, resampling, rng),
+        parameters.position_size,
+        parameters.ruin_drawdown,
+      )
+    })
+    .collect();
+}
+
+// nearest rank percentile of already sorted values
+fn percentile(sorted: &[f64], fraction: f64) -> f64 {
+  let rank = ((fraction * sorted.len() as f64).ceil() as usize).clamp(1, sorted.len());
+  return sorted[rank - 1];
+}
+
+pub struct Distribution {
+  pub mean: f64,
+  pub median: f64,
+  pub lower: f64,
+  pub upper: f64,
+}
+
+// two sided confidence interval
+pub fn distribution(values: &[f64], confidence: f64) -> Distribution {
+  let mut sorted = values.to_vec();
+  sorted.sort_by(|a, b| a.total_cmp(b));
+  let tail = (1.0 - confidence) / 2.0;
+  return Distribution {
+    mean: sorted.iter().sum::<f64>() / sorted.len() as f64,
+    median: percentile(&sorted, 0.5),
+    lower: percentile(&sorted, tail),
+    upper: percentile(&sorted, 1.0 - tail),
+  };
+}
+
+pub fn risk_of_ruin(simulations: &[Simulation]) -> f64 {
+  return simulations.iter().filter(|simulation| simulation.ruined).count() as f64 / simulations.len() as f64;
+}
@@ -0,0 +1,417 @@
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use rayon::prelude::*;
+use serde::Deserialize;
+
+const EULER_MASCHERONI: f64 = 0.5772156649015329;
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct OverfittingParameters {
+  // number of contiguous session blocks for cscv, must be even
+  #[serde(default = "default_partitions")]
+  pub partitions: usize,
+  #[serde(default = "default_bootstrap_samples")]
+  pub bootstrap_samples: usize,
+  // mean block length (in sessions) of the stationary bootstrap
+  #[serde(default = "default_block_length")]
+  pub block_length: f64,
+}
+
+fn default_partitions() -> usize {
+  return 8;
+}
+
+fn default_bootstrap_samples() -> usize {
+  return 1000;
+}
+
+fn default_block_length() -> f64 {
+  return 5.0;
+}
+
+impl OverfittingParameters {
+  // the number of sessions is only known once the candles are loaded, see fits_sessions
+  pub fn validate(&self) -> Result<(), String> {
+    if self.partitions < 2 || self.partitions.is_multiple_of(2) == false {
+      return Err(format!("partitions must be even and at least 2, got {}", self.partitions));
+    }
+    if self.bootstrap_samples == 0 {
+      return Err("bootstrap_samples must be at least 1".to_string());
+    }
+    if self.block_length < 1.0 {
+      return Err(format!("block_length must be at least 1 session, got {}", self.block_length));
+    }
+    return Ok(());
+  }
+
+  pub fn fits_sessions(&self, num_sessions: usize) -> Result<(), String> {
+    if self.partitions > num_sessions {
+      return Err(format!("{} partitions do not fit {num_sessions} sessions", self.partitions));
+    }
+    return Ok(());
+  }
+}
+
+pub fn mean(values: &[f64]) -> f64 {
+  if values.is_empty() {
+    return 0.0;
+  }
+  return values.iter().sum::<f64>() / values.len() as f64;
+}
+
+// per period, not annualized
+pub fn sharpe_ratio(returns: &[f64]) -> f64 {
+  if returns.len() < 2 {
+    return 0.0;
+  }
+  let mean = mean(returns);
+  let variance = returns.iter().map(|value| (value - mean).powi(2)).sum::<f64>() / (returns.len() - 1) as f64;
+  if variance <= 0.0 {
+    return 0.0;
+  }
+  return mean / variance.sqrt();
+}
+
+// abramowitz and stegun 7.1.26, absolute error below 1.5e-7
+fn erf(x: f64) -> f64 {
+  let sign = if x < 0.0 { -1.0 } else { 1.0 };
+  let x = x.abs();
+  let t = 1.0 / (1.0 + 0.3275911 * x);
+  let polynomial = t * (0.254829592 + t * (-0.284496736 + t * (1.421413741 + t * (-1.453152027 + t * 1.061405429))));
+  return sign * (1.0 - polynomial * (-x * x).exp());
+}
+
+pub fn normal_cdf(x: f64) -> f64 {
+  return 0.5 * (1.0 + erf(x / std::f64::consts::SQRT_2));
+}
+
+// acklam's rational approximation, relative error below 1.2e-9
+pub fn inverse_normal_cdf(p: f64) -> f64 {
+  assert!(p > 0.0 && p < 1.0, "inverse normal cdf is only defined on (0, 1)");
+  let a = [
+    -3.969683028665376e1,
+    2.209460984245205e2,
+    -2.759285104469687e2,
+    1.38357751867269e2,
+    -3.066479806614716e1,
+    2.506628277459239,
+  ];
+  let b = [
+    -5.447609879822406e1,
+    1.615858368580409e2,
+    -1.556989798598866e2,
+    6.680131188771972e1,
+    -1.328068155288572e1,
+  ];
+  let c = [
+    -7.784894002430293e-3,
+    -3.223964580411365e-1,
+    -2.400758277161838,
+    -2.549732539343734,
+    4.374664141464968,
+    2.938163982698783,
+  ];
+  let d = [7.784695709041462e-3, 3.224671290700398e-1, 2.445134137142996, 3.754408661907416];
+  let p_low = 0.02425;
+  if p < p_low {
+    let q = (-2.0 * p.ln()).sqrt();
+    return (((((c[0] * q + c[1]) * q + c[2]) * q + c[3]) * q + c[4]) * q + c[5]) / ((((d[0] * q + d[1]) * q + d[2]) * q + d[3]) * q + 1.0);
+  }
+  if p > 1.0 - p_low {
+    let q = (-2.0 * (1.0 - p).ln()).sqrt();
+    return -(((((c[0] * q + c[1]) * q + c[2]) * q + c[3]) * q + c[4]) * q + c[5]) / ((((d[0] * q + d[1]) * q + d[2]) * q + d[3]) * q + 1.0);
+  }
+  let q = p - 0.5;
+  let r = q * q;
+  return (((((a[0] * r + a[1]) * r + a[2]) * r + a[3]) * r + a[4]) * r + a[5]) * q / (((((b[0] * r + b[1]) * r + b[2]) * r + b[3]) * r + b[4]) * r + 1.0);
+}
+
+fn combinations(n: usize, k: usize) -> Vec<Vec<usize>> {
+  let mut results = vec![];
+  let mut current = vec![];
+  fn recurse(start: usize, n: usize, k: usize, current: &mut Vec<usize>, results: &mut Vec<Vec<usize>>) {
+    if current.len() == k {
+      results.push(current.clone());
+      return;
+    }
+    for index in start..n {
+      current.push(index);
+      recurse(index + 1, n, k, current, results);
+      current.pop();
+    }
+  }
+  recurse(0, n, k, &mut current, &mut results);
+  return results;
+}
+
+// combinatorially symmetric cross-validation (bailey, borwein, lopez de prado, zhu).
+// returns[combination][session]; sessions are cut into `partitions` contiguous blocks, every half of the blocks is used
+// once as in-sample, and pbo is the fraction of splits where the in-sample best (by sharpe) ranks at or below the
+// out-of-sample median
+pub fn probability_of_backtest_overfitting(returns: &[Vec<f64>], partitions: usize) -> f64 {
+  assert!(partitions >= 2 && partitions.is_multiple_of(2), "cscv partitions must be even");
+  let num_sessions = returns.first().map(|row| row.len()).unwrap_or(0);
+  if returns.len() < 2 || num_sessions < partitions {
+    return f64::NAN;
+  }
+  // per combination and block: (count, sum, sum of squares) so every split is only a handful of additions
+  let block_size = num_sessions / partitions;
+  let block_range = |block: usize| {
+    let start = block * block_size;
+    let end = if block == partitions - 1 { num_sessions } else { start + block_size };
+    return start..end;
+  };
+  let block_stats: Vec<Vec<(f64, f64, f64)>> = returns
+    .iter()
+    .map(|row| {
+      (0..partitions)
+        .map(|block| {
+          let values = &row[block_range(block)];
+          return (values.len() as f64, values.iter().sum(), values.iter().map(|value| value * value).sum());
+        })
+        .collect()
+    })
+    .collect();
+  let sharpe_of_blocks = |stats: &[(f64, f64, f64)], blocks: &[usize]| {
+    let (n, sum, sum_of_squares) = blocks.iter().fold((0.0, 0.0, 0.0), |acc, block| {
+      (acc.0 + stats[*block].0, acc.1 + stats[*block].1, acc.2 + stats[*block].2)
+    });
+    let mean = sum / n;
+    let variance = (sum_of_squares - n * mean * mean) / (n - 1.0);
+    return if variance > 0.0 { mean / variance.sqrt() } else { 0.0 };
+  };
+  let splits = combinations(partitions, partitions / 2);
+  let logits: Vec<f64> = splits
+    .par_iter()
+    .map(|in_sample_blocks| {
+      let out_of_sample_blocks: Vec<usize> = (0..partitions).filter(|block| in_sample_blocks.contains(block) == false).collect();
+      let mut best = 0;
+      let mut best_sharpe = f64::NEG_INFINITY;
+      for (index, stats) in block_stats.iter().enumerate() {
+        let sharpe = sharpe_of_blocks(stats, in_sample_blocks);
+        if sharpe > best_sharpe {
+          best = index;
+          best_sharpe = sharpe;
+        }
+      }
+      let out_of_sample: Vec<f64> = block_stats.iter().map(|stats| sharpe_of_blocks(stats, &out_of_sample_blocks)).collect();
+      let rank = out_of_sample.iter().filter(|sharpe| **sharpe < out_of_sample[best]).count() as f64 + 1.0;
+      let relative_rank = rank / (returns.len() as f64 + 1.0);
+      return (relative_rank / (1.0 - relative_rank)).ln();
+    })
+    .collect();
+  return logits.iter().filter(|logit| **logit <= 0.0).count() as f64 / logits.len() as f64;
+}
+
+// deflated sharpe ratio (bailey, lopez de prado): probability that the selected strategy's true sharpe is above the
+// maximum expected from `num_trials` unskilled trials whose sharpe ratios have the given variance
+pub fn deflated_sharpe_ratio(returns: &[f64], num_trials: usize, trial_sharpe_variance: f64) -> f64 {
+  let n = returns.len() as f64;
+  if returns.len() < 3 || num_trials == 0 {
+    return f64::NAN;
+  }
+  let mean = mean(returns);
+  let variance = returns.iter().map(|value| (value - mean).powi(2)).sum::<f64>() / n;
+  if variance <= 0.0 {
+    return f64::NAN;
+  }
+  let standard_deviation = variance.sqrt();
+  let skewness = returns.iter().map(|value| ((value - mean) / standard_deviation).powi(3)).sum::<f64>() / n;
+  let kurtosis = returns.iter().map(|value| ((value - mean) / standard_deviation).powi(4)).sum::<f64>() / n;
+  let sharpe = sharpe_ratio(returns);
+  let expected_max_sharpe = if num_trials > 1 {
+    let trials = num_trials as f64;
+    trial_sharpe_variance.sqrt()
+      * ((1.0 - EULER_MASCHERONI) * inverse_normal_cdf(1.0 - 1.0 / trials) + EULER_MASCHERONI * inverse_normal_cdf(1.0 - 1.0 / (trials * std::f64::consts::E)))
+  } else {
+    0.0
+  };
+  let denominator = (1.0 - skewness * sharpe + (kurtosis - 1.0) / 4.0 * sharpe * sharpe).max(f64::EPSILON).sqrt();
+  return normal_cdf((sharpe - expected_max_sharpe) * (n - 1.0).sqrt() / denominator);
+}
+
+// stationary bootstrap (politis, romano) as (start, length) blocks that wrap around the end of the sample
+fn stationary_bootstrap_blocks(num_sessions: usize, block_length: f64, rng: &mut StdRng) -> Vec<(usize, usize)> {
+  let mut blocks = vec![];
+  let mut total = 0;
+  while total < num_sessions {
+    let start = rng.gen_range(0..num_sessions);
+    let mut length = 1;
+    while total + length < num_sessions && rng.gen::<f64>() >= 1.0 / block_length {
+      length += 1;
+    }
+    blocks.push((start, length));
+    total += length;
+  }
+  return blocks;
+}
+
+// sum of a wrapping block using a prefix sum over the row concatenated with itself
+fn block_sum(prefix: &[f64], start: usize, length: usize) -> f64 {
+  return prefix[start + length] - prefix[start];
+}
+
+pub struct RealityCheck {
+  pub white_p_value: f64,
+  pub hansen_spa_p_value: f64,
+}
+
+// white's reality check and hansen's (consistent) superior predictive ability test of the best combination against the
+// baseline's per session returns; small p-values mean the best result is unlikely to be data snooping luck
+pub fn reality_check(returns: &[Vec<f64>], baseline: &[f64], parameters: &OverfittingParameters, seed: u64) -> RealityCheck {
+  let num_sessions = baseline.len();
+  if returns.is_empty() || num_sessions < 2 {
+    return RealityCheck {
+      white_p_value: f64::NAN,
+      hansen_spa_p_value: f64::NAN,
+    };
+  }
+  let t = num_sessions as f64;
+  // relative performance, as doubled prefix sums so bootstrap blocks can wrap
+  let prefixes: Vec<Vec<f64>> = returns
+    .iter()
+    .map(|row| {
+      let mut prefix = vec![0.0; 2 * num_sessions + 1];
+      for index in 0..2 * num_sessions {
+        let session = index % num_sessions;
+        prefix[index + 1] = prefix[index] + row[session] - baseline[session];
+      }
+      return prefix;
+    })
+    .collect();
+  let means: Vec<f64> = prefixes.iter().map(|prefix| prefix[num_sessions] / t).collect();
+  let mut rng = StdRng::seed_from_u64(seed);
+  let samples: Vec<Vec<(usize, usize)>> = (0..parameters.bootstrap_samples)
+    .map(|_| stationary_bootstrap_blocks(num_sessions, parameters.block_length, &mut rng))
+    .collect();
+  // bootstrap means[sample][combination], blocks may overshoot the sample length so divide by the actual count
+  let bootstrap_means: Vec<Vec<f64>> = samples
+    .par_iter()
+    .map(|blocks| {
+      let count: usize = blocks.iter().map(|(_, length)| length).sum();
+      return prefixes
+        .iter()
+        .map(|prefix| blocks.iter().map(|(start, length)| block_sum(prefix, *start, *length)).sum::<f64>() / count as f64)
+        .collect();
+    })
+    .collect();
+  let num_samples = bootstrap_means.len() as f64;
+  // omega_k: bootstrap standard deviation of sqrt(t) * mean
+  let omegas: Vec<f64> = (0..returns.len())
+    .map(|combination| {
+      let values: Vec<f64> = bootstrap_means.iter().map(|sample| sample[combination]).collect();
+      let sample_mean = mean(&values);
+      let variance = values.iter().map(|value| (value - sample_mean).powi(2)).sum::<f64>() / num_samples;
+      return (t * variance).sqrt().max(f64::EPSILON);
+    })
+    .collect();
+  // white: max of the non studentized means
+  let white_statistic = means.iter().map(|mean| t.sqrt() * mean).fold(f64::NEG_INFINITY, f64::max);
+  // hansen: studentized, poor performers are recentered to zero so they do not inflate the null distribution
+  let spa_statistic = means.iter().zip(&omegas).map(|(mean, omega)| t.sqrt() * mean / omega).fold(0.0, f64::max);
+  let threshold = -(2.0 * t.ln().ln().max(0.0)).sqrt();
+  let recentered_means: Vec<f64> = means
+    .iter()
+    .zip(&omegas)
+    .map(|(mean, omega)| if t.sqrt() * mean / omega > threshold { *mean } else { 0.0 })
+    .collect();
+  let mut white_exceedances = 0;
+  let mut spa_exceedances = 0;
+  for sample in &bootstrap_means {
+    let white_bootstrap = sample
+      .iter()
+      .zip(&means)
+      .map(|(bootstrap_mean, mean)| t.sqrt() * (bootstrap_mean - mean))
+      .fold(f64::NEG_INFINITY, f64::max);
+    let spa_bootstrap = sample
+      .iter()
+      .zip(recentered_means.iter().zip(&omegas))
+      .map(|(bootstrap_mean, (recentered_mean, omega))| t.sqrt() * (bootstrap_mean - recentered_mean) / omega)
+      .fold(0.0, f64::max);
+    if white_bootstrap >= white_statistic {
+      white_exceedances += 1;
+    }
+    if spa_bootstrap >= spa_statistic {
+      spa_exceedances += 1;
+    }
+  }
+  return RealityCheck {
+    white_p_value: white_exceedances as f64 / num_samples,
+    hansen_spa_p_value: spa_exceedances as f64 / num_samples,
+  };
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  // the same alternating noise on every row, shifted up by a per row drift
+  fn drifting_rows(drifts: &[f64], num_sessions: usize) -> Vec<Vec<f64>> {
+    return drifts
+      .iter()
+      .map(|drift| {
+        (0..num_sessions)
+          .map(|session| drift + if session.is_multiple_of(2) { 0.01 } else { -0.01 })
+          .collect()
+      })
+      .collect();
+  }
+
+  #[test]
+  fn pbo_is_zero_when_the_best_combination_is_best_everywhere() {
+    let returns = drifting_rows(&[0.0, 0.001, 0.002, 0.003], 40);
+    assert_eq!(probability_of_backtest_overfitting(&returns, 4), 0.0);
+  }
+
+  #[test]
+  fn pbo_is_one_when_in_sample_winners_lose_out_of_sample() {
+    // each row is good in one half and bad in the other, so the in-sample best is always the out-of-sample worst
+    let good = |session: usize| 0.01 + if session.is_multiple_of(2) { 0.001 } else { -0.001 };
+    let bad = |session: usize| -0.01 + if session.is_multiple_of(2) { 0.001 } else { -0.001 };
+    let first: Vec<f64> = (0..20).map(|session| if session < 10 { good(session) } else { bad(session) }).collect();
+    let second: Vec<f64> = (0..20).map(|session| if session < 10 { bad(session) } else { good(session) }).collect();
+    assert_eq!(probability_of_backtest_overfitting(&[first, second], 2), 1.0);
+  }
+
+  #[test]
+  fn pbo_needs_enough_sessions() {
+    let returns = drifting_rows(&[0.0, 0.001], 3);
+    assert!(probability_of_backtest_overfitting(&returns, 4).is_nan());
+  }
+
+  #[test]
+  fn deflated_sharpe_of_a_zero_mean_single_trial_is_one_half() {
+    let returns: Vec<f64> = (0..20usize).map(|session| if session.is_multiple_of(2) { 0.01 } else { -0.01 }).collect();
+    assert!((deflated_sharpe_ratio(&returns, 1, 0.0) - 0.5).abs() < 1e-6);
+  }
+
+  #[test]
+  fn deflated_sharpe_falls_with_the_number_of_trials() {
+    let returns = drifting_rows(&[0.002], 50).remove(0);
+    let single = deflated_sharpe_ratio(&returns, 1, 0.01);
+    let many = deflated_sharpe_ratio(&returns, 100, 0.01);
+    assert!(single > 0.5);
+    assert!(many < single);
+  }
+
+  #[test]
+  fn normal_quantiles() {
+    assert!((inverse_normal_cdf(0.975) - 1.959964).abs() < 1e-6);
+    assert!((normal_cdf(1.959964) - 0.975).abs() < 1e-6);
+    assert!(inverse_normal_cdf(0.5).abs() < 1e-9);
+  }
+
+  #[test]
+  fn validates_partitions() {
+    let parameters = |partitions: usize| OverfittingParameters {
+      partitions,
+      bootstrap_samples: 10,
+      block_length: 5.0,
+    };
+    assert!(parameters(8).validate().is_ok());
+    assert!(parameters(0).validate().is_err());
+    assert!(parameters(7).validate().is_err());
+    assert!(parameters(8).fits_sessions(8).is_ok());
+    assert!(parameters(8).fits_sessions(7).is_err());
+  }
+}
@@ -0,0 +1,114 @@
+use serde::{Deserialize, Serialize};
+
+use crate::checkpoint::{deserialize_float, deserialize_keyed_floats, serialize_float, serialize_keyed_floats};
+
+// running summary of a sequence of trade returns (profit_loss_percentage), in the order they were closed. the floats are
+// serialized through the checkpoint helpers so nan and infinities survive a reload
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct PerformanceMetrics {
+  #[serde(serialize_with = "serialize_float", deserialize_with = "deserialize_float")]
+  pub profit_loss_percentage: f64,
+  pub num_trades: usize,
+  pub num_wins: usize,
+  #[serde(serialize_with = "serialize_float", deserialize_with = "deserialize_float")]
+  pub gross_profit: f64,
+  #[serde(serialize_with = "serialize_float", deserialize_with = "deserialize_float")]
+  pub gross_loss: f64,
+  #[serde(serialize_with = "serialize_float", deserialize_with = "deserialize_float")]
+  pub max_drawdown: f64,
+  // summed profit_loss_percentage per grouping_key, only sessions that had trades
+  #[serde(serialize_with = "serialize_keyed_floats", deserialize_with = "deserialize_keyed_floats")]
+  pub session_returns: Vec<(i64, f64)>,
+  #[serde(serialize_with = "serialize_float", deserialize_with = "deserialize_float")]
+  sum_of_squares: f64,
+  #[serde(serialize_with = "serialize_float", deserialize_with = "deserialize_float")]
+  peak: f64,
+}
+
+impl PerformanceMetrics {
+  // treats every return as one trade, e.g. to summarize per session returns
+  pub fn from_returns(returns: &[f64]) -> PerformanceMetrics {
+    let mut metrics = PerformanceMetrics::default();
+    for (index, profit_loss_percentage) in returns.iter().enumerate() {
+      metrics.record(index as i64, *profit_loss_percentage);
+    }
+    return metrics;
+  }
+
+  pub fn record(&mut self, grouping_key: i64, profit_loss_percentage: f64) {
+    match self.session_returns.last_mut() {
+      Some((last_grouping_key, session_return)) if *last_grouping_key == grouping_key => *session_return += profit_loss_percentage,
+      _ => self.session_returns.push((grouping_key, profit_loss_percentage)),
+    }
+    self.profit_loss_percentage += profit_loss_percentage;
+    self.num_trades += 1;
+    self.sum_of_squares += profit_loss_percentage * profit_loss_percentage;
+    if profit_loss_percentage > 0.0 {
+      self.num_wins += 1;
+      self.gross_profit += profit_loss_percentage;
+    } else {
+      self.gross_loss -= profit_loss_percentage;
+    }
+    self.peak = self.peak.max(self.profit_loss_percentage);
+    self.max_drawdown = self.max_drawdown.max(self.peak - self.profit_loss_percentage);
+  }
+
+  pub fn win_rate(&self) -> f64 {
+    if self.num_trades == 0 {
+      return 0.0;
+    }
+    return self.num_wins as f64 / self.num_trades as f64;
+  }
+
+  // per trade, not annualized
+  pub fn sharpe_ratio(&self) -> f64 {
+    if self.num_trades < 2 {
+      return 0.0;
+    }
+    let n = self.num_trades as f64;
+    let mean = self.profit_loss_percentage / n;
+    let variance = (self.sum_of_squares - n * mean * mean) / (n - 1.0);
+    if variance <= 0.0 {
+      return 0.0;
+    }
+    return mean / variance.sqrt();
+  }
+
+  pub fn profit_factor(&self) -> f64 {
+    if self.gross_loss == 0.0 {
+      return if self.gross_profit > 0.0 { f64::INFINITY } else { 0.0 };
+    }
+    return self.gross_profit / self.gross_loss;
+  }
+
+  pub fn return_over_drawdown(&self) -> f64 {
+    if self.max_drawdown == 0.0 {
+      return if self.profit_loss_percentage > 0.0 { f64::INFINITY } else { 0.0 };
+    }
+    return self.profit_loss_percentage / self.max_drawdown;
+  }
+}
+
+// what optimizers maximize
+#[derive(Debug, Clone, Copy, Deserialize, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum Fitness {
+  #[default]
+  TotalReturn,
+  SharpeRatio,
+  ProfitFactor,
+  ReturnOverDrawdown,
+  WinRate,
+}
+
+impl Fitness {
+  pub fn score(&self, metrics: &PerformanceMetrics) -> f64 {
+    match self {
+      Fitness::TotalReturn => return metrics.profit_loss_percentage,
+      Fitness::SharpeRatio => return metrics.sharpe_ratio(),
+      Fitness::ProfitFactor => return metrics.profit_factor(),
+      Fitness::ReturnOverDrawdown => return metrics.return_over_drawdown(),
+      Fitness::WinRate => return metrics.win_rate(),
+    }
+  }
+}
@@ -0,0 +1,304 @@
+use std::{
+  fs,
+  path::PathBuf,
+  thread,
+  time::{Duration, Instant},
+};
+
+use chrono::{NaiveDate, Utc};
+use chrono_tz::US;
+use serde::Deserialize;
+
+use crate::fnv;
+use crate::{datetime_from_timestamp, Candle};
+
+pub const DEFAULT_BASE_URL: &str = "https://api.polygon.io";
+
+#[derive(Debug, Deserialize)]
+struct AggregatesResponse {
+  status: String,
+  #[serde(default)]
+  error: Option<String>,
+  #[serde(default)]
+  results: Vec<AggregateBar>,
+  #[serde(default)]
+  next_url: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct AggregateBar {
+  t: i64, // unix milliseconds of the start of the bar
+  o: f64,
+  h: f64,
+  l: f64,
+  c: f64,
+  v: f64,
+}
+
+pub struct PolygonConfig {
+  pub base_url: String,
+  pub api_key: String,
+  pub cache_directory: Option<PathBuf>,
+  pub requests_per_minute: u32,
+  pub max_retries: u32,
+}
+
+pub struct PolygonClient {
+  config: PolygonConfig,
+  agent: ureq::Agent,
+  last_request_at: Option<Instant>,
+}
+
+fn timespan_to_seconds(timespan: &str) -> i64 {
+  match timespan {
+    "second" => 1,
+    "minute" => 60,
+    "hour" => 60 * 60,
+    "day" => 60 * 60 * 24,
+    "week" => 60 * 60 * 24 * 7,
+    _ => panic!("unsupported timespan {timespan}"),
+  }
+}
+
+// stable across builds so cache filenames survive recompiles
+fn hash_url(url: &str) -> u64 {
+  return fnv::hash_bytes(url.as_bytes());
+}
+
+fn with_api_key(url: &str, api_key: &str) -> String {
+  let separator = if url.contains('?') { '&' } else { '?' };
+  return format!("{url}{separator}apiKey={api_key}");
+}
+
+// the session date `to` (YYYY-MM-DD or unix milliseconds) falls on, in exchange local time
+fn parse_range_end(to: &str) -> Option<NaiveDate> {
+  if let Ok(date) = NaiveDate::parse_from_str(to, "%Y-%m-%d") {
+    return Some(date);
+  }
+  let milliseconds: i64 = to.parse().ok()?;
+  return Some(datetime_from_timestamp(milliseconds / 1000).date_naive());
+}
+
+// ranges reaching today (or later) can still change, so they are never cached
+fn is_range_settled(to: &str, today: NaiveDate) -> bool {
+  return parse_range_end(to).is_some_and(|date| date < today);
+}
+
+fn parse_aggregates(body: &str, candle_size_seconds: i64) -> Result<(Vec<Candle>, Option<String>), String> {
+  let response: AggregatesResponse = serde_json::from_str(body).map_err(|error| format!("invalid aggregates response: {error}"))?;
+  if response.status != "OK" && response.status != "DELAYED" {
+    return Err(format!("polygon returned status {} {:?}", response.status, response.error));
+  }
+  let candles = response
+    .results
+    .iter()
+    .map(|bar| {
+      let start_timestamp = bar.t / 1000;
+      return Candle {
+        start_timestamp,
+        end_timestamp: start_timestamp + candle_size_seconds - 1,
+        open: bar.o,
+        high: bar.h,
+        low: bar.l,
+        close: bar.c,
+        volume: bar.v as i64,
+      };
+    })
+    .collect();
+  return Ok((candles, response.next_url));
+}
+
+impl PolygonClient {
+  pub fn new(config: PolygonConfig) -> PolygonClient {
+    if let Some(cache_directory) = &config.cache_directory {
+      fs::create_dir_all(cache_directory).unwrap();
+    }
+    return PolygonClient {
+      config,
+      agent: ureq::AgentBuilder::new().timeout(Duration::from_secs(60)).build(),
+      last_request_at: None,
+    };
+  }
+
+  fn cache_path(&self, url: &str) -> Option<PathBuf> {
+    let cache_directory = self.config.cache_directory.as_ref()?;
+    return Some(cache_directory.join(format!("{:016x}.json", hash_url(url))));
+  }
+
+  fn wait_for_rate_limit(&mut self) {
+    if self.config.requests_per_minute == 0 {
+      return;
+    }
+    let min_interval = Duration::from_secs(60) / self.config.requests_per_minute;
+    if let Some(last_request_at) = self.last_request_at {
+      let elapsed = last_request_at.elapsed();
+      if elapsed < min_interval {
+        thread::sleep(min_interval - elapsed);
+      }
+    }
+    self.last_request_at = Some(Instant::now());
+  }
+
+  // url is cached without the api key so that rotating keys does not bust the cache
+  fn read_cache(&self, url: &str) -> Option<String> {
+    return fs::read_to_string(self.cache_path(url)?).ok();
+  }
+
+  fn write_cache(&self, url: &str, body: &str) {
+    if let Some(cache_path) = self.cache_path(url) {
+      fs::write(cache_path, body).unwrap();
+    }
+  }
+
+  fn fetch(&mut self, url: &str) -> String {
+    let authenticated_url = with_api_key(url, &self.config.api_key);
+    let mut attempt = 0;
+    let body = loop {
+      self.wait_for_rate_limit();
+      match self.agent.get(&authenticated_url).call() {
+        Ok(response) => break response.into_string().unwrap(),
+        Err(ureq::Error::Status(429, _)) if attempt < self.config.max_retries => {
+          // back off exponentially when we get throttled anyway
+          attempt += 1;
+          thread::sleep(Duration::from_secs(2u64.pow(attempt)));
+        }
+        Err(error) => panic!("failed to fetch {url}: {error}"),
+      }
+    };
+    return body;
+  }
+
+  // from/to are YYYY-MM-DD (or unix milliseconds) as accepted by the aggregates v2 endpoint. pages are only cached once
+  // they parsed as a successful response and the range ends before today
+  pub fn get_aggregates(&mut self, ticker: &str, multiplier: i64, timespan: &str, from: &str, to: &str) -> Vec<Candle> {
+    let candle_size_seconds = multiplier * timespan_to_seconds(timespan);
+    let base_url = self.config.base_url.trim_end_matches('/');
+    let mut url = format!("{base_url}/v2/aggs/ticker/{ticker}/range/{multiplier}/{timespan}/{from}/{to}?adjusted=false&sort=asc&limit=50000");
+    let today = Utc::now().with_timezone(&US::Eastern).date_naive();
+    let is_cacheable = is_range_settled(to, today);
+    let mut candles = vec![];
+    loop {
+      let cached_body = if is_cacheable { self.read_cache(&url) } else { None };
+      let is_cached = cached_body.is_some();
+      let body = cached_body.unwrap_or_else(|| self.fetch(&url));
+      let (page, next_url) = parse_aggregates(&body, candle_size_seconds).unwrap_or_else(|error| panic!("{url}: {error}"));
+      if is_cacheable && is_cached == false {
+        self.write_cache(&url, &body);
+      }
+      candles.extend(page);
+      match next_url {
+        Some(next_url) => url = next_url,
+        None => break,
+      }
+    }
+    return candles;
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::io::{BufRead, BufReader, Write};
+  use std::net::TcpListener;
+  use std::panic::{self, AssertUnwindSafe};
+
+  use super::*;
+
+  // serves the bodies in order, one connection each, and hands back the request paths it saw. {base_url} in a body is
+  // replaced with the server's own url
+  fn serve(bodies: Vec<String>) -> (String, thread::JoinHandle<Vec<String>>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let base_url = format!("http://{}", listener.local_addr().unwrap());
+    let server_url = base_url.clone();
+    let handle = thread::spawn(move || {
+      let mut paths = vec![];
+      for body in bodies {
+        let body = body.replace("{base_url}", &server_url);
+        let (mut stream, _) = listener.accept().unwrap();
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+        let mut request_line = String::new();
+        reader.read_line(&mut request_line).unwrap();
+        paths.push(request_line.split_whitespace().nth(1).unwrap().to_string());
+        let mut line = String::new();
+        while reader.read_line(&mut line).unwrap() > 2 {
+          line.clear();
+        }
+        let response = format!(
+          "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+          body.len()
+        );
+        stream.write_all(response.as_bytes()).unwrap();
+      }
+      return paths;
+    });
+    return (base_url, handle);
+  }
+
+  fn client(base_url: &str, name: &str) -> (PolygonClient, PathBuf) {
+    let cache_directory = std::env::temp_dir().join(format!("polygon-test-{}-{name}", std::process::id()));
+    let _ = fs::remove_dir_all(&cache_directory);
+    let client = PolygonClient::new(PolygonConfig {
+      base_url: base_url.to_string(),
+      api_key: "key".to_string(),
+      cache_directory: Some(cache_directory.clone()),
+      requests_per_minute: 0,
+      max_retries: 0,
+    });
+    return (client, cache_directory);
+  }
+
+  fn num_cached(cache_directory: &PathBuf) -> usize {
+    return fs::read_dir(cache_directory).unwrap().count();
+  }
+
+  #[test]
+  fn follows_pages_and_serves_settled_ranges_from_cache() {
+    let first_page = r#"{"status":"OK","results":[{"t":1672756200000,"o":1,"h":2,"l":0.5,"c":1.5,"v":10}],"next_url":"{base_url}/next"}"#.to_string();
+    let second_page = r#"{"status":"OK","results":[{"t":1672756260000,"o":1.5,"h":1.5,"l":1,"c":1,"v":5}]}"#.to_string();
+    let (base_url, server) = serve(vec![first_page, second_page]);
+    let (mut client, cache_directory) = client(&base_url, "pages");
+    let candles = client.get_aggregates("SPY", 1, "minute", "2023-01-03", "2023-01-03");
+    let paths = server.join().unwrap();
+    assert_eq!(paths.len(), 2);
+    assert!(paths[1].starts_with("/next?apiKey=key"));
+    assert!(paths[0].starts_with("/v2/aggs/ticker/SPY/range/1/minute/2023-01-03/2023-01-03?"));
+    assert!(paths[0].ends_with("apiKey=key"));
+    assert_eq!(candles.len(), 2);
+    assert_eq!(candles[0].start_timestamp, 1672756200);
+    assert_eq!(candles[0].end_timestamp, 1672756259);
+    assert_eq!(candles[1].close, 1.0);
+    assert_eq!(num_cached(&cache_directory), 2);
+    // nothing is listening anymore, so this can only come from the cache
+    let cached_candles = client.get_aggregates("SPY", 1, "minute", "2023-01-03", "2023-01-03");
+    assert_eq!(cached_candles.len(), 2);
+  }
+
+  #[test]
+  fn does_not_cache_error_responses() {
+    let (base_url, server) = serve(vec![r#"{"status":"ERROR","error":"Unknown API Key"}"#.to_string()]);
+    let (mut client, cache_directory) = client(&base_url, "error");
+    let result = panic::catch_unwind(AssertUnwindSafe(|| client.get_aggregates("SPY", 1, "minute", "2023-01-03", "2023-01-03")));
+    server.join().unwrap();
+    assert!(result.is_err());
+    assert_eq!(num_cached(&cache_directory), 0);
+  }
+
+  #[test]
+  fn does_not_cache_ranges_reaching_today() {
+    let (base_url, server) = serve(vec![r#"{"status":"OK","results":[]}"#.to_string()]);
+    let (mut client, cache_directory) = client(&base_url, "today");
+    let today = Utc::now().with_timezone(&US::Eastern).date_naive().format("%Y-%m-%d").to_string();
+    client.get_aggregates("SPY", 1, "minute", "2023-01-03", &today);
+    server.join().unwrap();
+    assert_eq!(num_cached(&cache_directory), 0);
+  }
+
+  #[test]
+  fn settles_ranges_by_their_end() {
+    let today = NaiveDate::from_ymd_opt(2023, 3, 1).unwrap();
+    assert!(is_range_settled("2023-02-28", today));
+    assert!(is_range_settled("2023-03-01", today) == false);
+    assert!(is_range_settled("1677643200000", today)); // 2023-02-28 23:00 eastern
+    assert!(is_range_settled("1677646800000", today) == false); // 2023-03-01 00:00 eastern
+    assert!(is_range_settled("yesterday", today) == false);
+  }
+}
@@ -0,0 +1,47 @@
+use std::collections::HashMap;
+
+use crate::search::Point;
+
+// every point at most one step away in every dimension, excluding the point itself
+fn neighbours(point: &[usize], dimension_sizes: &[usize]) -> Vec<Point> {
+  let mut neighbours: Vec<Point> = vec![vec![]];
+  for (index, size) in point.iter().zip(dimension_sizes) {
+    let mut next_neighbours = vec![];
+    for neighbour in &neighbours {
+      for candidate in index.saturating_sub(1)..=(index + 1).min(size - 1) {
+        let mut next_neighbour = neighbour.clone();
+        next_neighbour.push(candidate);
+        next_neighbours.push(next_neighbour);
+      }
+    }
+    neighbours = next_neighbours;
+  }
+  return neighbours.into_iter().filter(|neighbour| neighbour.as_slice() != point).collect();
+}
+
+// mean minus standard deviation of the score over the point and its evaluated neighbours, so a plateau beats an
+// isolated spike of the same height. infinite scores (e.g. profit factor without losses) are left out of the neighbourhood
+pub fn robustness_scores(dimension_sizes: &[usize], points: &[Point], scores: &[f64]) -> Vec<f64> {
+  let score_by_point: HashMap<&[usize], f64> = points.iter().map(|point| point.as_slice()).zip(scores.iter().copied()).collect();
+  return points
+    .iter()
+    .zip(scores)
+    .map(|(point, score)| {
+      let neighbourhood: Vec<f64> = std::iter::once(*score)
+        .chain(
+          neighbours(point, dimension_sizes)
+            .iter()
+            .filter_map(|neighbour| score_by_point.get(neighbour.as_slice()).copied()),
+        )
+        .filter(|score| score.is_finite())
+        .collect();
+      if neighbourhood.is_empty() {
+        return *score;
+      }
+      let n = neighbourhood.len() as f64;
+      let mean = neighbourhood.iter().sum::<f64>() / n;
+      let variance = neighbourhood.iter().map(|score| (score - mean).powi(2)).sum::<f64>() / n;
+      return mean - variance.sqrt();
+    })
+    .collect();
+}
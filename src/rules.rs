@@ -0,0 +1,998 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+use chrono::Timelike;
+use rand::rngs::StdRng;
+
+use crate::indicators::{
+  Adx, AdxLine, Atr, BandLine, Bollinger, Ema, HullMa, Indicator, Keltner, Macd, MacdLine, Obv, OpeningRange, PreMarketRange, PriorDay, PriorDayLine,
+  RangeLine, Rsi, SessionVwap, Sma, Stochastic, StochasticLine, VolumeSma, VwapLine, Wma,
+};
+use crate::strategy::Strategy;
+use crate::timeframe::{HigherTimeframe, Timeframe};
+use crate::{datetime_from_timestamp, Candle, Direction};
+
+// rule language, one rule per line (or separated by ;), # starts a comment:
+//
+//   long when ema($fast) crosses_above ema($slow) and rsi(14) < 70 and time between 09:45 and 15:30
+//   short when ema($fast) crosses_below ema($slow)
+//   flat when close < vwap_lower(2)
+//   long when close@15m > ema(20)@15m and close@1d > sma(50)@1d
+//   score (ema($fast) - ema($slow)) / atr(14)
+//
+// the first rule whose condition holds sets the direction, without a match the previous direction is kept.
+// an optional score line gives the strategy a continuous score (positive for long), see the experiment's [score].
+// indicators and prices only ever see fully closed candles, `time` is the time the resulting position would be taken.
+// indicator arguments are numbers or $parameters, which are bound to the experiment's [parameters] of the same name.
+// a price or indicator followed by @<count><m|h|d> runs on higher timeframe bars and only changes when one has closed
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+  Number(f64),
+  Identifier(String),
+  Parameter(String),
+  // minutes after midnight, eastern
+  Time(u32),
+  Symbol(&'static str),
+  Separator,
+}
+
+const KEYWORDS: [&str; 9] = ["score", "when", "and", "or", "not", "time", "between", "crosses_above", "crosses_below"];
+const SYMBOLS: [&str; 14] = ["<=", ">=", "==", "!=", "<", ">", "(", ")", ",", "+", "-", "*", "/", "@"];
+
+fn tokenize(text: &str) -> Result<Vec<Token>, String> {
+  let mut tokens = vec![];
+  let characters: Vec<char> = text.chars().collect();
+  let mut position = 0;
+  while position < characters.len() {
+    let character = characters[position];
+    if character == '#' {
+      while position < characters.len() && characters[position] != '\n' {
+        position += 1;
+      }
+      continue;
+    }
+    if character == '\n' || character == ';' {
+      tokens.push(Token::Separator);
+      position += 1;
+      continue;
+    }
+    if character.is_whitespace() {
+      position += 1;
+      continue;
+    }
+    if character.is_ascii_digit() || character == '.' {
+      let start = position;
+      while position < characters.len() && (characters[position].is_ascii_digit() || characters[position] == '.' || characters[position] == ':') {
+        position += 1;
+      }
+      let text: String = characters[start..position].iter().collect();
+      let token = match text.split_once(':') {
+        Some((hours, minutes)) => {
+          let hours: u32 = hours.parse().map_err(|_| format!("invalid time {text}"))?;
+          let minutes: u32 = minutes.parse().map_err(|_| format!("invalid time {text}"))?;
+          if hours > 23 || minutes > 59 {
+            return Err(format!("invalid time {text}"));
+          }
+          Token::Time(hours * 60 + minutes)
+        }
+        None => Token::Number(text.parse().map_err(|_| format!("invalid number {text}"))?),
+      };
+      tokens.push(token);
+      continue;
+    }
+    if character.is_alphabetic() || character == '_' || character == '$' {
+      let start = position;
+      position += 1;
+      while position < characters.len() && (characters[position].is_alphanumeric() || characters[position] == '_') {
+        position += 1;
+      }
+      let text: String = characters[start..position].iter().collect();
+      match text.strip_prefix('$') {
+        Some("") => return Err("expected a parameter name after $".to_string()),
+        Some(name) => tokens.push(Token::Parameter(name.to_string())),
+        None => tokens.push(Token::Identifier(text)),
+      }
+      continue;
+    }
+    let rest: String = characters[position..(position + 2).min(characters.len())].iter().collect();
+    match SYMBOLS.iter().find(|symbol| rest.starts_with(**symbol)) {
+      Some(symbol) => {
+        tokens.push(Token::Symbol(symbol));
+        position += symbol.len();
+      }
+      None => return Err(format!("unexpected character {character:?}")),
+    }
+  }
+  return Ok(tokens);
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PriceField {
+  Open,
+  High,
+  Low,
+  Close,
+  Volume,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ArithmeticOperator {
+  Add,
+  Subtract,
+  Multiply,
+  Divide,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ComparisonOperator {
+  Less,
+  LessOrEqual,
+  Greater,
+  GreaterOrEqual,
+  Equal,
+  NotEqual,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CrossDirection {
+  Above,
+  Below,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Argument {
+  Number(f64),
+  Parameter(String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expression {
+  Number(f64),
+  Parameter(String),
+  Price(PriceField),
+  Indicator { name: String, arguments: Vec<Argument> },
+  // price or indicator on higher timeframe bars
+  HigherTimeframe(Timeframe, Box<Expression>),
+  Negate(Box<Expression>),
+  Arithmetic(ArithmeticOperator, Box<Expression>, Box<Expression>),
+  Compare(ComparisonOperator, Box<Expression>, Box<Expression>),
+  Cross(CrossDirection, Box<Expression>, Box<Expression>),
+  And(Box<Expression>, Box<Expression>),
+  Or(Box<Expression>, Box<Expression>),
+  Not(Box<Expression>),
+  // start inclusive, end exclusive, minutes after midnight eastern
+  TimeBetween(u32, u32),
+}
+
+impl Expression {
+  fn is_condition(&self) -> bool {
+    match self {
+      Expression::Compare(..) | Expression::Cross(..) | Expression::And(..) | Expression::Or(..) | Expression::Not(..) | Expression::TimeBetween(..) => {
+        return true
+      }
+      _ => return false,
+    }
+  }
+
+  fn collect_parameters(&self, parameters: &mut BTreeSet<String>) {
+    match self {
+      Expression::Parameter(name) => {
+        parameters.insert(name.clone());
+      }
+      Expression::Indicator { arguments, .. } => {
+        for argument in arguments {
+          if let Argument::Parameter(name) = argument {
+            parameters.insert(name.clone());
+          }
+        }
+      }
+      Expression::HigherTimeframe(_, inner) | Expression::Negate(inner) | Expression::Not(inner) => inner.collect_parameters(parameters),
+      Expression::Arithmetic(_, left, right)
+      | Expression::Compare(_, left, right)
+      | Expression::Cross(_, left, right)
+      | Expression::And(left, right)
+      | Expression::Or(left, right) => {
+        left.collect_parameters(parameters);
+        right.collect_parameters(parameters);
+      }
+      Expression::Number(_) | Expression::Price(_) | Expression::TimeBetween(..) => {}
+    }
+  }
+}
+
+#[derive(Debug, Clone)]
+pub struct Rule {
+  pub direction: Direction,
+  pub condition: Expression,
+}
+
+struct Parser {
+  tokens: Vec<Token>,
+  position: usize,
+}
+
+impl Parser {
+  fn peek(&self) -> Option<&Token> {
+    return self.tokens.get(self.position);
+  }
+
+  fn advance(&mut self) -> Option<Token> {
+    let token = self.tokens.get(self.position).cloned();
+    self.position += 1;
+    return token;
+  }
+
+  fn is_keyword(&self, keyword: &str) -> bool {
+    return matches!(self.peek(), Some(Token::Identifier(text)) if text == keyword);
+  }
+
+  fn is_symbol(&self, symbol: &str) -> bool {
+    return matches!(self.peek(), Some(Token::Symbol(text)) if *text == symbol);
+  }
+
+  fn expect_keyword(&mut self, keyword: &str) -> Result<(), String> {
+    if self.is_keyword(keyword) == false {
+      return Err(format!("expected {keyword}, found {:?}", self.peek()));
+    }
+    self.position += 1;
+    return Ok(());
+  }
+
+  fn expect_symbol(&mut self, symbol: &str) -> Result<(), String> {
+    if self.is_symbol(symbol) == false {
+      return Err(format!("expected {symbol}, found {:?}", self.peek()));
+    }
+    self.position += 1;
+    return Ok(());
+  }
+
+  fn rule(&mut self) -> Result<Rule, String> {
+    let direction = match self.advance() {
+      Some(Token::Identifier(text)) if text == "long" => Direction::Long,
+      Some(Token::Identifier(text)) if text == "short" => Direction::Short,
+      Some(Token::Identifier(text)) if text == "flat" => Direction::Flat,
+      token => return Err(format!("expected long, short, flat or score, found {token:?}")),
+    };
+    self.expect_keyword("when")?;
+    let condition = self.or()?;
+    if condition.is_condition() == false {
+      return Err("a rule needs a condition, not a number".to_string());
+    }
+    return Ok(Rule { direction, condition });
+  }
+
+  fn or(&mut self) -> Result<Expression, String> {
+    let mut left = self.and()?;
+    while self.is_keyword("or") {
+      self.position += 1;
+      let right = self.and()?;
+      left = Expression::Or(Box::new(condition(left)?), Box::new(condition(right)?));
+    }
+    return Ok(left);
+  }
+
+  fn and(&mut self) -> Result<Expression, String> {
+    let mut left = self.not()?;
+    while self.is_keyword("and") {
+      self.position += 1;
+      let right = self.not()?;
+      left = Expression::And(Box::new(condition(left)?), Box::new(condition(right)?));
+    }
+    return Ok(left);
+  }
+
+  fn not(&mut self) -> Result<Expression, String> {
+    if self.is_keyword("not") {
+      self.position += 1;
+      return Ok(Expression::Not(Box::new(condition(self.not()?)?)));
+    }
+    return self.comparison();
+  }
+
+  fn comparison(&mut self) -> Result<Expression, String> {
+    if self.is_keyword("time") {
+      self.position += 1;
+      self.expect_keyword("between")?;
+      let start = self.time()?;
+      self.expect_keyword("and")?;
+      let end = self.time()?;
+      return Ok(Expression::TimeBetween(start, end));
+    }
+    let left = self.sum()?;
+    let operator = match self.peek() {
+      Some(Token::Symbol("<")) => Some(ComparisonOperator::Less),
+      Some(Token::Symbol("<=")) => Some(ComparisonOperator::LessOrEqual),
+      Some(Token::Symbol(">")) => Some(ComparisonOperator::Greater),
+      Some(Token::Symbol(">=")) => Some(ComparisonOperator::GreaterOrEqual),
+      Some(Token::Symbol("==")) => Some(ComparisonOperator::Equal),
+      Some(Token::Symbol("!=")) => Some(ComparisonOperator::NotEqual),
+      _ => None,
+    };
+    if let Some(operator) = operator {
+      self.position += 1;
+      let right = self.sum()?;
+      return Ok(Expression::Compare(operator, Box::new(number(left)?), Box::new(number(right)?)));
+    }
+    let cross_direction = if self.is_keyword("crosses_above") {
+      Some(CrossDirection::Above)
+    } else if self.is_keyword("crosses_below") {
+      Some(CrossDirection::Below)
+    } else {
+      None
+    };
+    if let Some(cross_direction) = cross_direction {
+      self.position += 1;
+      let right = self.sum()?;
+      return Ok(Expression::Cross(cross_direction, Box::new(number(left)?), Box::new(number(right)?)));
+    }
+    return Ok(left);
+  }
+
+  fn time(&mut self) -> Result<u32, String> {
+    match self.advance() {
+      Some(Token::Time(minutes)) => return Ok(minutes),
+      token => return Err(format!("expected a time like 09:30, found {token:?}")),
+    }
+  }
+
+  fn sum(&mut self) -> Result<Expression, String> {
+    let mut left = self.product()?;
+    loop {
+      let operator = if self.is_symbol("+") {
+        ArithmeticOperator::Add
+      } else if self.is_symbol("-") {
+        ArithmeticOperator::Subtract
+      } else {
+        return Ok(left);
+      };
+      self.position += 1;
+      let right = self.product()?;
+      left = Expression::Arithmetic(operator, Box::new(number(left)?), Box::new(number(right)?));
+    }
+  }
+
+  fn product(&mut self) -> Result<Expression, String> {
+    let mut left = self.unary()?;
+    loop {
+      let operator = if self.is_symbol("*") {
+        ArithmeticOperator::Multiply
+      } else if self.is_symbol("/") {
+        ArithmeticOperator::Divide
+      } else {
+        return Ok(left);
+      };
+      self.position += 1;
+      let right = self.unary()?;
+      left = Expression::Arithmetic(operator, Box::new(number(left)?), Box::new(number(right)?));
+    }
+  }
+
+  fn unary(&mut self) -> Result<Expression, String> {
+    if self.is_symbol("-") {
+      self.position += 1;
+      return Ok(Expression::Negate(Box::new(number(self.unary()?)?)));
+    }
+    return self.primary();
+  }
+
+  fn primary(&mut self) -> Result<Expression, String> {
+    match self.advance() {
+      Some(Token::Number(value)) => return Ok(Expression::Number(value)),
+      Some(Token::Parameter(name)) => return Ok(Expression::Parameter(name)),
+      Some(Token::Symbol("(")) => {
+        let inner = self.or()?;
+        self.expect_symbol(")")?;
+        return Ok(inner);
+      }
+      Some(Token::Identifier(name)) => {
+        let price_field = match name.as_str() {
+          "open" => Some(PriceField::Open),
+          "high" => Some(PriceField::High),
+          "low" => Some(PriceField::Low),
+          "close" => Some(PriceField::Close),
+          "volume" => Some(PriceField::Volume),
+          _ => None,
+        };
+        if let Some(price_field) = price_field {
+          return self.timeframe(Expression::Price(price_field));
+        }
+        if KEYWORDS.contains(&name.as_str()) {
+          return Err(format!("expected a value, found {name}"));
+        }
+        self.expect_symbol("(")?;
+        let mut arguments = vec![];
+        while self.is_symbol(")") == false {
+          if arguments.is_empty() == false {
+            self.expect_symbol(",")?;
+          }
+          match self.advance() {
+            Some(Token::Number(value)) => arguments.push(Argument::Number(value)),
+            Some(Token::Parameter(name)) => arguments.push(Argument::Parameter(name)),
+            token => return Err(format!("indicator arguments must be numbers or $parameters, found {token:?}")),
+          }
+        }
+        self.expect_symbol(")")?;
+        // fail on unknown names and wrong argument counts at parse time rather than per combination
+        build_indicator(&name, &arguments.iter().map(|_| 1.0).collect::<Vec<f64>>())?;
+        return self.timeframe(Expression::Indicator { name, arguments });
+      }
+      token => return Err(format!("unexpected {token:?}")),
+    }
+  }
+
+  // optional @15m / @1h / @1d suffix
+  fn timeframe(&mut self, expression: Expression) -> Result<Expression, String> {
+    if self.is_symbol("@") == false {
+      return Ok(expression);
+    }
+    self.position += 1;
+    let count = match self.advance() {
+      Some(Token::Number(count)) => count,
+      token => return Err(format!("expected a timeframe like 15m after @, found {token:?}")),
+    };
+    let unit = match self.advance() {
+      Some(Token::Identifier(unit)) => unit,
+      token => return Err(format!("expected a timeframe unit after @{count}, found {token:?}")),
+    };
+    let timeframe = Timeframe::parse(count, &unit)?;
+    return Ok(Expression::HigherTimeframe(timeframe, Box::new(expression)));
+  }
+}
+
+fn condition(expression: Expression) -> Result<Expression, String> {
+  if expression.is_condition() {
+    return Ok(expression);
+  }
+  return Err(format!("expected a condition, found {expression:?}"));
+}
+
+fn number(expression: Expression) -> Result<Expression, String> {
+  if expression.is_condition() {
+    return Err(format!("expected a number, found {expression:?}"));
+  }
+  return Ok(expression);
+}
+
+pub fn build_indicator(name: &str, arguments: &[f64]) -> Result<Box<dyn Indicator>, String> {
+  let expect = |count: usize| {
+    if arguments.len() != count {
+      return Err(format!("{name} takes {count} arguments, got {}", arguments.len()));
+    }
+    return Ok(());
+  };
+  let periods = |index: usize| arguments[index].round().max(1.0) as usize;
+  let indicator: Box<dyn Indicator> = match name {
+    "sma" => expect(1).map(|_| Box::new(Sma::new(periods(0))) as Box<dyn Indicator>)?,
+    "ema" => expect(1).map(|_| Box::new(Ema::new(periods(0))) as Box<dyn Indicator>)?,
+    "wma" => expect(1).map(|_| Box::new(Wma::new(periods(0))) as Box<dyn Indicator>)?,
+    "hma" => expect(1).map(|_| Box::new(HullMa::new(periods(0))) as Box<dyn Indicator>)?,
+    "rsi" => expect(1).map(|_| Box::new(Rsi::new(periods(0))) as Box<dyn Indicator>)?,
+    "atr" => expect(1).map(|_| Box::new(Atr::new(periods(0))) as Box<dyn Indicator>)?,
+    "obv" => expect(0).map(|_| Box::new(Obv::new()) as Box<dyn Indicator>)?,
+    "volume_sma" => expect(1).map(|_| Box::new(VolumeSma::new(periods(0))) as Box<dyn Indicator>)?,
+    "macd" | "macd_signal" | "macd_histogram" => {
+      expect(3)?;
+      let line = match name {
+        "macd" => MacdLine::Macd,
+        "macd_signal" => MacdLine::Signal,
+        _ => MacdLine::Histogram,
+      };
+      Box::new(Macd::new(periods(0), periods(1), periods(2), line))
+    }
+    "bb_upper" | "bb_middle" | "bb_lower" | "kc_upper" | "kc_middle" | "kc_lower" => {
+      expect(2)?;
+      let line = match &name[3..] {
+        "upper" => BandLine::Upper,
+        "middle" => BandLine::Middle,
+        _ => BandLine::Lower,
+      };
+      if name.starts_with("bb") {
+        Box::new(Bollinger::new(periods(0), arguments[1], line))
+      } else {
+        Box::new(Keltner::new(periods(0), arguments[1], line))
+      }
+    }
+    "stoch_k" | "stoch_d" => {
+      expect(2)?;
+      let line = if name == "stoch_k" { StochasticLine::K } else { StochasticLine::D };
+      Box::new(Stochastic::new(periods(0), periods(1), line))
+    }
+    "adx" | "plus_di" | "minus_di" => {
+      expect(1)?;
+      let line = match name {
+        "adx" => AdxLine::Adx,
+        "plus_di" => AdxLine::PlusDi,
+        _ => AdxLine::MinusDi,
+      };
+      Box::new(Adx::new(periods(0), line))
+    }
+    "vwap" => expect(0).map(|_| Box::new(SessionVwap::new(VwapLine::Vwap, 0.0)) as Box<dyn Indicator>)?,
+    "vwap_upper" => expect(1).map(|_| Box::new(SessionVwap::new(VwapLine::Upper, arguments[0])) as Box<dyn Indicator>)?,
+    "vwap_lower" => expect(1).map(|_| Box::new(SessionVwap::new(VwapLine::Lower, arguments[0])) as Box<dyn Indicator>)?,
+    "or_high" => expect(1).map(|_| Box::new(OpeningRange::new(periods(0), RangeLine::High)) as Box<dyn Indicator>)?,
+    "or_low" => expect(1).map(|_| Box::new(OpeningRange::new(periods(0), RangeLine::Low)) as Box<dyn Indicator>)?,
+    "prior_high" => expect(0).map(|_| Box::new(PriorDay::new(PriorDayLine::High)) as Box<dyn Indicator>)?,
+    "prior_low" => expect(0).map(|_| Box::new(PriorDay::new(PriorDayLine::Low)) as Box<dyn Indicator>)?,
+    "prior_close" => expect(0).map(|_| Box::new(PriorDay::new(PriorDayLine::Close)) as Box<dyn Indicator>)?,
+    "premarket_high" => expect(0).map(|_| Box::new(PreMarketRange::new(RangeLine::High)) as Box<dyn Indicator>)?,
+    "premarket_low" => expect(0).map(|_| Box::new(PreMarketRange::new(RangeLine::Low)) as Box<dyn Indicator>)?,
+    _ => return Err(format!("unknown indicator {name}")),
+  };
+  return Ok(indicator);
+}
+
+#[derive(Debug, Clone)]
+pub struct RuleSet {
+  pub rules: Vec<Rule>,
+  pub score: Option<Expression>,
+}
+
+impl RuleSet {
+  pub fn parse(text: &str) -> Result<RuleSet, String> {
+    let mut rules = vec![];
+    let mut score = None;
+    let tokens = tokenize(text)?;
+    for (index, line) in tokens
+      .split(|token| *token == Token::Separator)
+      .filter(|line| line.is_empty() == false)
+      .enumerate()
+    {
+      let mut parser = Parser {
+        tokens: line.to_vec(),
+        position: 0,
+      };
+      let line_number = index + 1;
+      if parser.is_keyword("score") {
+        parser.position += 1;
+        if score.is_some() {
+          return Err(format!("rule {line_number}: only one score line is allowed"));
+        }
+        score = Some(parser.or().and_then(number).map_err(|error| format!("rule {line_number}: {error}"))?);
+      } else {
+        rules.push(parser.rule().map_err(|error| format!("rule {line_number}: {error}"))?);
+      }
+      if let Some(token) = parser.peek() {
+        return Err(format!("rule {line_number}: unexpected {token:?} after rule"));
+      }
+    }
+    if rules.is_empty() && score.is_none() {
+      return Err("no rules".to_string());
+    }
+    return Ok(RuleSet { rules, score });
+  }
+
+  // sorted names of every $parameter
+  pub fn parameter_names(&self) -> Vec<String> {
+    let mut parameters = BTreeSet::new();
+    for rule in &self.rules {
+      rule.condition.collect_parameters(&mut parameters);
+    }
+    if let Some(score) = &self.score {
+      score.collect_parameters(&mut parameters);
+    }
+    return parameters.into_iter().collect();
+  }
+
+  pub fn build(&self, parameters: &BTreeMap<String, f64>) -> RuleStrategy {
+    let mut compiler = Compiler::new(parameters);
+    let rules = self
+      .rules
+      .iter()
+      .map(|rule| (rule.direction.clone(), compiler.condition(&rule.condition)))
+      .collect();
+    let score = self.score.as_ref().map(|score| compiler.numeric(score));
+    return RuleStrategy {
+      evaluator: compiler.evaluator(),
+      rules,
+      score,
+      direction: Direction::Flat,
+    };
+  }
+}
+
+// a single condition in the rule language, e.g. "atr(14) > 0.5" or "not time between 12:00 and 13:00"
+#[derive(Debug, Clone)]
+pub struct Filter {
+  pub condition: Expression,
+}
+
+impl Filter {
+  pub fn parse(text: &str) -> Result<Filter, String> {
+    let tokens = tokenize(text)?;
+    if tokens.contains(&Token::Separator) {
+      return Err(format!("filter {text:?} must be a single condition"));
+    }
+    let mut parser = Parser { tokens, position: 0 };
+    let condition = condition(parser.or()?).map_err(|error| format!("filter {text:?}: {error}"))?;
+    if let Some(token) = parser.peek() {
+      return Err(format!("filter {text:?}: unexpected {token:?} after condition"));
+    }
+    return Ok(Filter { condition });
+  }
+
+  pub fn parameter_names(&self) -> Vec<String> {
+    let mut parameters = BTreeSet::new();
+    self.condition.collect_parameters(&mut parameters);
+    return parameters.into_iter().collect();
+  }
+
+  pub fn build(&self, parameters: &BTreeMap<String, f64>) -> RuleFilter {
+    let mut compiler = Compiler::new(parameters);
+    let condition = compiler.condition(&self.condition);
+    return RuleFilter {
+      evaluator: compiler.evaluator(),
+      condition,
+    };
+  }
+}
+
+// parameters resolved and indicators instantiated, identical indicator calls share one instance
+enum Numeric {
+  Constant(f64),
+  Price(PriceField),
+  Indicator(usize),
+  Negate(Box<Numeric>),
+  Arithmetic(ArithmeticOperator, Box<Numeric>, Box<Numeric>),
+}
+
+enum Condition {
+  Compare(ComparisonOperator, Numeric, Numeric),
+  // index into the crosses, which are updated on every closed candle whether or not their rule gets evaluated
+  Cross(usize),
+  And(Box<Condition>, Box<Condition>),
+  Or(Box<Condition>, Box<Condition>),
+  Not(Box<Condition>),
+  TimeBetween(u32, u32),
+}
+
+struct Cross {
+  direction: CrossDirection,
+  left: Numeric,
+  right: Numeric,
+  previous: Option<(f64, f64)>,
+  is_crossed: bool,
+}
+
+struct Compiler<'a> {
+  parameters: &'a BTreeMap<String, f64>,
+  indicators: Vec<Box<dyn Indicator>>,
+  indicator_slots: HashMap<String, usize>,
+  crosses: Vec<Cross>,
+}
+
+impl Compiler<'_> {
+  fn new(parameters: &BTreeMap<String, f64>) -> Compiler<'_> {
+    return Compiler {
+      parameters,
+      indicators: vec![],
+      indicator_slots: HashMap::new(),
+      crosses: vec![],
+    };
+  }
+
+  fn evaluator(self) -> Evaluator {
+    return Evaluator {
+      indicator_values: vec![f64::NAN; self.indicators.len()],
+      indicators: self.indicators,
+      crosses: self.crosses,
+      last_candle: None,
+    };
+  }
+
+  fn parameter(&self, name: &str) -> f64 {
+    return *self.parameters.get(name).unwrap_or_else(|| panic!("no value for ${name}"));
+  }
+
+  fn numeric(&mut self, expression: &Expression) -> Numeric {
+    match expression {
+      Expression::Number(value) => return Numeric::Constant(*value),
+      Expression::Parameter(name) => return Numeric::Constant(self.parameter(name)),
+      Expression::Price(field) => return Numeric::Price(*field),
+      Expression::Indicator { .. } | Expression::HigherTimeframe(..) => {
+        let (key, indicator) = self.indicator(expression);
+        if let Some(slot) = self.indicator_slots.get(&key) {
+          return Numeric::Indicator(*slot);
+        }
+        self.indicators.push(indicator);
+        self.indicator_slots.insert(key, self.indicators.len() - 1);
+        return Numeric::Indicator(self.indicators.len() - 1);
+      }
+      Expression::Negate(inner) => return Numeric::Negate(Box::new(self.numeric(inner))),
+      Expression::Arithmetic(operator, left, right) => return Numeric::Arithmetic(*operator, Box::new(self.numeric(left)), Box::new(self.numeric(right))),
+      _ => unreachable!("conditions are rejected by the parser where numbers are expected"),
+    }
+  }
+
+  // sharing key and instance of an indicator, or a price on higher timeframe bars
+  fn indicator(&self, expression: &Expression) -> (String, Box<dyn Indicator>) {
+    match expression {
+      Expression::Price(field) => return (format!("{field:?}"), Box::new(PriceIndicator(*field))),
+      Expression::Indicator { name, arguments } => {
+        let arguments: Vec<f64> = arguments
+          .iter()
+          .map(|argument| match argument {
+            Argument::Number(value) => *value,
+            Argument::Parameter(name) => self.parameter(name),
+          })
+          .collect();
+        return (format!("{name}{arguments:?}"), build_indicator(name, &arguments).unwrap());
+      }
+      Expression::HigherTimeframe(timeframe, inner) => {
+        let (key, indicator) = self.indicator(inner);
+        return (format!("{key}@{timeframe:?}"), Box::new(HigherTimeframe::new(*timeframe, indicator)));
+      }
+      _ => unreachable!("the parser only puts prices and indicators on a timeframe"),
+    }
+  }
+
+  fn condition(&mut self, expression: &Expression) -> Condition {
+    match expression {
+      Expression::Compare(operator, left, right) => return Condition::Compare(*operator, self.numeric(left), self.numeric(right)),
+      Expression::Cross(direction, left, right) => {
+        let cross = Cross {
+          direction: *direction,
+          left: self.numeric(left),
+          right: self.numeric(right),
+          previous: None,
+          is_crossed: false,
+        };
+        self.crosses.push(cross);
+        return Condition::Cross(self.crosses.len() - 1);
+      }
+      Expression::And(left, right) => return Condition::And(Box::new(self.condition(left)), Box::new(self.condition(right))),
+      Expression::Or(left, right) => return Condition::Or(Box::new(self.condition(left)), Box::new(self.condition(right))),
+      Expression::Not(inner) => return Condition::Not(Box::new(self.condition(inner))),
+      Expression::TimeBetween(start, end) => return Condition::TimeBetween(*start, *end),
+      _ => unreachable!("numbers are rejected by the parser where conditions are expected"),
+    }
+  }
+}
+
+// indicator and cross state of compiled conditions, fed every closed candle
+struct Evaluator {
+  indicators: Vec<Box<dyn Indicator>>,
+  indicator_values: Vec<f64>,
+  crosses: Vec<Cross>,
+  last_candle: Option<Candle>,
+}
+
+pub struct RuleStrategy {
+  evaluator: Evaluator,
+  rules: Vec<(Direction, Condition)>,
+  score: Option<Numeric>,
+  direction: Direction,
+}
+
+// vetoes whatever it filters while its condition does not hold, including before the first closed candle
+pub struct RuleFilter {
+  evaluator: Evaluator,
+  condition: Condition,
+}
+
+fn price(field: PriceField, candle: &Candle) -> f64 {
+  match field {
+    PriceField::Open => return candle.open,
+    PriceField::High => return candle.high,
+    PriceField::Low => return candle.low,
+    PriceField::Close => return candle.close,
+    PriceField::Volume => return candle.volume as f64,
+  }
+}
+
+// a price as an indicator, so it can run on higher timeframe bars
+struct PriceIndicator(PriceField);
+
+impl Indicator for PriceIndicator {
+  fn next(&mut self, candle: &Candle) -> f64 {
+    return price(self.0, candle);
+  }
+}
+
+fn evaluate_numeric(numeric: &Numeric, candle: &Candle, indicator_values: &[f64]) -> f64 {
+  match numeric {
+    Numeric::Constant(value) => return *value,
+    Numeric::Price(field) => return price(*field, candle),
+    Numeric::Indicator(slot) => return indicator_values[*slot],
+    Numeric::Negate(inner) => return -evaluate_numeric(inner, candle, indicator_values),
+    Numeric::Arithmetic(operator, left, right) => {
+      let left = evaluate_numeric(left, candle, indicator_values);
+      let right = evaluate_numeric(right, candle, indicator_values);
+      match operator {
+        ArithmeticOperator::Add => return left + right,
+        ArithmeticOperator::Subtract => return left - right,
+        ArithmeticOperator::Multiply => return left * right,
+        ArithmeticOperator::Divide => return left / right,
+      }
+    }
+  }
+}
+
+impl Evaluator {
+  // nan never satisfies a comparison
+  fn evaluate_condition(&self, condition: &Condition, candle: &Candle, minute_of_day: u32) -> bool {
+    match condition {
+      Condition::Compare(operator, left, right) => {
+        let left = evaluate_numeric(left, candle, &self.indicator_values);
+        let right = evaluate_numeric(right, candle, &self.indicator_values);
+        match operator {
+          ComparisonOperator::Less => return left < right,
+          ComparisonOperator::LessOrEqual => return left <= right,
+          ComparisonOperator::Greater => return left > right,
+          ComparisonOperator::GreaterOrEqual => return left >= right,
+          ComparisonOperator::Equal => return left == right,
+          ComparisonOperator::NotEqual => return left != right,
+        }
+      }
+      Condition::Cross(index) => return self.crosses[*index].is_crossed,
+      Condition::And(left, right) => return self.evaluate_condition(left, candle, minute_of_day) && self.evaluate_condition(right, candle, minute_of_day),
+      Condition::Or(left, right) => return self.evaluate_condition(left, candle, minute_of_day) || self.evaluate_condition(right, candle, minute_of_day),
+      Condition::Not(inner) => return self.evaluate_condition(inner, candle, minute_of_day) == false,
+      Condition::TimeBetween(start, end) => return *start <= minute_of_day && minute_of_day < *end,
+    }
+  }
+
+  fn on_closed_candle(&mut self, candle: &Candle) {
+    for (value, indicator) in self.indicator_values.iter_mut().zip(self.indicators.iter_mut()) {
+      *value = indicator.next(candle);
+    }
+    for cross in self.crosses.iter_mut() {
+      let left = evaluate_numeric(&cross.left, candle, &self.indicator_values);
+      let right = evaluate_numeric(&cross.right, candle, &self.indicator_values);
+      cross.is_crossed = match (cross.previous, cross.direction) {
+        (Some((previous_left, previous_right)), CrossDirection::Above) => previous_left <= previous_right && left > right,
+        (Some((previous_left, previous_right)), CrossDirection::Below) => previous_left >= previous_right && left < right,
+        (None, _) => false,
+      };
+      cross.previous = Some((left, right));
+    }
+    self.last_candle = Some(*candle);
+  }
+
+  // none before the first closed candle, `time` is the decision timestamp
+  fn is_satisfied(&self, condition: &Condition, timestamp: i64) -> Option<bool> {
+    let candle = self.last_candle?;
+    let eastern = datetime_from_timestamp(timestamp);
+    let minute_of_day = eastern.hour() * 60 + eastern.minute();
+    return Some(self.evaluate_condition(condition, &candle, minute_of_day));
+  }
+}
+
+impl Strategy for RuleStrategy {
+  fn on_closed_candle(&mut self, candle: &Candle) {
+    self.evaluator.on_closed_candle(candle);
+  }
+
+  fn direction(&mut self, timestamp: i64, _current_candle: &Candle, _rng: &mut StdRng) -> Direction {
+    for (direction, condition) in &self.rules {
+      match self.evaluator.is_satisfied(condition, timestamp) {
+        Some(true) => {
+          self.direction = direction.clone();
+          break;
+        }
+        Some(false) => continue,
+        None => return Direction::Flat,
+      }
+    }
+    return self.direction.clone();
+  }
+
+  // none before the first closed candle, nan until its indicators are ready
+  fn score(&self) -> Option<f64> {
+    let candle = self.evaluator.last_candle?;
+    return Some(evaluate_numeric(self.score.as_ref()?, &candle, &self.evaluator.indicator_values));
+  }
+}
+
+impl RuleFilter {
+  pub fn on_closed_candle(&mut self, candle: &Candle) {
+    self.evaluator.on_closed_candle(candle);
+  }
+
+  pub fn allows(&self, timestamp: i64) -> bool {
+    return self.evaluator.is_satisfied(&self.condition, timestamp) == Some(true);
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn parameter(name: &str) -> Box<Expression> {
+    return Box::new(Expression::Parameter(name.to_string()));
+  }
+
+  fn ema(name: &str) -> Box<Expression> {
+    return Box::new(Expression::Indicator {
+      name: "ema".to_string(),
+      arguments: vec![Argument::Parameter(name.to_string())],
+    });
+  }
+
+  fn error_of(text: &str) -> String {
+    return RuleSet::parse(text).unwrap_err();
+  }
+
+  #[test]
+  fn parses_rules_in_order_with_comments_and_separators() {
+    let rule_set =
+      RuleSet::parse("# entries\nlong when ema($fast) crosses_above ema($slow) # cross\n\nshort when close < 10; flat when time between 12:00 and 13:30")
+        .unwrap();
+    let directions: Vec<Direction> = rule_set.rules.iter().map(|rule| rule.direction.clone()).collect();
+    assert_eq!(directions, [Direction::Long, Direction::Short, Direction::Flat]);
+    assert_eq!(rule_set.rules[0].condition, Expression::Cross(CrossDirection::Above, ema("fast"), ema("slow")));
+    assert_eq!(
+      rule_set.rules[1].condition,
+      Expression::Compare(
+        ComparisonOperator::Less,
+        Box::new(Expression::Price(PriceField::Close)),
+        Box::new(Expression::Number(10.0))
+      )
+    );
+    assert_eq!(rule_set.rules[2].condition, Expression::TimeBetween(12 * 60, 13 * 60 + 30));
+    assert_eq!(rule_set.score, None);
+    assert_eq!(rule_set.parameter_names(), ["fast", "slow"]);
+  }
+
+  #[test]
+  fn and_binds_tighter_than_or_and_products_tighter_than_sums() {
+    let rule_set = RuleSet::parse("long when $a > 1 or $b > 2 and not $c > 3\nscore $a + $b * -$c").unwrap();
+    let greater = |name: &str, value: f64| {
+      Box::new(Expression::Compare(
+        ComparisonOperator::Greater,
+        parameter(name),
+        Box::new(Expression::Number(value)),
+      ))
+    };
+    assert_eq!(
+      rule_set.rules[0].condition,
+      Expression::Or(
+        greater("a", 1.0),
+        Box::new(Expression::And(greater("b", 2.0), Box::new(Expression::Not(greater("c", 3.0)))))
+      )
+    );
+    let product = Expression::Arithmetic(ArithmeticOperator::Multiply, parameter("b"), Box::new(Expression::Negate(parameter("c"))));
+    assert_eq!(
+      rule_set.score,
+      Some(Expression::Arithmetic(ArithmeticOperator::Add, parameter("a"), Box::new(product)))
+    );
+  }
+
+  #[test]
+  fn parses_higher_timeframes() {
+    let rule_set = RuleSet::parse("long when close@15m > ema(20)@1d").unwrap();
+    let Expression::Compare(_, left, right) = &rule_set.rules[0].condition else {
+      panic!("expected a comparison");
+    };
+    assert!(matches!(&**left, Expression::HigherTimeframe(Timeframe::Seconds(900), inner) if **inner == Expression::Price(PriceField::Close)));
+    assert!(matches!(&**right, Expression::HigherTimeframe(Timeframe::Session, _)));
+  }
+
+  #[test]
+  fn a_score_line_alone_is_a_rule_set() {
+    let rule_set = RuleSet::parse("score (ema($fast) - ema($slow)) / atr(14)").unwrap();
+    assert!(rule_set.rules.is_empty());
+    assert_eq!(rule_set.parameter_names(), ["fast", "slow"]);
+  }
+
+  #[test]
+  fn reports_errors_with_their_rule_number() {
+    assert_eq!(error_of(""), "no rules");
+    assert_eq!(error_of("# only a comment"), "no rules");
+    assert_eq!(
+      error_of("long when close > 1\nbuy when close > 1"),
+      "rule 2: expected long, short, flat or score, found Some(Identifier(\"buy\"))"
+    );
+    assert_eq!(error_of("long when close + 1"), "rule 1: a rule needs a condition, not a number");
+    assert_eq!(
+      error_of("score close > 1"),
+      "rule 1: expected a number, found Compare(Greater, Price(Close), Number(1.0))"
+    );
+    assert_eq!(error_of("score close\nscore open"), "rule 2: only one score line is allowed");
+    assert_eq!(error_of("long when close > 1 1"), "rule 1: unexpected Number(1.0) after rule");
+    assert_eq!(error_of("long when time between 09:30 and 25:00"), "invalid time 25:00");
+    assert_eq!(error_of("long when close > $"), "expected a parameter name after $");
+    assert!(error_of("long when foo(1) > 1").starts_with("rule 1: "));
+    assert!(error_of("long when ema(1, 2) > 1").starts_with("rule 1: "));
+    assert!(error_of("long when ema(close) > 1").contains("indicator arguments must be numbers or $parameters"));
+  }
+}
@@ -0,0 +1,199 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use serde::Deserialize;
+
+use crate::strategy::Setting;
+use crate::Direction;
+
+// what to do with the continuous score a strategy gives next to its direction (positive for long, negative for short).
+// settings are numbers or "$name" to optimize them as signal parameters
+#[derive(Debug, Clone, Deserialize)]
+pub struct ScoreParameters {
+  // replaces the strategy's direction: long from a score at or above it, short at or below its negative, flat in between
+  #[serde(default)]
+  pub entry_threshold: Option<Setting>,
+  // a held side is kept until the score falls back through this, entry_threshold when omitted
+  #[serde(default)]
+  pub exit_threshold: Option<Setting>,
+  // score magnitude that trades a whole unit, smaller ones trade a proportionally smaller unit. every unit is whole when
+  // omitted
+  #[serde(default)]
+  pub full_size_score: Option<Setting>,
+  // bounds on the size of a unit, also the size when there is no score. above 0 with full_size_score so every entry
+  // trades something
+  #[serde(default)]
+  pub min_size: f64,
+  #[serde(default = "default_max_size")]
+  pub max_size: f64,
+}
+
+fn default_max_size() -> f64 {
+  return 1.0;
+}
+
+impl ScoreParameters {
+  pub fn validate(&self) -> Result<(), String> {
+    for setting in [&self.entry_threshold, &self.exit_threshold, &self.full_size_score].into_iter().flatten() {
+      setting.validate()?;
+    }
+    if self.exit_threshold.is_some() && self.entry_threshold.is_none() {
+      return Err("exit_threshold needs an entry_threshold".to_string());
+    }
+    if (0.0 <= self.min_size && self.min_size <= self.max_size) == false {
+      return Err(format!("expected 0 <= min_size <= max_size, got {} and {}", self.min_size, self.max_size));
+    }
+    if self.full_size_score.is_some() && self.min_size <= 0.0 {
+      return Err("min_size must be above 0 when full_size_score sizes entries".to_string());
+    }
+    return Ok(());
+  }
+
+  // checks the settings resolved for one combination of the parameters they name
+  pub fn validate_values(&self, values: &BTreeMap<String, f64>) -> Result<(), String> {
+    let score = self.build(values);
+    if let Some(entry_threshold) = score.entry_threshold {
+      if entry_threshold < 0.0 {
+        return Err(format!("entry_threshold must be at least 0, got {entry_threshold}"));
+      }
+    }
+    if let (Some(entry_threshold), Some(exit_threshold)) = (score.entry_threshold, score.exit_threshold) {
+      if (0.0 <= exit_threshold && exit_threshold <= entry_threshold) == false {
+        return Err(format!(
+          "expected 0 <= exit_threshold <= entry_threshold, got {exit_threshold} and {entry_threshold}"
+        ));
+      }
+    }
+    if let Some(full_size_score) = score.full_size_score {
+      if full_size_score <= 0.0 {
+        return Err(format!("full_size_score must be above 0, got {full_size_score}"));
+      }
+    }
+    return Ok(());
+  }
+
+  // sorted
+  pub fn parameter_names(&self) -> Vec<String> {
+    let settings = [&self.entry_threshold, &self.exit_threshold, &self.full_size_score];
+    let names: BTreeSet<String> = settings.into_iter().flatten().filter_map(|setting| setting.parameter_name()).collect();
+    return names.into_iter().collect();
+  }
+
+  pub fn build(&self, values: &BTreeMap<String, f64>) -> Score {
+    let entry_threshold = self.entry_threshold.as_ref().map(|setting| setting.resolve(values));
+    return Score {
+      entry_threshold,
+      exit_threshold: self.exit_threshold.as_ref().map(|setting| setting.resolve(values)).or(entry_threshold),
+      full_size_score: self.full_size_score.as_ref().map(|setting| setting.resolve(values)),
+      min_size: self.min_size,
+      max_size: self.max_size,
+    };
+  }
+}
+
+// score parameters resolved for one signal parameter combination, the default leaves directions and sizes alone
+#[derive(Debug, Clone, Default)]
+pub struct Score {
+  entry_threshold: Option<f64>,
+  exit_threshold: Option<f64>,
+  full_size_score: Option<f64>,
+  min_size: f64,
+  max_size: f64,
+}
+
+impl Score {
+  // the direction to hold given the one held so far, flat without a (finite) score
+  pub fn direction(&self, score: Option<f64>, previous_direction: &Direction, direction: Direction) -> Direction {
+    let (entry_threshold, exit_threshold) = match (self.entry_threshold, self.exit_threshold) {
+      (Some(entry_threshold), Some(exit_threshold)) => (entry_threshold, exit_threshold),
+      _ => return direction,
+    };
+    let score = match score {
+      Some(score) if score.is_finite() => score,
+      _ => return Direction::Flat,
+    };
+    if score >= entry_threshold {
+      return Direction::Long;
+    }
+    if score <= -entry_threshold {
+      return Direction::Short;
+    }
+    match previous_direction {
+      Direction::Long if score > exit_threshold => return Direction::Long,
+      Direction::Short if score < -exit_threshold => return Direction::Short,
+      _ => return Direction::Flat,
+    }
+  }
+
+  // fraction of a unit to trade at an entry
+  pub fn size(&self, score: Option<f64>) -> f64 {
+    let full_size_score = match self.full_size_score {
+      Some(full_size_score) => full_size_score,
+      None => return 1.0,
+    };
+    match score {
+      Some(score) if score.is_finite() => return (score.abs() / full_size_score).clamp(self.min_size, self.max_size),
+      _ => return self.min_size,
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn parameters(text: &str) -> ScoreParameters {
+    return toml::from_str(text).unwrap();
+  }
+
+  fn values(values: &[(&str, f64)]) -> BTreeMap<String, f64> {
+    return values.iter().map(|(name, value)| (name.to_string(), *value)).collect();
+  }
+
+  #[test]
+  fn thresholds_are_ordered_and_not_negative() {
+    assert!(parameters("entry_threshold = 0.002\nexit_threshold = 0.001")
+      .validate_values(&values(&[]))
+      .is_ok());
+    assert!(parameters("entry_threshold = 0.002\nexit_threshold = 0.002")
+      .validate_values(&values(&[]))
+      .is_ok());
+    assert_eq!(
+      parameters("entry_threshold = -0.002").validate_values(&values(&[])),
+      Err("entry_threshold must be at least 0, got -0.002".to_string())
+    );
+    assert_eq!(
+      parameters("entry_threshold = 0.002\nexit_threshold = -0.001").validate_values(&values(&[])),
+      Err("expected 0 <= exit_threshold <= entry_threshold, got -0.001 and 0.002".to_string())
+    );
+    let crossed = parameters("entry_threshold = \"$entry\"\nexit_threshold = 0.003");
+    assert!(crossed.validate_values(&values(&[("entry", 0.004)])).is_ok());
+    assert_eq!(
+      crossed.validate_values(&values(&[("entry", 0.002)])),
+      Err("expected 0 <= exit_threshold <= entry_threshold, got 0.003 and 0.002".to_string())
+    );
+  }
+
+  #[test]
+  fn sizing_by_score_needs_a_positive_min_size() {
+    assert_eq!(
+      parameters("full_size_score = 0.002").validate(),
+      Err("min_size must be above 0 when full_size_score sizes entries".to_string())
+    );
+    assert!(parameters("full_size_score = 0.002\nmin_size = 0.25").validate().is_ok());
+    assert!(parameters("entry_threshold = 0.002").validate().is_ok());
+    assert_eq!(
+      parameters("full_size_score = 0\nmin_size = 0.25").validate_values(&values(&[])),
+      Err("full_size_score must be above 0, got 0".to_string())
+    );
+  }
+
+  #[test]
+  fn sizes_scale_with_the_score_within_bounds() {
+    let score = parameters("full_size_score = 0.002\nmin_size = 0.25").build(&values(&[]));
+    assert_eq!(score.size(Some(0.001)), 0.5);
+    assert_eq!(score.size(Some(-0.0002)), 0.25);
+    assert_eq!(score.size(Some(0.01)), 1.0);
+    assert_eq!(score.size(None), 0.25);
+    assert_eq!(score.size(Some(f64::NAN)), 0.25);
+  }
+}
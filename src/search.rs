@@ -0,0 +1,356 @@
+use std::collections::{BTreeMap, HashSet};
+
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::Rng;
+use serde::Deserialize;
+
+use crate::experiment::{Constraint, Experiment, BACKTEST_PARAMETER_NAMES};
+use crate::genetic::GeneticParameters;
+
+// rejection sampling gives up after this many infeasible/duplicate draws in a row
+const MAX_ATTEMPTS: usize = 10_000;
+
+#[derive(Debug, Clone, Deserialize, Default)]
+#[serde(tag = "strategy", rename_all = "snake_case")]
+pub enum Search {
+  #[default]
+  Grid,
+  Random {
+    budget: usize,
+  },
+  LatinHypercube {
+    budget: usize,
+  },
+  // tree-structured parzen estimator
+  Tpe {
+    budget: usize,
+    #[serde(default = "default_initial_points")]
+    initial_points: usize,
+    #[serde(default = "default_gamma")]
+    gamma: f64,
+    #[serde(default = "default_candidates")]
+    candidates: usize,
+  },
+  Genetic(GeneticParameters),
+}
+
+fn default_initial_points() -> usize {
+  return 20;
+}
+
+fn default_gamma() -> f64 {
+  return 0.25;
+}
+
+fn default_candidates() -> usize {
+  return 64;
+}
+
+// a point is one index into the candidate values of every dimension
+pub type Point = Vec<usize>;
+
+pub struct SearchSpace {
+  pub names: Vec<String>,
+  pub values: Vec<Vec<f64>>,
+  // the leading dimensions are signal parameters, points sharing them share signals
+  pub num_signal_parameters: usize,
+  constraints: Vec<Constraint>,
+}
+
+impl SearchSpace {
+  pub fn new(experiment: &Experiment) -> SearchSpace {
+    let mut names = experiment.signal_parameter_names();
+    let num_signal_parameters = names.len();
+    names.extend(BACKTEST_PARAMETER_NAMES.iter().map(|name| name.to_string()));
+    let values = names.iter().map(|name| experiment.parameters[name].values()).collect();
+    return SearchSpace {
+      names,
+      values,
+      num_signal_parameters,
+      constraints: experiment.constraints(),
+    };
+  }
+
+  pub fn point_values(&self, point: &[usize]) -> BTreeMap<String, f64> {
+    return self
+      .names
+      .iter()
+      .zip(point)
+      .enumerate()
+      .map(|(dimension, (name, index))| (name.clone(), self.values[dimension][*index]))
+      .collect();
+  }
+
+  // inverse of point_values, values must be exactly one of the candidates of their dimension
+  pub fn point_of(&self, values: &[f64]) -> Point {
+    return self
+      .values
+      .iter()
+      .zip(values)
+      .zip(&self.names)
+      .map(|((candidates, value), name)| {
+        candidates
+          .iter()
+          .position(|candidate| candidate == value)
+          .unwrap_or_else(|| panic!("{name} = {value} is not a candidate value"))
+      })
+      .collect();
+  }
+
+  pub fn is_feasible(&self, point: &[usize]) -> bool {
+    let values = self.point_values(point);
+    return self.constraints.iter().all(|constraint| constraint.is_satisfied(&values));
+  }
+
+  fn random_point(&self, rng: &mut StdRng) -> Point {
+    return self.values.iter().map(|values| rng.gen_range(0..values.len())).collect();
+  }
+
+  fn random_feasible_point(&self, rng: &mut StdRng, seen: &HashSet<Point>) -> Option<Point> {
+    for _ in 0..MAX_ATTEMPTS {
+      let point = self.random_point(rng);
+      if self.is_feasible(&point) && seen.contains(&point) == false {
+        return Some(point);
+      }
+    }
+    return None;
+  }
+}
+
+// uniformly sampled distinct feasible points, fewer than budget if the space runs out
+pub fn random_search(space: &SearchSpace, budget: usize, rng: &mut StdRng) -> Vec<Point> {
+  let mut seen = HashSet::new();
+  let mut points = vec![];
+  while points.len() < budget {
+    let point = match space.random_feasible_point(rng, &seen) {
+      Some(point) => point,
+      None => break,
+    };
+    seen.insert(point.clone());
+    points.push(point);
+  }
+  return points;
+}
+
+// every dimension is cut into budget strata and each stratum is used exactly once, infeasible or duplicate
+// samples are swapped for random feasible ones
+pub fn latin_hypercube(space: &SearchSpace, budget: usize, rng: &mut StdRng) -> Vec<Point> {
+  let mut strata_per_dimension = vec![];
+  for _ in &space.values {
+    let mut strata: Vec<usize> = (0..budget).collect();
+    strata.shuffle(rng);
+    strata_per_dimension.push(strata);
+  }
+  let mut seen = HashSet::new();
+  let mut points = vec![];
+  for sample in 0..budget {
+    let point: Point = space
+      .values
+      .iter()
+      .zip(&strata_per_dimension)
+      .map(|(values, strata)| {
+        let position = (strata[sample] as f64 + rng.gen::<f64>()) / budget as f64;
+        return ((position * values.len() as f64) as usize).min(values.len() - 1);
+      })
+      .collect();
+    let point = if space.is_feasible(&point) && seen.contains(&point) == false {
+      point
+    } else {
+      match space.random_feasible_point(rng, &seen) {
+        Some(point) => point,
+        None => break,
+      }
+    };
+    seen.insert(point.clone());
+    points.push(point);
+  }
+  return points;
+}
+
+// smoothed histogram over the candidate indices of one dimension, neighbouring values share half the weight
+fn parzen_weights(num_values: usize, observations: &[usize]) -> Vec<f64> {
+  let mut weights = vec![1.0 / num_values as f64; num_values]; // uniform prior
+  for index in observations {
+    weights[*index] += 1.0;
+    if *index > 0 {
+      weights[*index - 1] += 0.5;
+    }
+    if *index + 1 < num_values {
+      weights[*index + 1] += 0.5;
+    }
+  }
+  let total: f64 = weights.iter().sum();
+  return weights.iter().map(|weight| weight / total).collect();
+}
+
+fn sample_index(weights: &[f64], rng: &mut StdRng) -> usize {
+  let mut remaining = rng.gen::<f64>();
+  for (index, weight) in weights.iter().enumerate() {
+    remaining -= weight;
+    if remaining <= 0.0 {
+      return index;
+    }
+  }
+  return weights.len() - 1;
+}
+
+// maximizes objective, evaluating one point at a time; returns every evaluated point with its score
+pub fn tpe_search<F>(
+  space: &SearchSpace,
+  budget: usize,
+  initial_points: usize,
+  gamma: f64,
+  candidates: usize,
+  rng: &mut StdRng,
+  mut objective: F,
+) -> Vec<(Point, f64)>
+where
+  F: FnMut(&[usize]) -> f64,
+{
+  let mut seen = HashSet::new();
+  let mut history: Vec<(Point, f64)> = vec![];
+  for point in random_search(space, initial_points.min(budget), rng) {
+    let score = objective(&point);
+    seen.insert(point.clone());
+    history.push((point, score));
+  }
+  while history.len() < budget {
+    // split history into the best gamma fraction and the rest
+    let mut ranked: Vec<&(Point, f64)> = history.iter().collect();
+    ranked.sort_by(|a, b| b.1.total_cmp(&a.1));
+    let num_good = ((ranked.len() as f64 * gamma).ceil() as usize).max(1);
+    let (good, bad) = ranked.split_at(num_good);
+    let good_weights: Vec<Vec<f64>> = (0..space.values.len())
+      .map(|dimension| {
+        parzen_weights(
+          space.values[dimension].len(),
+          &good.iter().map(|(point, _)| point[dimension]).collect::<Vec<usize>>(),
+        )
+      })
+      .collect();
+    let bad_weights: Vec<Vec<f64>> = (0..space.values.len())
+      .map(|dimension| {
+        parzen_weights(
+          space.values[dimension].len(),
+          &bad.iter().map(|(point, _)| point[dimension]).collect::<Vec<usize>>(),
+        )
+      })
+      .collect();
+    // draw candidates from the good density and keep the one with the best good/bad likelihood ratio
+    let mut best: Option<(Point, f64)> = None;
+    for _ in 0..candidates {
+      let point: Point = good_weights.iter().map(|weights| sample_index(weights, rng)).collect();
+      if space.is_feasible(&point) == false || seen.contains(&point) {
+        continue;
+      }
+      let ratio: f64 = point
+        .iter()
+        .enumerate()
+        .map(|(dimension, index)| good_weights[dimension][*index].ln() - bad_weights[dimension][*index].ln())
+        .sum();
+      if best.as_ref().map(|(_, best_ratio)| ratio > *best_ratio).unwrap_or(true) {
+        best = Some((point, ratio));
+      }
+    }
+    let point = match best {
+      Some((point, _)) => point,
+      None => match space.random_feasible_point(rng, &seen) {
+        Some(point) => point,
+        None => break,
+      },
+    };
+    let score = objective(&point);
+    seen.insert(point.clone());
+    history.push((point, score));
+  }
+  return history;
+}
+
+#[cfg(test)]
+mod tests {
+  use rand::SeedableRng;
+
+  use super::*;
+  use crate::experiment;
+
+  fn space() -> SearchSpace {
+    return SearchSpace::new(&experiment::load_experiment(None));
+  }
+
+  fn assert_distinct_and_feasible(space: &SearchSpace, points: &[Point]) {
+    let distinct: HashSet<&Point> = points.iter().collect();
+    assert_eq!(distinct.len(), points.len());
+    assert!(points.iter().all(|point| space.is_feasible(point)));
+  }
+
+  fn tpe(space: &SearchSpace, budget: usize, seed: u64) -> Vec<Point> {
+    // peaks in the middle of every dimension
+    let objective = |point: &[usize]| -point.iter().map(|index| (*index as f64 - 4.0).powi(2)).sum::<f64>();
+    let history = tpe_search(space, budget, 10, 0.25, 16, &mut StdRng::seed_from_u64(seed), objective);
+    return history.into_iter().map(|(point, _)| point).collect();
+  }
+
+  #[test]
+  fn random_search_respects_the_budget_and_the_seed() {
+    let space = space();
+    let points = random_search(&space, 30, &mut StdRng::seed_from_u64(7));
+    assert_eq!(points.len(), 30);
+    assert_distinct_and_feasible(&space, &points);
+    assert_eq!(points, random_search(&space, 30, &mut StdRng::seed_from_u64(7)));
+  }
+
+  #[test]
+  fn latin_hypercube_respects_the_budget_and_the_seed() {
+    let space = space();
+    let points = latin_hypercube(&space, 30, &mut StdRng::seed_from_u64(7));
+    assert_eq!(points.len(), 30);
+    assert_distinct_and_feasible(&space, &points);
+    assert_eq!(points, latin_hypercube(&space, 30, &mut StdRng::seed_from_u64(7)));
+  }
+
+  #[test]
+  fn latin_hypercube_uses_every_stratum_once_without_constraints() {
+    let budget = 10;
+    let space = SearchSpace {
+      names: vec!["a".to_string(), "b".to_string()],
+      values: vec![
+        (0..budget).map(|value| value as f64).collect(),
+        (0..2 * budget).map(|value| value as f64).collect(),
+      ],
+      num_signal_parameters: 2,
+      constraints: vec![],
+    };
+    let points = latin_hypercube(&space, budget, &mut StdRng::seed_from_u64(7));
+    assert_eq!(points.len(), budget);
+    // stratum s of b covers candidate indices [s * len / b, (s + 1) * len / b)
+    for (dimension, values) in space.values.iter().enumerate() {
+      let mut strata: Vec<usize> = points.iter().map(|point| point[dimension] * budget / values.len()).collect();
+      strata.sort();
+      assert_eq!(strata, (0..budget).collect::<Vec<usize>>());
+    }
+  }
+
+  #[test]
+  fn tpe_respects_the_budget_and_the_seed() {
+    let space = space();
+    let points = tpe(&space, 30, 7);
+    assert_eq!(points.len(), 30);
+    assert_distinct_and_feasible(&space, &points);
+    assert_eq!(points, tpe(&space, 30, 7));
+  }
+
+  #[test]
+  fn searches_stop_when_the_space_runs_out() {
+    let space = SearchSpace {
+      names: vec!["a".to_string()],
+      values: vec![vec![1.0, 2.0, 3.0]],
+      num_signal_parameters: 1,
+      constraints: vec![],
+    };
+    let mut rng = StdRng::seed_from_u64(7);
+    assert_eq!(random_search(&space, 5, &mut rng).len(), 3);
+    assert_eq!(latin_hypercube(&space, 5, &mut rng).len(), 3);
+    assert_eq!(tpe(&space, 5, 7).len(), 3);
+  }
+}
@@ -0,0 +1,118 @@
+use std::collections::BTreeSet;
+
+use chrono::NaiveDate;
+use serde::Deserialize;
+
+use crate::{datetime_from_timestamp, Direction};
+
+// which sides the account can take
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DirectionPolicy {
+  #[default]
+  Both,
+  LongOnly,
+  ShortOnly,
+}
+
+// one row per symbol that cannot be borrowed, on a session date or always when the date is empty, e.g.
+//   symbol,date
+//   GME,
+//   AMC,2023-01-05
+#[derive(Debug, Clone, Deserialize)]
+pub struct HardToBorrow {
+  pub symbol: String,
+  #[serde(default)]
+  pub date: Option<String>, // YYYY-MM-DD in exchange local time
+}
+
+// turns the sides an account cannot take into flat, applied before trades are built
+#[derive(Debug, Clone, Default)]
+pub struct ShortSelling {
+  direction_policy: DirectionPolicy,
+  is_always_hard_to_borrow: bool,
+  hard_to_borrow_dates: BTreeSet<NaiveDate>,
+}
+
+impl ShortSelling {
+  pub fn new(direction_policy: DirectionPolicy, symbol: Option<&str>, hard_to_borrow: &[HardToBorrow]) -> ShortSelling {
+    let mut short_selling = ShortSelling {
+      direction_policy,
+      ..ShortSelling::default()
+    };
+    for entry in hard_to_borrow.iter().filter(|entry| Some(entry.symbol.as_str()) == symbol) {
+      match entry.date.as_deref().filter(|date| date.is_empty() == false) {
+        Some(date) => {
+          let date = NaiveDate::parse_from_str(date, "%Y-%m-%d").unwrap_or_else(|_| panic!("invalid hard to borrow date {date:?}, expected YYYY-MM-DD"));
+          short_selling.hard_to_borrow_dates.insert(date);
+        }
+        None => short_selling.is_always_hard_to_borrow = true,
+      }
+    }
+    return short_selling;
+  }
+
+  // grouping_key is the session's regular open, which dates it
+  pub fn allow(&self, grouping_key: i64, direction: Direction) -> Direction {
+    let is_allowed = match (&direction, self.direction_policy) {
+      (Direction::Flat, _) => true,
+      (Direction::Long, DirectionPolicy::ShortOnly) | (Direction::Short, DirectionPolicy::LongOnly) => false,
+      (Direction::Long, _) => true,
+      (Direction::Short, _) => {
+        self.is_always_hard_to_borrow == false
+          && (self.hard_to_borrow_dates.is_empty() || self.hard_to_borrow_dates.contains(&datetime_from_timestamp(grouping_key).date_naive()) == false)
+      }
+    };
+    if is_allowed {
+      return direction;
+    }
+    return Direction::Flat;
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  // 2023-03-01 09:30 eastern
+  const REGULAR_OPEN: i64 = 1677681000;
+  const DAY: i64 = 86400;
+
+  fn hard_to_borrow(csv: &str) -> Vec<HardToBorrow> {
+    let mut reader = csv::Reader::from_reader(csv.as_bytes());
+    return reader.deserialize().map(|record| record.unwrap()).collect();
+  }
+
+  #[test]
+  fn direction_policies_keep_only_their_side() {
+    let long_only = ShortSelling::new(DirectionPolicy::LongOnly, None, &[]);
+    assert_eq!(long_only.allow(REGULAR_OPEN, Direction::Long), Direction::Long);
+    assert_eq!(long_only.allow(REGULAR_OPEN, Direction::Short), Direction::Flat);
+    let short_only = ShortSelling::new(DirectionPolicy::ShortOnly, None, &[]);
+    assert_eq!(short_only.allow(REGULAR_OPEN, Direction::Long), Direction::Flat);
+    assert_eq!(short_only.allow(REGULAR_OPEN, Direction::Short), Direction::Short);
+    assert_eq!(short_only.allow(REGULAR_OPEN, Direction::Flat), Direction::Flat);
+  }
+
+  #[test]
+  fn hard_to_borrow_dates_only_block_their_session() {
+    let entries = hard_to_borrow("symbol,date\nAMC,2023-03-01\nGME,\n");
+    let short_selling = ShortSelling::new(DirectionPolicy::Both, Some("AMC"), &entries);
+    assert_eq!(short_selling.allow(REGULAR_OPEN, Direction::Short), Direction::Flat);
+    assert_eq!(short_selling.allow(REGULAR_OPEN, Direction::Long), Direction::Long);
+    assert_eq!(short_selling.allow(REGULAR_OPEN + DAY, Direction::Short), Direction::Short);
+  }
+
+  #[test]
+  fn hard_to_borrow_without_a_date_blocks_every_session() {
+    let entries = hard_to_borrow("symbol,date\nAMC,2023-03-01\nGME,\n");
+    let short_selling = ShortSelling::new(DirectionPolicy::Both, Some("GME"), &entries);
+    assert_eq!(short_selling.allow(REGULAR_OPEN, Direction::Short), Direction::Flat);
+    assert_eq!(short_selling.allow(REGULAR_OPEN + DAY, Direction::Short), Direction::Flat);
+    // other symbols and runs without one are not affected
+    let short_selling = ShortSelling::new(DirectionPolicy::Both, Some("SPY"), &entries);
+    assert_eq!(short_selling.allow(REGULAR_OPEN, Direction::Short), Direction::Short);
+    let short_selling = ShortSelling::new(DirectionPolicy::Both, None, &entries);
+    assert_eq!(short_selling.allow(REGULAR_OPEN, Direction::Short), Direction::Short);
+  }
+}
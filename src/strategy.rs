@@ -0,0 +1,443 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use rand::rngs::StdRng;
+use rand::Rng;
+use serde::Deserialize;
+
+use crate::indicators::{Ema, Indicator};
+use crate::rules::{Filter, RuleFilter, RuleSet};
+use crate::{Candle, Direction};
+
+// the walker in build_signals calls on_closed_candle for every candle once it has fully closed, then asks for the
+// direction to hold from the open of the following candle on
+pub trait Strategy {
+  fn on_closed_candle(&mut self, candle: &Candle);
+  // only current_candle's open is known at timestamp, strategies that look at more of it are knowingly optimistic
+  fn direction(&mut self, timestamp: i64, current_candle: &Candle, rng: &mut StdRng) -> Direction;
+  // strength behind the last direction, positive for long and negative for short, none for strategies without one
+  fn score(&self) -> Option<f64> {
+    return None;
+  }
+}
+
+// long while the fast ema is above the slow one, short otherwise
+pub struct EmaCross {
+  fast: Ema,
+  slow: Ema,
+  // spread of the emas relative to the slow one
+  score: f64,
+}
+
+impl EmaCross {
+  pub fn new(fast_periods: usize, slow_periods: usize) -> EmaCross {
+    return EmaCross {
+      fast: Ema::new(fast_periods),
+      slow: Ema::new(slow_periods),
+      score: f64::NAN,
+    };
+  }
+
+  // fast_periods and slow_periods, after a prefix that tells composite members apart
+  fn parameter_names(prefix: &str) -> Vec<String> {
+    return vec![format!("{prefix}fast_periods"), format!("{prefix}slow_periods")];
+  }
+
+  fn from_values(prefix: &str, values: &BTreeMap<String, f64>) -> EmaCross {
+    return EmaCross::new(
+      values[&format!("{prefix}fast_periods")] as usize,
+      values[&format!("{prefix}slow_periods")] as usize,
+    );
+  }
+}
+
+impl Strategy for EmaCross {
+  fn on_closed_candle(&mut self, candle: &Candle) {
+    self.fast.next(candle);
+    self.slow.next(candle);
+  }
+
+  fn direction(&mut self, _timestamp: i64, current_candle: &Candle, rng: &mut StdRng) -> Direction {
+    // feed to indicators (pretend we can accurately predict close)
+    let accuracy = 0.45;
+    let predicted_close_correctly = rng.gen_bool(accuracy) == true;
+    let last_fast;
+    let last_slow;
+    if predicted_close_correctly == true {
+      last_fast = self.fast.next(current_candle);
+      last_slow = self.slow.next(current_candle);
+    } else {
+      let predicted_candle = Candle {
+        close: current_candle.open,
+        ..*current_candle
+      };
+      last_fast = self.fast.next(&predicted_candle);
+      last_slow = self.slow.next(&predicted_candle);
+    }
+    self.score = (last_fast - last_slow) / last_slow;
+    if last_fast > last_slow {
+      return Direction::Long;
+    }
+    return Direction::Short;
+  }
+
+  fn score(&self) -> Option<f64> {
+    return Some(self.score);
+  }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Vote {
+  // more than half of the members agree on a side
+  #[default]
+  Majority,
+  // every member agrees on a side
+  Unanimous,
+  // weighted mean of long +1, short -1 and flat 0 reaches the threshold
+  Weighted,
+}
+
+// a number, or "$name" to optimize it as a signal parameter
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(untagged)]
+pub enum Setting {
+  Value(f64),
+  Parameter(String),
+}
+
+impl Setting {
+  pub fn validate(&self) -> Result<(), String> {
+    if let Setting::Parameter(text) = self {
+      if text.starts_with('$') == false || text.len() == 1 {
+        return Err(format!("expected a number or \"$name\", got {text:?}"));
+      }
+    }
+    return Ok(());
+  }
+
+  pub fn parameter_name(&self) -> Option<String> {
+    match self {
+      Setting::Value(_) => return None,
+      Setting::Parameter(text) => return Some(text.trim_start_matches('$').to_string()),
+    }
+  }
+
+  pub fn resolve(&self, values: &BTreeMap<String, f64>) -> f64 {
+    match self {
+      Setting::Value(value) => return *value,
+      Setting::Parameter(_) => return values[&self.parameter_name().unwrap()],
+    }
+  }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct CompositeParameters {
+  #[serde(default)]
+  pub vote: Vote,
+  // weighted vote only
+  #[serde(default = "default_threshold")]
+  pub threshold: Setting,
+  pub members: Vec<MemberParameters>,
+  // conditions in the rule language that must all hold, otherwise the composite is flat
+  #[serde(default)]
+  pub filters: Vec<String>,
+}
+
+fn default_threshold() -> Setting {
+  return Setting::Value(0.5);
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct MemberParameters {
+  // the ema cross when omitted
+  #[serde(default)]
+  pub rules: Option<String>,
+  #[serde(default = "default_weight")]
+  pub weight: Setting,
+}
+
+fn default_weight() -> Setting {
+  return Setting::Value(1.0);
+}
+
+#[derive(Debug, Clone)]
+pub struct Composite {
+  vote: Vote,
+  threshold: Setting,
+  members: Vec<(StrategyDefinition, Setting)>,
+  filters: Vec<Filter>,
+}
+
+impl Composite {
+  pub fn parse(parameters: &CompositeParameters) -> Result<Composite, String> {
+    if parameters.members.is_empty() {
+      return Err("a composite needs at least one member".to_string());
+    }
+    let settings = parameters.members.iter().map(|member| &member.weight).chain([&parameters.threshold]);
+    for setting in settings {
+      setting.validate()?;
+    }
+    let mut members = vec![];
+    for (index, member) in parameters.members.iter().enumerate() {
+      let definition = match &member.rules {
+        Some(text) => StrategyDefinition::Rules(RuleSet::parse(text).map_err(|error| format!("member {}: {error}", index + 1))?),
+        None => StrategyDefinition::EmaCross,
+      };
+      members.push((definition, member.weight.clone()));
+    }
+    let filters = parameters
+      .filters
+      .iter()
+      .map(|text| Filter::parse(text))
+      .collect::<Result<Vec<Filter>, String>>()?;
+    return Ok(Composite {
+      vote: parameters.vote,
+      threshold: parameters.threshold.clone(),
+      members,
+      filters,
+    });
+  }
+
+  // ema cross members read member1.fast_periods, member2.slow_periods and so on (numbered like in errors) so each can be
+  // optimized on its own, rules members bind their $names as usual and can share them
+  fn member_prefix(index: usize) -> String {
+    return format!("member{}.", index + 1);
+  }
+
+  fn parameter_names(&self) -> Vec<String> {
+    let mut names = BTreeSet::new();
+    for (index, (definition, weight)) in self.members.iter().enumerate() {
+      match definition {
+        StrategyDefinition::EmaCross => names.extend(EmaCross::parameter_names(&Composite::member_prefix(index))),
+        _ => names.extend(definition.parameter_names()),
+      }
+      names.extend(weight.parameter_name());
+    }
+    for filter in &self.filters {
+      names.extend(filter.parameter_names());
+    }
+    names.extend(self.threshold.parameter_name());
+    return names.into_iter().collect();
+  }
+
+  // the weighted score divides by the summed weights, so none may be negative (a negative one flips its member's vote
+  // and can cancel the others out) and at least one has to stay above zero in every combination. candidate_values
+  // gives the values a weight parameter is optimized over
+  pub fn validate_weights(&self, candidate_values: impl Fn(&str) -> Vec<f64>) -> Result<(), String> {
+    let weight_values = |weight: &Setting| match weight {
+      Setting::Value(value) => vec![*value],
+      Setting::Parameter(_) => candidate_values(&weight.parameter_name().unwrap()),
+    };
+    for (index, (_, weight)) in self.members.iter().enumerate() {
+      if let Some(value) = weight_values(weight).into_iter().find(|value| *value < 0.0) {
+        return Err(format!("member {} weight must not be negative, got {value}", index + 1));
+      }
+    }
+    if self.members.iter().all(|(_, weight)| weight_values(weight).contains(&0.0)) {
+      return Err("every member weight can be 0 at once, at least one has to be non-zero".to_string());
+    }
+    return Ok(());
+  }
+
+  fn build_member(index: usize, definition: &StrategyDefinition, values: &BTreeMap<String, f64>) -> Box<dyn Strategy> {
+    match definition {
+      StrategyDefinition::EmaCross => return Box::new(EmaCross::from_values(&Composite::member_prefix(index), values)),
+      _ => return definition.build(values),
+    }
+  }
+}
+
+pub struct CompositeStrategy {
+  vote: Vote,
+  threshold: f64,
+  members: Vec<(Box<dyn Strategy>, f64)>,
+  filters: Vec<RuleFilter>,
+  // weighted mean of long +1, short -1 and flat 0 over the members, whatever the vote
+  score: f64,
+}
+
+impl CompositeStrategy {
+  fn weighted_score(&self, directions: &[Direction]) -> f64 {
+    let total_weight: f64 = self.members.iter().map(|(_, weight)| weight).sum();
+    return directions
+      .iter()
+      .zip(&self.members)
+      .map(|(direction, (_, weight))| match direction {
+        Direction::Long => *weight,
+        Direction::Short => -weight,
+        Direction::Flat => 0.0,
+      })
+      .sum::<f64>()
+      / total_weight;
+  }
+
+  fn vote(&self, directions: &[Direction]) -> Direction {
+    let count = |side: &Direction| directions.iter().filter(|direction| *direction == side).count();
+    match self.vote {
+      Vote::Majority => {
+        for side in [Direction::Long, Direction::Short] {
+          if count(&side) * 2 > directions.len() {
+            return side;
+          }
+        }
+        return Direction::Flat;
+      }
+      Vote::Unanimous => {
+        for side in [Direction::Long, Direction::Short] {
+          if count(&side) == directions.len() {
+            return side;
+          }
+        }
+        return Direction::Flat;
+      }
+      Vote::Weighted => {
+        let score = self.score;
+        if score > 0.0 && score >= self.threshold {
+          return Direction::Long;
+        }
+        if score < 0.0 && -score >= self.threshold {
+          return Direction::Short;
+        }
+        return Direction::Flat;
+      }
+    }
+  }
+}
+
+impl Strategy for CompositeStrategy {
+  fn on_closed_candle(&mut self, candle: &Candle) {
+    for (member, _) in self.members.iter_mut() {
+      member.on_closed_candle(candle);
+    }
+    for filter in self.filters.iter_mut() {
+      filter.on_closed_candle(candle);
+    }
+  }
+
+  fn direction(&mut self, timestamp: i64, current_candle: &Candle, rng: &mut StdRng) -> Direction {
+    // every member is asked every time so its state does not depend on the vote or the filters
+    let directions: Vec<Direction> = self
+      .members
+      .iter_mut()
+      .map(|(member, _)| member.direction(timestamp, current_candle, rng))
+      .collect();
+    if self.filters.iter().any(|filter| filter.allows(timestamp) == false) {
+      self.score = 0.0;
+      return Direction::Flat;
+    }
+    self.score = self.weighted_score(&directions);
+    return self.vote(&directions);
+  }
+
+  fn score(&self) -> Option<f64> {
+    return Some(self.score);
+  }
+}
+
+// what the experiment trades, instantiated once per signal parameter combination
+#[derive(Debug, Clone, Default)]
+pub enum StrategyDefinition {
+  #[default]
+  EmaCross,
+  Rules(RuleSet),
+  Composite(Composite),
+}
+
+impl StrategyDefinition {
+  // sorted, signal parameters besides warmup_periods
+  pub fn parameter_names(&self) -> Vec<String> {
+    match self {
+      StrategyDefinition::EmaCross => return EmaCross::parameter_names(""),
+      StrategyDefinition::Rules(rule_set) => return rule_set.parameter_names(),
+      StrategyDefinition::Composite(composite) => return composite.parameter_names(),
+    }
+  }
+
+  // whether its strategies give a score next to the direction
+  pub fn has_score(&self) -> bool {
+    match self {
+      StrategyDefinition::EmaCross | StrategyDefinition::Composite(_) => return true,
+      StrategyDefinition::Rules(rule_set) => return rule_set.score.is_some(),
+    }
+  }
+
+  pub fn build(&self, values: &BTreeMap<String, f64>) -> Box<dyn Strategy> {
+    match self {
+      StrategyDefinition::EmaCross => return Box::new(EmaCross::from_values("", values)),
+      StrategyDefinition::Rules(rule_set) => return Box::new(rule_set.build(values)),
+      StrategyDefinition::Composite(composite) => {
+        return Box::new(CompositeStrategy {
+          vote: composite.vote,
+          threshold: composite.threshold.resolve(values),
+          members: composite
+            .members
+            .iter()
+            .enumerate()
+            .map(|(index, (definition, weight))| (Composite::build_member(index, definition, values), weight.resolve(values)))
+            .collect(),
+          filters: composite.filters.iter().map(|filter| filter.build(values)).collect(),
+          score: f64::NAN,
+        })
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn composite(text: &str) -> Composite {
+    return Composite::parse(&toml::from_str(text).unwrap()).unwrap();
+  }
+
+  fn candidates(name: &str) -> Vec<f64> {
+    match name {
+      "weight" => return vec![0.0, 1.0],
+      "signed" => return vec![-1.0, 1.0],
+      _ => return vec![1.0],
+    }
+  }
+
+  #[test]
+  fn ema_cross_members_have_their_own_parameters() {
+    let composite = composite(r#"members = [{}, { rules = "long when close > ema($fast)" }, { weight = "$weight" }]"#);
+    assert_eq!(
+      composite.parameter_names(),
+      [
+        "fast",
+        "member1.fast_periods",
+        "member1.slow_periods",
+        "member3.fast_periods",
+        "member3.slow_periods",
+        "weight"
+      ]
+    );
+    assert_eq!(StrategyDefinition::EmaCross.parameter_names(), ["fast_periods", "slow_periods"]);
+  }
+
+  #[test]
+  fn some_member_weight_has_to_stay_non_zero() {
+    assert!(composite("members = [{ weight = 0 }, { weight = 1 }]").validate_weights(candidates).is_ok());
+    assert!(composite(r#"members = [{ weight = 0 }, { weight = "$other" }]"#)
+      .validate_weights(candidates)
+      .is_ok());
+    assert_eq!(
+      composite(r#"members = [{ weight = 0 }, { weight = "$weight" }]"#).validate_weights(candidates),
+      Err("every member weight can be 0 at once, at least one has to be non-zero".to_string())
+    );
+  }
+
+  #[test]
+  fn member_weights_must_not_be_negative() {
+    // 1 and -1 would sum to a zero denominator
+    assert_eq!(
+      composite("members = [{ weight = 1 }, { weight = -1 }]").validate_weights(candidates),
+      Err("member 2 weight must not be negative, got -1".to_string())
+    );
+    assert_eq!(
+      composite(r#"members = [{ weight = "$signed" }, { weight = 1 }]"#).validate_weights(candidates),
+      Err("member 1 weight must not be negative, got -1".to_string())
+    );
+  }
+}
@@ -0,0 +1,217 @@
+use std::collections::BTreeMap;
+
+use serde::ser::SerializeStruct;
+use serde::{Deserialize, Serialize, Serializer};
+
+use crate::Candle;
+
+// consolidated (CTA/UTP) trade condition codes as numbered by polygon
+pub const AVERAGE_PRICE_TRADE: u32 = 2;
+pub const CASH_SALE: u32 = 7;
+pub const DERIVATIVELY_PRICED: u32 = 10;
+pub const EXTENDED_HOURS_SOLD_OUT_OF_SEQUENCE: u32 = 13;
+pub const NEXT_DAY: u32 = 20;
+pub const PRIOR_REFERENCE_PRICE: u32 = 22;
+pub const SOLD_LAST: u32 = 30; // late report
+pub const SOLD_OUT_OF_SEQUENCE: u32 = 33;
+pub const ODD_LOT_TRADE: u32 = 37;
+pub const CONTINGENT_TRADE: u32 = 52;
+pub const QUALIFIED_CONTINGENT_TRADE: u32 = 53;
+
+// trades carrying any of these still count towards volume but must not set open/high/low/close
+pub const DEFAULT_EXCLUDED_CONDITIONS: [u32; 11] = [
+  AVERAGE_PRICE_TRADE,
+  CASH_SALE,
+  DERIVATIVELY_PRICED,
+  EXTENDED_HOURS_SOLD_OUT_OF_SEQUENCE,
+  NEXT_DAY,
+  PRIOR_REFERENCE_PRICE,
+  SOLD_LAST,
+  SOLD_OUT_OF_SEQUENCE,
+  ODD_LOT_TRADE,
+  CONTINGENT_TRADE,
+  QUALIFIED_CONTINGENT_TRADE,
+];
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct TradeTick {
+  pub timestamp: i64, // unix nanoseconds (sip_timestamp)
+  pub price: f64,
+  pub size: f64,
+  #[serde(default)]
+  pub conditions: String, // semicolon separated condition codes, e.g. "12;37"
+}
+
+impl TradeTick {
+  pub fn condition_codes(&self) -> Vec<u32> {
+    return self
+      .conditions
+      .split(';')
+      .map(|code| code.trim())
+      .filter(|code| code.is_empty() == false)
+      .map(|code| code.parse().unwrap())
+      .collect();
+  }
+}
+
+// a candle with the volume weighted average price and number of its trades. like volume both cover every trade, excluded
+// ones included, so a bucket of only excluded trades has their vwap and count
+#[derive(Debug, Clone, Copy)]
+pub struct AggregatedCandle {
+  pub candle: Candle,
+  pub vwap: f64,
+  pub trade_count: i64,
+}
+
+// flat, the candle columns followed by vwap and trade_count (csv cannot write flattened structs)
+impl Serialize for AggregatedCandle {
+  fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+    let mut row = serializer.serialize_struct("AggregatedCandle", 9)?;
+    row.serialize_field("start_timestamp", &self.candle.start_timestamp)?;
+    row.serialize_field("end_timestamp", &self.candle.end_timestamp)?;
+    row.serialize_field("open", &self.candle.open)?;
+    row.serialize_field("high", &self.candle.high)?;
+    row.serialize_field("low", &self.candle.low)?;
+    row.serialize_field("close", &self.candle.close)?;
+    row.serialize_field("volume", &self.candle.volume)?;
+    row.serialize_field("vwap", &self.vwap)?;
+    row.serialize_field("trade_count", &self.trade_count)?;
+    return row.end();
+  }
+}
+
+#[derive(Default)]
+struct Bucket {
+  ohlc: Option<(f64, f64, f64, f64)>,
+  // of every trade, excluded ones included
+  all_ohlc: Option<(f64, f64, f64, f64)>,
+  volume: f64,
+  notional: f64,
+  trade_count: i64,
+}
+
+fn update_ohlc(ohlc: Option<(f64, f64, f64, f64)>, price: f64) -> Option<(f64, f64, f64, f64)> {
+  match ohlc {
+    None => return Some((price, price, price, price)),
+    Some((open, high, low, _close)) => return Some((open, high.max(price), low.min(price), price)),
+  }
+}
+
+// ticks are expected in time order. a bucket whose trades are all excluded still becomes a candle with their volume, flat
+// at the previous close, or priced from the excluded trades when there is no previous candle
+pub fn build_candles_from_ticks(ticks: &[TradeTick], resolution_seconds: i64, excluded_conditions: &[u32]) -> Vec<AggregatedCandle> {
+  let mut buckets: BTreeMap<i64, Bucket> = BTreeMap::new();
+  for tick in ticks {
+    let timestamp = tick.timestamp.div_euclid(1_000_000_000);
+    let start_timestamp = timestamp - timestamp.rem_euclid(resolution_seconds);
+    let bucket = buckets.entry(start_timestamp).or_default();
+    bucket.volume += tick.size;
+    bucket.notional += tick.price * tick.size;
+    bucket.trade_count += 1;
+    bucket.all_ohlc = update_ohlc(bucket.all_ohlc, tick.price);
+    let is_excluded = tick.condition_codes().iter().any(|code| excluded_conditions.contains(code));
+    if is_excluded {
+      continue;
+    }
+    bucket.ohlc = update_ohlc(bucket.ohlc, tick.price);
+  }
+  let mut candles: Vec<AggregatedCandle> = vec![];
+  for (start_timestamp, bucket) in buckets {
+    let previous_close = candles.last().map(|aggregated| aggregated.candle.close);
+    let (open, high, low, close) = match (bucket.ohlc, previous_close) {
+      (Some(ohlc), _) => ohlc,
+      (None, Some(previous_close)) => (previous_close, previous_close, previous_close, previous_close),
+      (None, None) => bucket.all_ohlc.unwrap(),
+    };
+    // zero sized trades only
+    let vwap = if bucket.volume > 0.0 { bucket.notional / bucket.volume } else { close };
+    candles.push(AggregatedCandle {
+      candle: Candle {
+        start_timestamp,
+        end_timestamp: start_timestamp + resolution_seconds - 1,
+        open,
+        high,
+        low,
+        close,
+        volume: bucket.volume as i64,
+      },
+      vwap,
+      trade_count: bucket.trade_count,
+    });
+  }
+  return candles;
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn tick(seconds: i64, price: f64, size: f64, conditions: &str) -> TradeTick {
+    return TradeTick {
+      timestamp: seconds * 1_000_000_000,
+      price,
+      size,
+      conditions: conditions.to_string(),
+    };
+  }
+
+  #[test]
+  fn excluded_trades_count_towards_volume_only() {
+    let ticks = [
+      tick(0, 10.0, 100.0, ""),
+      tick(10, 12.0, 5.0, "37"),
+      tick(20, 9.0, 100.0, "12"),
+      tick(30, 11.0, 100.0, ""),
+    ];
+    let candles = build_candles_from_ticks(&ticks, 60, &DEFAULT_EXCLUDED_CONDITIONS);
+    assert_eq!(candles.len(), 1);
+    let candle = candles[0].candle;
+    assert_eq!((candle.open, candle.high, candle.low, candle.close), (10.0, 11.0, 9.0, 11.0));
+    assert_eq!(candle.volume, 305);
+    assert_eq!((candle.start_timestamp, candle.end_timestamp), (0, 59));
+    // (10 * 100 + 12 * 5 + 9 * 100 + 11 * 100) / 305
+    assert_eq!(candles[0].vwap, 3060.0 / 305.0);
+    assert_eq!(candles[0].trade_count, 4);
+  }
+
+  #[test]
+  fn bucket_of_only_excluded_trades_carries_the_previous_close() {
+    let ticks = [
+      tick(0, 10.0, 100.0, ""),
+      tick(65, 12.0, 5.0, "37"),
+      tick(70, 13.0, 3.0, "37"),
+      tick(130, 11.0, 100.0, ""),
+    ];
+    let candles = build_candles_from_ticks(&ticks, 60, &DEFAULT_EXCLUDED_CONDITIONS);
+    assert_eq!(candles.len(), 3);
+    let candle = candles[1].candle;
+    assert_eq!(candle.start_timestamp, 60);
+    assert_eq!((candle.open, candle.high, candle.low, candle.close), (10.0, 10.0, 10.0, 10.0));
+    assert_eq!(candle.volume, 8);
+    // the excluded trades' own vwap, not the carried close
+    assert_eq!(candles[1].vwap, (12.0 * 5.0 + 13.0 * 3.0) / 8.0);
+    assert_eq!(candles[1].trade_count, 2);
+  }
+
+  #[test]
+  fn first_bucket_of_only_excluded_trades_is_priced_from_them() {
+    let ticks = [tick(0, 12.0, 5.0, "37"), tick(10, 13.0, 3.0, "37;12"), tick(70, 11.0, 100.0, "")];
+    let candles = build_candles_from_ticks(&ticks, 60, &DEFAULT_EXCLUDED_CONDITIONS);
+    assert_eq!(candles.len(), 2);
+    let candle = candles[0].candle;
+    assert_eq!((candle.open, candle.high, candle.low, candle.close), (12.0, 13.0, 12.0, 13.0));
+    assert_eq!(candle.volume, 8);
+  }
+
+  #[test]
+  fn writes_vwap_and_trade_count_after_the_candle_columns() {
+    let candles = build_candles_from_ticks(&[tick(0, 10.0, 1.0, ""), tick(1, 12.0, 3.0, "")], 60, &DEFAULT_EXCLUDED_CONDITIONS);
+    let mut writer = csv::WriterBuilder::new().has_headers(true).from_writer(vec![]);
+    writer.serialize(candles[0]).unwrap();
+    let text = String::from_utf8(writer.into_inner().unwrap()).unwrap();
+    assert_eq!(
+      text,
+      "start_timestamp,end_timestamp,open,high,low,close,volume,vwap,trade_count\n0,59,10.0,12.0,10.0,12.0,4,11.5,2\n"
+    );
+  }
+}
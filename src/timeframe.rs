@@ -0,0 +1,182 @@
+// higher timeframe bars built from the closed base candles a strategy is fed. a bar is only handed out once a base candle
+// reaching its end has closed (or a later candle shows it is over), so its values are never ahead of the walker's pointer
+
+use crate::indicators::Indicator;
+use crate::{determine_session_type, get_regular_market_session_start_and_end, Candle, MarketSessionType};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Timeframe {
+  // bars of this many seconds anchored at the regular open, extended hours included
+  Seconds(i64),
+  // one bar per regular session
+  Session,
+}
+
+impl Timeframe {
+  // "15m", "4h" or "1d"
+  pub fn parse(count: f64, unit: &str) -> Result<Timeframe, String> {
+    if count < 1.0 || count.fract() != 0.0 {
+      return Err(format!("timeframe count must be a positive integer, got {count}"));
+    }
+    let count = count as i64;
+    match unit {
+      "m" => return Ok(Timeframe::Seconds(count * 60)),
+      "h" => return Ok(Timeframe::Seconds(count * 60 * 60)),
+      "d" if count == 1 => return Ok(Timeframe::Session),
+      "d" => return Err("only 1d bars are supported".to_string()),
+      _ => return Err(format!("unknown timeframe unit {unit}, expected m, h or d")),
+    }
+  }
+
+  // start and inclusive end of the bar a candle belongs to (like a candle's own end), none when the timeframe skips it
+  fn bar_bounds(&self, candle: &Candle) -> Option<(i64, i64)> {
+    let (regular_session_start, regular_session_end) = get_regular_market_session_start_and_end(candle.start_timestamp);
+    match self {
+      Timeframe::Seconds(size) => {
+        let offset = candle.start_timestamp - regular_session_start.timestamp();
+        let start = regular_session_start.timestamp() + offset.div_euclid(*size) * size;
+        return Some((start, start + size - 1));
+      }
+      Timeframe::Session => {
+        if determine_session_type(candle.start_timestamp) != MarketSessionType::Regular {
+          return None;
+        }
+        return Some((regular_session_start.timestamp(), regular_session_end.timestamp()));
+      }
+    }
+  }
+}
+
+pub struct TimeframeAggregator {
+  timeframe: Timeframe,
+  bar: Option<Candle>,
+}
+
+impl TimeframeAggregator {
+  pub fn new(timeframe: Timeframe) -> TimeframeAggregator {
+    return TimeframeAggregator { timeframe, bar: None };
+  }
+
+  // feed one closed base candle, returns the bars it closed in order (at most two when data is missing)
+  pub fn next(&mut self, candle: &Candle) -> Vec<Candle> {
+    let mut closed_bars = vec![];
+    // the last base candle of the pending bar was missing, it is over once anything later shows up
+    if let Some(bar) = self.bar {
+      if candle.start_timestamp > bar.end_timestamp {
+        closed_bars.push(bar);
+        self.bar = None;
+      }
+    }
+    let (start, end) = match self.timeframe.bar_bounds(candle) {
+      Some(bounds) => bounds,
+      None => return closed_bars,
+    };
+    let bar = match self.bar {
+      Some(bar) => Candle {
+        high: bar.high.max(candle.high),
+        low: bar.low.min(candle.low),
+        close: candle.close,
+        volume: bar.volume + candle.volume,
+        ..bar
+      },
+      None => Candle {
+        start_timestamp: start,
+        end_timestamp: end,
+        ..*candle
+      },
+    };
+    if candle.end_timestamp >= end {
+      closed_bars.push(bar);
+      self.bar = None;
+    } else {
+      self.bar = Some(bar);
+    }
+    return closed_bars;
+  }
+}
+
+// runs an indicator on higher timeframe bars, holding its value from the last closed bar in between (nan before the first)
+pub struct HigherTimeframe {
+  aggregator: TimeframeAggregator,
+  indicator: Box<dyn Indicator>,
+  value: f64,
+}
+
+impl HigherTimeframe {
+  pub fn new(timeframe: Timeframe, indicator: Box<dyn Indicator>) -> HigherTimeframe {
+    return HigherTimeframe {
+      aggregator: TimeframeAggregator::new(timeframe),
+      indicator,
+      value: f64::NAN,
+    };
+  }
+}
+
+impl Indicator for HigherTimeframe {
+  fn next(&mut self, candle: &Candle) -> f64 {
+    for bar in self.aggregator.next(candle) {
+      self.value = self.indicator.next(&bar);
+    }
+    return self.value;
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  // 2023-03-01 09:30 eastern
+  const REGULAR_OPEN: i64 = 1677681000;
+
+  fn minute_candle(start_timestamp: i64, close: f64) -> Candle {
+    return Candle {
+      start_timestamp,
+      end_timestamp: start_timestamp + 59,
+      open: close,
+      high: close + 1.0,
+      low: close - 1.0,
+      close,
+      volume: 10,
+    };
+  }
+
+  #[test]
+  fn a_bar_closes_on_its_last_base_candle() {
+    let mut aggregator = TimeframeAggregator::new(Timeframe::parse(15.0, "m").unwrap());
+    for minute in 0..14 {
+      assert!(aggregator.next(&minute_candle(REGULAR_OPEN + minute * 60, minute as f64)).is_empty());
+    }
+    let bars = aggregator.next(&minute_candle(REGULAR_OPEN + 14 * 60, 14.0));
+    assert_eq!(bars.len(), 1);
+    let bar = bars[0];
+    assert_eq!((bar.start_timestamp, bar.end_timestamp), (REGULAR_OPEN, REGULAR_OPEN + 15 * 60 - 1));
+    assert_eq!((bar.open, bar.high, bar.low, bar.close, bar.volume), (0.0, 15.0, -1.0, 14.0, 150));
+    // the next candle starts a new bar
+    assert!(aggregator.next(&minute_candle(REGULAR_OPEN + 15 * 60, 15.0)).is_empty());
+  }
+
+  #[test]
+  fn a_bar_missing_its_last_base_candle_closes_on_the_next_one() {
+    let mut aggregator = TimeframeAggregator::new(Timeframe::Seconds(5 * 60));
+    for minute in 0..4 {
+      assert!(aggregator.next(&minute_candle(REGULAR_OPEN + minute * 60, 1.0)).is_empty());
+    }
+    // 09:35 to 09:39 is missing entirely, 09:40 closes the first bar and starts the third
+    let bars = aggregator.next(&minute_candle(REGULAR_OPEN + 10 * 60, 2.0));
+    assert_eq!(bars.len(), 1);
+    assert_eq!((bars[0].start_timestamp, bars[0].close, bars[0].volume), (REGULAR_OPEN, 1.0, 40));
+  }
+
+  #[test]
+  fn a_session_bar_closes_on_the_last_regular_candle() {
+    let mut aggregator = TimeframeAggregator::new(Timeframe::Session);
+    // pre-market is skipped
+    assert!(aggregator.next(&minute_candle(REGULAR_OPEN - 60, 1.0)).is_empty());
+    for minute in 0..389 {
+      assert!(aggregator.next(&minute_candle(REGULAR_OPEN + minute * 60, 2.0)).is_empty());
+    }
+    let bars = aggregator.next(&minute_candle(REGULAR_OPEN + 389 * 60, 3.0));
+    assert_eq!(bars.len(), 1);
+    assert_eq!((bars[0].open, bars[0].close, bars[0].volume), (2.0, 3.0, 3900));
+  }
+}
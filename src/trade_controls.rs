@@ -0,0 +1,270 @@
+use serde::Deserialize;
+
+use crate::candle_series::CandleSeries;
+use crate::{Direction, Signal};
+
+// limits on how often build_trades trades, periods count signals (one per candle)
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct TradeControls {
+  // signals to wait after a close before opening again, 0 allows switching sides in one go
+  #[serde(default)]
+  pub cooldown_periods: usize,
+  // signals a position is held before the strategy or another control may close or reverse it. session ends, forced
+  // exits and blackouts (Signal::is_forced_flat) still close it right away
+  #[serde(default)]
+  pub min_hold_periods: usize,
+  // opens per session (grouping_key)
+  #[serde(default)]
+  pub max_trades_per_day: Option<usize>,
+  // flat for the rest of the session once the realized return of its trades reaches this (negative, like
+  // stop_loss_percentage). realized between signal prices, before profit limits and stop losses
+  #[serde(default)]
+  pub daily_loss_limit_percentage: Option<f64>,
+}
+
+// adding units to a position while the signal keeps asking for it
+#[derive(Debug, Clone, Deserialize)]
+pub struct Pyramiding {
+  // units held at most, the open included
+  pub max_units: usize,
+  // signals the wanted direction has to persist after the last entry before the next add
+  #[serde(default = "default_add_every_periods")]
+  pub add_every_periods: usize,
+  #[serde(default)]
+  pub add_when: AddCondition,
+}
+
+fn default_add_every_periods() -> usize {
+  return 1;
+}
+
+// what else has to hold for an add once add_every_periods have passed
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AddCondition {
+  // nothing, the wanted direction persisting is enough
+  #[default]
+  Persisting,
+  // the strategy's score is further on the held side than at the last entry, needs a strategy with a score
+  StrongerScore,
+}
+
+impl Pyramiding {
+  // whether to add a unit to a position whose wanted direction persists, scores are signed like Strategy::score
+  pub fn should_add(&self, num_units: usize, periods_since_entry: usize, direction: &Direction, score: Option<f64>, last_entry_score: Option<f64>) -> bool {
+    if num_units >= self.max_units || periods_since_entry < self.add_every_periods {
+      return false;
+    }
+    match self.add_when {
+      AddCondition::Persisting => return true,
+      AddCondition::StrongerScore => {
+        let sign = if *direction == Direction::Long { 1.0 } else { -1.0 };
+        match (score, last_entry_score) {
+          // false when either is nan
+          (Some(score), Some(last_entry_score)) => return sign * score > sign * last_entry_score,
+          _ => return false,
+        }
+      }
+    }
+  }
+}
+
+impl TradeControls {
+  fn is_unrestricted(&self) -> bool {
+    return self.cooldown_periods == 0 && self.min_hold_periods == 0 && self.max_trades_per_day.is_none() && self.daily_loss_limit_percentage.is_none();
+  }
+
+  // the direction actually held after every signal given the one wanted at it, priced at the open of the fill candle at
+  // the signal
+  pub fn directions(&self, signals: &[Signal], wanted_directions: &[Direction], fill_candles: &CandleSeries) -> Vec<Direction> {
+    if self.is_unrestricted() {
+      return wanted_directions.to_vec();
+    }
+    let mut directions = Vec::with_capacity(signals.len());
+    let mut held = Direction::Flat;
+    // signal index and price of the open position
+    let mut opened: Option<(usize, f64)> = None;
+    let mut last_close_index: Option<usize> = None;
+    let mut grouping_key = None;
+    let mut num_opens_today = 0;
+    let mut realized_today = 0.0;
+    let mut is_halted_today = false;
+    for (index, (signal, wanted_direction)) in signals.iter().zip(wanted_directions).enumerate() {
+      if grouping_key != Some(signal.grouping_key) {
+        grouping_key = Some(signal.grouping_key);
+        num_opens_today = 0;
+        realized_today = 0.0;
+        is_halted_today = false;
+      }
+      let price = || match fill_candles.get(signal.timestamp) {
+        Some(candle) => candle.open,
+        None => panic!("no fill candle for trade at {}", signal.timestamp),
+      };
+      let mut direction = if is_halted_today { Direction::Flat } else { wanted_direction.clone() };
+      // too early to close or switch sides, keep holding unless the session forces it flat
+      if let Some((open_index, _)) = opened {
+        if direction != held && signal.is_forced_flat == false && index - open_index < self.min_hold_periods {
+          direction = held.clone();
+        }
+      }
+      // close
+      if held != Direction::Flat && direction != held {
+        let (_, open_price) = opened.take().unwrap();
+        let sign = if held == Direction::Long { 1.0 } else { -1.0 };
+        realized_today += sign * (price() - open_price) / open_price;
+        last_close_index = Some(index);
+        held = Direction::Flat;
+        if self.daily_loss_limit_percentage.is_some_and(|limit| realized_today <= limit) {
+          is_halted_today = true;
+          direction = Direction::Flat;
+        }
+      }
+      // open
+      if held == Direction::Flat && direction != Direction::Flat {
+        let is_cooled_down = last_close_index.is_none_or(|close_index| index - close_index >= self.cooldown_periods);
+        let is_under_cap = self.max_trades_per_day.is_none_or(|max_trades| num_opens_today < max_trades);
+        if is_cooled_down && is_under_cap {
+          opened = Some((index, price()));
+          num_opens_today += 1;
+          held = direction;
+        }
+      }
+      directions.push(held.clone());
+    }
+    return directions;
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::Candle;
+
+  // 2023-03-01 09:30 eastern
+  const REGULAR_OPEN: i64 = 1677681000;
+
+  // one signal per minute from the regular open, in a session per entry of sessions
+  fn signals(sessions: &[&[(Direction, bool)]]) -> Vec<Signal> {
+    let mut signals = vec![];
+    for (day, session) in sessions.iter().enumerate() {
+      let grouping_key = REGULAR_OPEN + day as i64 * 24 * 60 * 60;
+      for (minute, (direction, is_forced_flat)) in session.iter().enumerate() {
+        signals.push(Signal {
+          grouping_key,
+          timestamp: grouping_key + minute as i64 * 60,
+          direction: direction.clone(),
+          score: None,
+          is_forced_flat: *is_forced_flat,
+        });
+      }
+    }
+    return signals;
+  }
+
+  // opens at the given prices at every signal
+  fn fill_candles(signals: &[Signal], prices: &[f64]) -> CandleSeries {
+    let candles = signals
+      .iter()
+      .zip(prices)
+      .map(|(signal, price)| Candle {
+        start_timestamp: signal.timestamp,
+        end_timestamp: signal.timestamp + 59,
+        open: *price,
+        high: *price,
+        low: *price,
+        close: *price,
+        volume: 100,
+      })
+      .collect();
+    return CandleSeries::new(candles, 60);
+  }
+
+  fn held(controls: &TradeControls, sessions: &[&[(Direction, bool)]], prices: &[f64]) -> Vec<Direction> {
+    let signals = signals(sessions);
+    let wanted: Vec<Direction> = signals.iter().map(|signal| signal.direction.clone()).collect();
+    let prices = if prices.is_empty() { vec![100.0; signals.len()] } else { prices.to_vec() };
+    return controls.directions(&signals, &wanted, &fill_candles(&signals, &prices));
+  }
+
+  const LONG: (Direction, bool) = (Direction::Long, false);
+  const SHORT: (Direction, bool) = (Direction::Short, false);
+  const FLAT: (Direction, bool) = (Direction::Flat, false);
+  const FORCED_FLAT: (Direction, bool) = (Direction::Flat, true);
+
+  fn min_hold(periods: usize) -> TradeControls {
+    return TradeControls {
+      min_hold_periods: periods,
+      ..TradeControls::default()
+    };
+  }
+
+  #[test]
+  fn min_hold_delays_exits_to_flat() {
+    let directions = held(&min_hold(3), &[&[LONG, FLAT, FLAT, FLAT, FLAT]], &[]);
+    assert_eq!(
+      directions,
+      [Direction::Long, Direction::Long, Direction::Long, Direction::Flat, Direction::Flat]
+    );
+  }
+
+  #[test]
+  fn min_hold_delays_switches() {
+    let directions = held(&min_hold(3), &[&[LONG, SHORT, SHORT, SHORT]], &[]);
+    assert_eq!(directions, [Direction::Long, Direction::Long, Direction::Long, Direction::Short]);
+  }
+
+  #[test]
+  fn min_hold_never_delays_forced_exits() {
+    let directions = held(&min_hold(3), &[&[LONG, LONG, FORCED_FLAT, FORCED_FLAT]], &[]);
+    assert_eq!(directions, [Direction::Long, Direction::Long, Direction::Flat, Direction::Flat]);
+  }
+
+  #[test]
+  fn cooldown_waits_after_a_close() {
+    let controls = TradeControls {
+      cooldown_periods: 2,
+      ..TradeControls::default()
+    };
+    let directions = held(&controls, &[&[LONG, FLAT, LONG, LONG, LONG]], &[]);
+    assert_eq!(
+      directions,
+      [Direction::Long, Direction::Flat, Direction::Flat, Direction::Long, Direction::Long]
+    );
+  }
+
+  #[test]
+  fn max_trades_per_day_resets_every_session() {
+    let controls = TradeControls {
+      max_trades_per_day: Some(1),
+      ..TradeControls::default()
+    };
+    let directions = held(&controls, &[&[LONG, FLAT, SHORT], &[SHORT, FLAT]], &[]);
+    assert_eq!(
+      directions,
+      [Direction::Long, Direction::Flat, Direction::Flat, Direction::Short, Direction::Flat]
+    );
+  }
+
+  #[test]
+  fn daily_loss_limit_halts_the_rest_of_the_session() {
+    let controls = TradeControls {
+      daily_loss_limit_percentage: Some(-0.01),
+      ..TradeControls::default()
+    };
+    // the long loses 2% and closes on the switch, which is then not opened
+    let directions = held(&controls, &[&[LONG, SHORT, SHORT], &[LONG]], &[100.0, 98.0, 98.0, 98.0]);
+    assert_eq!(directions, [Direction::Long, Direction::Flat, Direction::Flat, Direction::Long]);
+  }
+
+  #[test]
+  fn a_loss_realized_after_the_min_hold_halts_the_session() {
+    let controls = TradeControls {
+      min_hold_periods: 2,
+      daily_loss_limit_percentage: Some(-0.01),
+      ..TradeControls::default()
+    };
+    // the short is opened at 100 and closed at a loss once held long enough, which then halts the session
+    let directions = held(&controls, &[&[SHORT, FLAT, FLAT, LONG]], &[100.0, 101.0, 102.0, 102.0]);
+    assert_eq!(directions, [Direction::Short, Direction::Short, Direction::Flat, Direction::Flat]);
+  }
+}
@@ -0,0 +1,89 @@
+use crate::Candle;
+
+// running high/low over the candles a trade is exposed to, built once per trade so that every
+// backtest parameter combination can find the first touch of a price level with a binary search
+pub struct TradePath<'a> {
+  candles: &'a [Candle],
+  running_high: Vec<f64>,
+  running_low: Vec<f64>,
+}
+
+impl<'a> TradePath<'a> {
+  pub fn new(candles: &'a [Candle]) -> TradePath<'a> {
+    let mut running_high = Vec::with_capacity(candles.len());
+    let mut running_low = Vec::with_capacity(candles.len());
+    let mut high = f64::NEG_INFINITY;
+    let mut low = f64::INFINITY;
+    for candle in candles {
+      high = high.max(candle.high);
+      low = low.min(candle.low);
+      running_high.push(high);
+      running_low.push(low);
+    }
+    return TradePath {
+      candles,
+      running_high,
+      running_low,
+    };
+  }
+
+  pub fn candle(&self, index: usize) -> &'a Candle {
+    return &self.candles[index];
+  }
+
+  // index of the first candle whose high is >= price
+  pub fn first_high_at_or_above(&self, price: f64) -> Option<usize> {
+    let index = self.running_high.partition_point(|high| *high < price);
+    return if index < self.candles.len() { Some(index) } else { None };
+  }
+
+  // index of the first candle whose low is <= price
+  pub fn first_low_at_or_below(&self, price: f64) -> Option<usize> {
+    let index = self.running_low.partition_point(|low| *low > price);
+    return if index < self.candles.len() { Some(index) } else { None };
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn candle(high: f64, low: f64) -> Candle {
+    return Candle {
+      start_timestamp: 0,
+      end_timestamp: 59,
+      open: low,
+      high,
+      low,
+      close: high,
+      volume: 100,
+    };
+  }
+
+  #[test]
+  fn first_touches_match_a_linear_scan() {
+    // the entry candle already spans 99..101, the high of 102 is reached twice and the low of 97 twice
+    let candles = [
+      candle(101.0, 99.0),
+      candle(102.0, 100.0),
+      candle(100.5, 98.0),
+      candle(102.0, 97.0),
+      candle(101.0, 97.0),
+      candle(103.0, 99.5),
+    ];
+    let trade_path = TradePath::new(&candles);
+    let prices = [96.0, 97.0, 97.5, 98.0, 99.0, 99.5, 100.0, 101.0, 101.5, 102.0, 102.5, 103.0, 104.0];
+    for price in prices {
+      let high_scan = candles.iter().position(|candle| candle.high >= price);
+      let low_scan = candles.iter().position(|candle| candle.low <= price);
+      assert_eq!(trade_path.first_high_at_or_above(price), high_scan, "high at {price}");
+      assert_eq!(trade_path.first_low_at_or_below(price), low_scan, "low at {price}");
+    }
+    // a touch on the entry candle and the first of two equal highs
+    assert_eq!(trade_path.first_high_at_or_above(101.0), Some(0));
+    assert_eq!(trade_path.first_low_at_or_below(99.0), Some(0));
+    assert_eq!(trade_path.first_high_at_or_above(102.0), Some(1));
+    assert_eq!(trade_path.first_low_at_or_below(97.0), Some(3));
+    assert_eq!(trade_path.first_high_at_or_above(104.0), None);
+  }
+}
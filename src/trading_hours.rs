@@ -0,0 +1,189 @@
+use chrono::{Duration, NaiveDateTime, TimeZone, Timelike};
+use chrono_tz::US;
+use serde::Deserialize;
+
+use crate::{datetime_from_timestamp, Direction};
+
+// times are HH:MM in exchange local time, windows include their start and exclude their end
+#[derive(Debug, Clone, Deserialize)]
+pub struct TradingHoursParameters {
+  // new positions are only opened inside one of these, open ones can still be held or closed outside. the whole
+  // regular session when empty
+  #[serde(default)]
+  pub entry_windows: Vec<(String, String)>,
+  // flat from this time until the end of the session
+  #[serde(default)]
+  pub forced_exit: Option<String>,
+  // csv of scheduled events to stay flat around, see Blackout
+  #[serde(default)]
+  pub blackouts_filename: Option<String>,
+}
+
+// one row per event, e.g.
+//   start,end,event
+//   2023-03-22 13:55,2023-03-22 15:00,FOMC
+//   2023-04-12 08:25,2023-04-12 09:45,CPI
+#[derive(Debug, Clone, Deserialize)]
+pub struct Blackout {
+  pub start: String, // YYYY-MM-DD HH:MM in exchange local time, inclusive
+  pub end: String,   // exclusive
+  #[serde(default)]
+  pub event: String,
+}
+
+// minutes after midnight
+fn parse_time(text: &str) -> u32 {
+  let (hours, minutes) = text.split_once(':').unwrap_or_else(|| panic!("invalid time {text:?}, expected HH:MM"));
+  let hours: u32 = hours.parse().unwrap_or_else(|_| panic!("invalid time {text:?}, expected HH:MM"));
+  let minutes: u32 = minutes.parse().unwrap_or_else(|_| panic!("invalid time {text:?}, expected HH:MM"));
+  assert!(hours <= 23 && minutes <= 59, "invalid time {text:?}, expected HH:MM");
+  return hours * 60 + minutes;
+}
+
+// a time skipped when clocks spring forward maps to the first minute after the gap, a repeated one to its first occurrence
+fn parse_local_timestamp(text: &str) -> i64 {
+  let mut naive = NaiveDateTime::parse_from_str(text, "%Y-%m-%d %H:%M").unwrap_or_else(|_| panic!("invalid datetime {text:?}, expected YYYY-MM-DD HH:MM"));
+  loop {
+    if let Some(datetime) = US::Eastern.from_local_datetime(&naive).earliest() {
+      return datetime.timestamp();
+    }
+    naive += Duration::minutes(1);
+  }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct TradingHours {
+  entry_windows: Vec<(u32, u32)>,
+  forced_exit: Option<u32>,
+  // sorted by start
+  blackouts: Vec<(i64, i64)>,
+}
+
+impl TradingHours {
+  pub fn new(parameters: &TradingHoursParameters, blackouts: &[Blackout]) -> TradingHours {
+    let entry_windows = parameters
+      .entry_windows
+      .iter()
+      .map(|(start, end)| (parse_time(start), parse_time(end)))
+      .collect();
+    let mut blackouts: Vec<(i64, i64)> = blackouts
+      .iter()
+      .map(|blackout| {
+        let (start, end) = (parse_local_timestamp(&blackout.start), parse_local_timestamp(&blackout.end));
+        assert!(start < end, "blackout {} ends before it starts", blackout.event);
+        return (start, end);
+      })
+      .collect();
+    blackouts.sort();
+    return TradingHours {
+      entry_windows,
+      forced_exit: parameters.forced_exit.as_deref().map(parse_time),
+      blackouts,
+    };
+  }
+
+  fn is_blacked_out(&self, timestamp: i64) -> bool {
+    // blackouts can overlap, so check every one that started by now
+    let started = self.blackouts.partition_point(|(start, _)| *start <= timestamp);
+    return self.blackouts[..started].iter().any(|(_, end)| timestamp < *end);
+  }
+
+  // from the forced exit on and during blackouts
+  pub fn is_forced_flat(&self, timestamp: i64) -> bool {
+    if self.forced_exit.is_none() && self.blackouts.is_empty() {
+      return false;
+    }
+    let eastern = datetime_from_timestamp(timestamp);
+    let minute_of_day = eastern.hour() * 60 + eastern.minute();
+    return self.forced_exit.is_some_and(|forced_exit| minute_of_day >= forced_exit) || self.is_blacked_out(timestamp);
+  }
+
+  // the direction to hold from timestamp on, given the one held so far and the one the strategy wants
+  pub fn apply(&self, timestamp: i64, previous_direction: &Direction, direction: Direction) -> Direction {
+    if self.entry_windows.is_empty() && self.forced_exit.is_none() && self.blackouts.is_empty() {
+      return direction;
+    }
+    if self.is_forced_flat(timestamp) {
+      return Direction::Flat;
+    }
+    let eastern = datetime_from_timestamp(timestamp);
+    let minute_of_day = eastern.hour() * 60 + eastern.minute();
+    let is_entry_allowed = self.entry_windows.is_empty() || self.entry_windows.iter().any(|(start, end)| *start <= minute_of_day && minute_of_day < *end);
+    // outside the entry windows a position can be kept or closed, but not opened or reversed
+    if is_entry_allowed == false && direction != *previous_direction {
+      return Direction::Flat;
+    }
+    return direction;
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  // wednesday 2023-03-01 09:30 eastern
+  const REGULAR_OPEN: i64 = 1677681000;
+  const DAY: i64 = 86400;
+
+  fn at(minutes_after_open: i64) -> i64 {
+    return REGULAR_OPEN + minutes_after_open * 60;
+  }
+
+  fn trading_hours(entry_windows: &[(&str, &str)], forced_exit: Option<&str>, blackouts: &[(&str, &str)]) -> TradingHours {
+    let parameters = TradingHoursParameters {
+      entry_windows: entry_windows.iter().map(|(start, end)| (start.to_string(), end.to_string())).collect(),
+      forced_exit: forced_exit.map(str::to_string),
+      blackouts_filename: None,
+    };
+    let blackouts: Vec<Blackout> = blackouts
+      .iter()
+      .map(|(start, end)| Blackout {
+        start: start.to_string(),
+        end: end.to_string(),
+        event: String::new(),
+      })
+      .collect();
+    return TradingHours::new(&parameters, &blackouts);
+  }
+
+  #[test]
+  fn maps_times_skipped_by_daylight_saving_to_the_end_of_the_gap() {
+    // 2024-03-10 03:00 edt
+    assert_eq!(parse_local_timestamp("2024-03-10 02:30"), 1710054000);
+    assert_eq!(parse_local_timestamp("2024-03-10 03:00"), 1710054000);
+    // 2024-11-03 01:30 edt, the first of the two
+    assert_eq!(parse_local_timestamp("2024-11-03 01:30"), 1730611800);
+  }
+
+  #[test]
+  fn entry_windows_only_gate_new_positions() {
+    let trading_hours = trading_hours(&[("09:45", "10:30")], None, &[]);
+    assert_eq!(trading_hours.apply(at(10), &Direction::Flat, Direction::Long), Direction::Flat);
+    assert_eq!(trading_hours.apply(at(15), &Direction::Flat, Direction::Long), Direction::Long);
+    // the end is exclusive
+    assert_eq!(trading_hours.apply(at(60), &Direction::Flat, Direction::Long), Direction::Flat);
+    assert_eq!(trading_hours.apply(at(60), &Direction::Long, Direction::Long), Direction::Long);
+    assert_eq!(trading_hours.apply(at(60), &Direction::Long, Direction::Short), Direction::Flat);
+    assert_eq!(trading_hours.apply(at(60), &Direction::Long, Direction::Flat), Direction::Flat);
+  }
+
+  #[test]
+  fn forced_exit_holds_until_the_end_of_the_session() {
+    let trading_hours = trading_hours(&[], Some("15:30"), &[]);
+    assert_eq!(trading_hours.apply(at(359), &Direction::Long, Direction::Long), Direction::Long);
+    assert_eq!(trading_hours.apply(at(360), &Direction::Long, Direction::Long), Direction::Flat);
+    assert!(trading_hours.is_forced_flat(at(389)));
+    assert!(trading_hours.is_forced_flat(at(0) + DAY) == false);
+  }
+
+  #[test]
+  fn blackouts_can_straddle_sessions() {
+    let trading_hours = trading_hours(&[], None, &[("2023-03-01 15:30", "2023-03-02 09:45")]);
+    assert!(trading_hours.is_forced_flat(at(359)) == false);
+    assert!(trading_hours.is_forced_flat(at(360)));
+    assert!(trading_hours.is_forced_flat(at(0) + DAY));
+    assert!(trading_hours.is_forced_flat(at(14) + DAY));
+    assert!(trading_hours.is_forced_flat(at(15) + DAY) == false);
+    assert_eq!(trading_hours.apply(at(0) + DAY, &Direction::Flat, Direction::Long), Direction::Flat);
+  }
+}
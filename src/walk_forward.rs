@@ -0,0 +1,177 @@
+use std::ops::Range;
+
+use serde::Deserialize;
+
+use crate::performance::{Fitness, PerformanceMetrics};
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WalkForwardMode {
+  // train window slides forward with a fixed length
+  Rolling,
+  // train window always starts at the first session and grows
+  Anchored,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct WalkForwardParameters {
+  pub mode: WalkForwardMode,
+  pub train_sessions: usize,
+  pub test_sessions: usize,
+}
+
+impl WalkForwardParameters {
+  // the number of sessions is only known once the candles are loaded, see fits_sessions
+  pub fn validate(&self) -> Result<(), String> {
+    if self.train_sessions == 0 || self.test_sessions == 0 {
+      return Err(format!(
+        "train_sessions and test_sessions must be at least 1, got {} and {}",
+        self.train_sessions, self.test_sessions
+      ));
+    }
+    return Ok(());
+  }
+
+  // room for at least one full fold
+  pub fn fits_sessions(&self, num_sessions: usize) -> Result<(), String> {
+    if self.train_sessions + self.test_sessions > num_sessions {
+      return Err(format!(
+        "{} train and {} test sessions do not fit {num_sessions} sessions",
+        self.train_sessions, self.test_sessions
+      ));
+    }
+    return Ok(());
+  }
+}
+
+#[derive(Debug, Clone)]
+pub struct Fold {
+  pub train: Range<usize>,
+  pub test: Range<usize>,
+  pub selected: usize,
+  pub train_score: f64,
+  pub test_return: f64,
+}
+
+// session index ranges, consecutive test windows never overlap and together cover everything after the first train window.
+// windows are not empty, see WalkForwardParameters::validate
+pub fn build_folds(num_sessions: usize, parameters: &WalkForwardParameters) -> Vec<(Range<usize>, Range<usize>)> {
+  let mut folds = vec![];
+  let mut test_start = parameters.train_sessions;
+  while test_start < num_sessions {
+    let train_start = match parameters.mode {
+      WalkForwardMode::Rolling => test_start - parameters.train_sessions,
+      WalkForwardMode::Anchored => 0,
+    };
+    let test_end = (test_start + parameters.test_sessions).min(num_sessions);
+    folds.push((train_start..test_start, test_start..test_end));
+    test_start = test_end;
+  }
+  return folds;
+}
+
+// returns[combination][session] is the summed profit_loss_percentage of that combination on that session.
+// each fold selects the combination with the best in-sample fitness (computed over per session returns)
+// and records what it then made on the following test window
+pub fn walk_forward(returns: &[Vec<f64>], num_sessions: usize, parameters: &WalkForwardParameters, fitness: Fitness) -> Vec<Fold> {
+  let mut folds = vec![];
+  if returns.is_empty() {
+    return folds;
+  }
+  for (train, test) in build_folds(num_sessions, parameters) {
+    let mut selected = 0;
+    let mut train_score = f64::NEG_INFINITY;
+    for (index, combination_returns) in returns.iter().enumerate() {
+      let score = fitness.score(&PerformanceMetrics::from_returns(&combination_returns[train.clone()]));
+      if score > train_score {
+        selected = index;
+        train_score = score;
+      }
+    }
+    let test_return = returns[selected][test.clone()].iter().sum();
+    folds.push(Fold {
+      train,
+      test,
+      selected,
+      train_score,
+      test_return,
+    });
+  }
+  return folds;
+}
+
+// out-of-sample per session returns of the selected combinations, test windows back to back
+pub fn stitched_returns(returns: &[Vec<f64>], folds: &[Fold]) -> Vec<f64> {
+  return folds
+    .iter()
+    .flat_map(|fold| returns[fold.selected][fold.test.clone()].iter().copied())
+    .collect();
+}
+
+// mean and standard deviation of every parameter across the folds' selections
+pub fn parameter_stability<'a>(parameter_values: &'a [Vec<(String, f64)>], folds: &[Fold]) -> Vec<(&'a str, f64, f64)> {
+  let mut stability = vec![];
+  if folds.is_empty() {
+    return stability;
+  }
+  let n = folds.len() as f64;
+  for (position, (name, _)) in parameter_values[folds[0].selected].iter().enumerate() {
+    let values: Vec<f64> = folds.iter().map(|fold| parameter_values[fold.selected][position].1).collect();
+    let mean = values.iter().sum::<f64>() / n;
+    let variance = values.iter().map(|value| (value - mean).powi(2)).sum::<f64>() / n;
+    stability.push((name.as_str(), mean, variance.sqrt()));
+  }
+  return stability;
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn parameters(mode: WalkForwardMode, train_sessions: usize, test_sessions: usize) -> WalkForwardParameters {
+    return WalkForwardParameters {
+      mode,
+      train_sessions,
+      test_sessions,
+    };
+  }
+
+  // test windows back to back from the end of the first train window to the last session
+  fn assert_tests_cover(folds: &[(Range<usize>, Range<usize>)], train_sessions: usize, num_sessions: usize) {
+    assert_eq!(folds[0].1.start, train_sessions);
+    for pair in folds.windows(2) {
+      assert_eq!(pair[0].1.end, pair[1].1.start);
+    }
+    assert_eq!(folds[folds.len() - 1].1.end, num_sessions);
+    for (train, test) in folds {
+      assert_eq!(train.end, test.start);
+    }
+  }
+
+  #[test]
+  fn rolling_folds_slide_a_fixed_train_window() {
+    let folds = build_folds(10, &parameters(WalkForwardMode::Rolling, 4, 3));
+    assert_eq!(folds, [(0..4, 4..7), (3..7, 7..10)]);
+    assert_tests_cover(&folds, 4, 10);
+  }
+
+  #[test]
+  fn anchored_folds_grow_from_the_first_session() {
+    // the last test window is cut short by the end of the sessions
+    let folds = build_folds(11, &parameters(WalkForwardMode::Anchored, 4, 3));
+    assert_eq!(folds, [(0..4, 4..7), (0..7, 7..10), (0..10, 10..11)]);
+    assert_tests_cover(&folds, 4, 11);
+  }
+
+  #[test]
+  fn windows_must_fit() {
+    assert!(parameters(WalkForwardMode::Rolling, 0, 3).validate().is_err());
+    assert!(parameters(WalkForwardMode::Rolling, 4, 0).validate().is_err());
+    assert!(parameters(WalkForwardMode::Rolling, 4, 3).validate().is_ok());
+    assert!(parameters(WalkForwardMode::Rolling, 4, 3).fits_sessions(7).is_ok());
+    assert_eq!(
+      parameters(WalkForwardMode::Rolling, 4, 3).fits_sessions(6),
+      Err("4 train and 3 test sessions do not fit 6 sessions".to_string())
+    );
+  }
+}